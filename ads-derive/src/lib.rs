@@ -0,0 +1,307 @@
+//! Derive macro for the structure traits of the `ads` crate.
+//!
+//! This crate is used through the `derive` feature of `ads`, which
+//! re-exports the macro as `ads::AdsStruct`.  See the `ads::structs` module
+//! for documentation.
+//!
+//! The macro only needs to understand the names, types and attributes of the
+//! fields, so it parses the input directly instead of depending on `syn`.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Derive `AdsSize`, `FromAds` and `ToAds` from `ads::structs` for a struct
+/// with named fields.
+///
+/// Fields are decoded in declaration order.  `#[ads(pad = N)]` skips N bytes
+/// before a field, or after the last field if given on the struct.
+/// `#[ads(string = N)]` decodes a `String` field as `STRING(N)`.
+#[proc_macro_derive(AdsStruct, attributes(ads))]
+pub fn derive_ads_struct(input: TokenStream) -> TokenStream {
+    match parse_struct(input) {
+        Ok(def) => def.expand(),
+        Err((span, msg)) => compile_error(span, &msg),
+    }
+}
+
+type ParseResult<T> = Result<T, (Span, String)>;
+
+/// The parsed struct definition.
+struct Struct {
+    name: String,
+    fields: Vec<Field>,
+    /// Trailing padding in bytes.
+    pad: usize,
+}
+
+/// A parsed field of the struct.
+struct Field {
+    name: String,
+    ty: String,
+    /// Padding before the field in bytes.
+    pad: usize,
+    /// The capacity of a `STRING(N)` field.
+    string: Option<usize>,
+}
+
+fn parse_struct(input: TokenStream) -> ParseResult<Struct> {
+    let mut tokens = input.into_iter();
+    let mut pad = 0;
+
+    // Outer attributes and visibility, up to the `struct` keyword.
+    loop {
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '#' => {
+                if let Some(TokenTree::Group(attr)) = tokens.next() {
+                    for (key, value) in parse_ads_attr(&attr)? {
+                        match &*key.to_string() {
+                            "pad" => pad += value,
+                            _ => return Err((key.span(), format!(
+                                "unknown ads attribute `{}` on a struct", key))),
+                        }
+                    }
+                }
+            }
+            Some(TokenTree::Ident(i)) if i.to_string() == "struct" => break,
+            Some(TokenTree::Ident(i)) if i.to_string() == "enum" || i.to_string() == "union" => {
+                return Err((i.span(), "AdsStruct can only be derived for structs".into()));
+            }
+            Some(_) => {}
+            None => return Err((Span::call_site(), "expected a struct".into())),
+        }
+    }
+
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        _ => return Err((Span::call_site(), "expected a struct name".into())),
+    };
+    let body = match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            return Err((p.span(), "AdsStruct does not support generic structs".into()));
+        }
+        Some(t) => {
+            return Err((t.span(), "AdsStruct requires a struct with named fields".into()));
+        }
+        None => {
+            return Err((Span::call_site(), "AdsStruct requires a struct with named fields".into()));
+        }
+    };
+
+    let fields = split_fields(body.stream()).into_iter()
+                                             .map(parse_field)
+                                             .collect::<ParseResult<_>>()?;
+    Ok(Struct { name, fields, pad })
+}
+
+/// Split the body of the struct at the commas between fields.
+fn split_fields(body: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut fields = Vec::new();
+    let mut current = Vec::new();
+    // Commas within generic arguments of a field type don't separate fields.
+    let mut depth = 0usize;
+    let mut arrow = false;
+    for tt in body {
+        if let TokenTree::Punct(p) = &tt {
+            match p.as_char() {
+                ',' if depth == 0 => {
+                    fields.push(std::mem::take(&mut current));
+                    continue;
+                }
+                '<' => depth += 1,
+                '>' if !arrow => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            arrow = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+        } else {
+            arrow = false;
+        }
+        current.push(tt);
+    }
+    if !current.is_empty() {
+        fields.push(current);
+    }
+    fields
+}
+
+fn parse_field(tokens: Vec<TokenTree>) -> ParseResult<Field> {
+    let mut tokens = tokens.into_iter().peekable();
+    let mut pad = 0;
+    let mut string = None;
+
+    while let Some(TokenTree::Punct(p)) = tokens.peek() {
+        if p.as_char() != '#' {
+            break;
+        }
+        tokens.next();
+        if let Some(TokenTree::Group(attr)) = tokens.next() {
+            for (key, value) in parse_ads_attr(&attr)? {
+                match &*key.to_string() {
+                    "pad" => pad += value,
+                    "string" => string = Some(value),
+                    _ => return Err((key.span(), format!("unknown ads attribute `{}`", key))),
+                }
+            }
+        }
+    }
+
+    // Visibility, possibly with a restriction like `pub(crate)`.
+    if let Some(TokenTree::Ident(i)) = tokens.peek() {
+        if i.to_string() == "pub" {
+            tokens.next();
+            if let Some(TokenTree::Group(g)) = tokens.peek() {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    tokens.next();
+                }
+            }
+        }
+    }
+
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        Some(t) => return Err((t.span(), "expected a field name".into())),
+        None => return Err((Span::call_site(), "expected a field name".into())),
+    };
+    match tokens.next() {
+        Some(TokenTree::Punct(p)) if p.as_char() == ':' => {}
+        _ => return Err((Span::call_site(), format!("expected a type for field `{}`", name))),
+    }
+    let ty = tokens.collect::<TokenStream>().to_string();
+
+    Ok(Field { name, ty, pad, string })
+}
+
+/// Parse the `key = value` pairs of an `#[ads(...)]` attribute.
+///
+/// Other attributes result in an empty list.
+fn parse_ads_attr(attr: &Group) -> ParseResult<Vec<(Ident, usize)>> {
+    let mut tokens = attr.stream().into_iter();
+    match tokens.next() {
+        Some(TokenTree::Ident(i)) if i.to_string() == "ads" => {}
+        _ => return Ok(Vec::new()),
+    }
+    let args = match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g,
+        _ => return Err((attr.span(), "expected ads(key = value, ...)".into())),
+    };
+
+    let mut result = Vec::new();
+    let mut tokens = args.stream().into_iter();
+    loop {
+        let key = match tokens.next() {
+            Some(TokenTree::Ident(key)) => key,
+            None => break,
+            Some(t) => return Err((t.span(), "expected an ads attribute name".into())),
+        };
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '=' => {}
+            _ => return Err((key.span(), format!("expected `{} = <number>`", key))),
+        }
+        let value = match tokens.next() {
+            Some(TokenTree::Literal(lit)) => parse_number(&lit).ok_or_else(
+                || (lit.span(), format!("expected a number for `{}`", key)))?,
+            _ => return Err((key.span(), format!("expected `{} = <number>`", key))),
+        };
+        result.push((key, value));
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ',' => {}
+            None => break,
+            Some(t) => return Err((t.span(), "expected a comma".into())),
+        }
+    }
+    Ok(result)
+}
+
+fn parse_number(lit: &Literal) -> Option<usize> {
+    let text = lit.to_string().replace('_', "");
+    text.trim_end_matches("usize").parse().ok()
+}
+
+impl Struct {
+    fn expand(&self) -> TokenStream {
+        const ADS: &str = "::ads::structs";
+        let name = &self.name;
+
+        let mut sizes = Vec::new();
+        let mut decode = String::new();
+        let mut encode = String::new();
+        for field in &self.fields {
+            if field.pad > 0 {
+                sizes.push(field.pad.to_string());
+                decode.push_str(&format!("{}: {{ decoder.skip({})?; ", field.name, field.pad));
+                encode.push_str(&format!("encoder.skip({})?; ", field.pad));
+            } else {
+                decode.push_str(&format!("{}: {{ ", field.name));
+            }
+            match field.string {
+                Some(len) => {
+                    sizes.push((len + 1).to_string());
+                    decode.push_str(&format!("decoder.read_string({})? }}, ", len));
+                    encode.push_str(&format!("encoder.write_string(&self.{}, {})?; ",
+                                             field.name, len));
+                }
+                None => {
+                    sizes.push(format!("<{} as {}::AdsSize>::ADS_SIZE", field.ty, ADS));
+                    decode.push_str("decoder.read()? }, ");
+                    encode.push_str(&format!("encoder.write(&self.{})?; ", field.name));
+                }
+            }
+        }
+        if self.pad > 0 {
+            sizes.push(self.pad.to_string());
+            encode.push_str(&format!("encoder.skip({})?; ", self.pad));
+        }
+        let size = if sizes.is_empty() { "0".into() } else { sizes.join(" + ") };
+
+        // Without fields (or padding), the decoder and encoder are only used
+        // to check the size.
+        let decoder = if self.fields.is_empty() { "_" } else { "mut decoder" };
+        let encoder = if encode.is_empty() { "_" } else { "mut encoder" };
+
+        format!("
+            #[automatically_derived]
+            impl {ads}::AdsSize for {name} {{
+                const ADS_SIZE: usize = {size};
+            }}
+
+            #[automatically_derived]
+            impl {ads}::FromAds for {name} {{
+                fn from_ads(data: &[u8]) -> ::ads::Result<Self> {{
+                    let {decoder} = {ads}::Decoder::new(
+                        data, <Self as {ads}::AdsSize>::ADS_SIZE)?;
+                    Ok(Self {{ {decode} }})
+                }}
+            }}
+
+            #[automatically_derived]
+            impl {ads}::ToAds for {name} {{
+                fn to_ads(&self, data: &mut [u8]) -> ::ads::Result<()> {{
+                    let {encoder} = {ads}::Encoder::new(
+                        data, <Self as {ads}::AdsSize>::ADS_SIZE)?;
+                    {encode}
+                    Ok(())
+                }}
+            }}
+        ", ads = ADS, name = name, size = size, decoder = decoder, encoder = encoder,
+           decode = decode, encode = encode)
+            .parse()
+            .expect("generated code is valid")
+    }
+}
+
+/// Return a `compile_error!` invocation with the given message.
+fn compile_error(span: Span, msg: &str) -> TokenStream {
+    let mut lit = Literal::string(msg);
+    lit.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis, TokenTree::Literal(lit).into());
+    group.set_span(span);
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct({
+            let mut bang = Punct::new('!', Spacing::Alone);
+            bang.set_span(span);
+            bang
+        }),
+        TokenTree::Group(group),
+        TokenTree::Punct(Punct::new(';', Spacing::Alone)),
+    ].into_iter().collect()
+}
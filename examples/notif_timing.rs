@@ -0,0 +1,59 @@
+//! Compare allocations and throughput of notification reception with and
+//! without reuse of receive buffers.
+//!
+//! Run with `cargo run --release --features mock --example notif_timing`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use ads::notif::{Attributes, TransmissionMode};
+
+struct Counting;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const COUNT: usize = 20000;
+
+fn run(pool_size: usize) {
+    let server = ads::mock::MockServer::new().unwrap();
+    let client = ads::Client::new(server.addr(), ads::Timeouts::new(Duration::from_secs(1)),
+                                  ads::Source::Auto).unwrap();
+    client.buffer_pool_size(pool_size);
+    let dev = client.device(ads::AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    let chan = client.get_notification_channel();
+    let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                 Duration::ZERO, Duration::ZERO);
+    let handle = dev.add_notification(0x4020, 0, &attrib).unwrap();
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let now = Instant::now();
+    for i in 0..COUNT as u32 {
+        server.send_notification(handle, &i.to_le_bytes()).unwrap();
+        drop(chan.recv().unwrap());
+    }
+    let elapsed = now.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+    println!("pool size {:2}: {:?} ({:.0} notifs/s), {:.2} allocations per notification",
+             pool_size, elapsed, COUNT as f64 / elapsed.as_secs_f64(),
+             allocs as f64 / COUNT as f64);
+}
+
+fn main() {
+    run(0);
+    run(ads::client::DEFAULT_BUFFER_POOL_SIZE);
+}
@@ -0,0 +1,559 @@
+//! An asynchronous ADS client, based on tokio.
+//!
+//! This is only available with the `tokio` feature.  It shares the protocol
+//! framing with the blocking [`Client`](crate::Client), but allows any number
+//! of requests to be in flight at the same time; replies are matched to the
+//! requests by invoke ID.
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::io;
+#[cfg(feature = "futures-core")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+#[cfg(feature = "futures-core")]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LE};
+#[cfg(feature = "futures-core")]
+use futures_core::Stream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use zerocopy::byteorder::{U16, U32};
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::client::{auto_source, next_auto_port, AddNotif, AdsState, Command, DeviceInfo,
+                    DeviceInfoRaw, IndexLength, IndexLengthRW, ReadState, Source, Timeouts,
+                    WriteControl, AMS_HEADER_SIZE, TCP_HEADER_SIZE};
+use crate::errors::ErrContext;
+use crate::{frame, notif};
+use crate::{AmsAddr, AmsNetId, Error, Result};
+
+/// Requests waiting for their reply, by invoke ID.  This is `None` once the
+/// reader task has stopped.
+type Pending = Arc<StdMutex<Option<HashMap<u32, oneshot::Sender<Result<Vec<u8>>>>>>>;
+
+/// Where the reader task puts notifications.  Replaced when the notification
+/// buffer is reconfigured.
+type NotifSink = Arc<StdMutex<(NotifSender, Arc<AtomicU64>)>>;
+
+/// Represents an asynchronous connection to a ADS server.
+///
+/// All communication methods use `&self`, so the client can be shared between
+/// tasks, e.g. in an `Arc`.
+pub struct AsyncClient {
+    /// Writing half of the TCP connection
+    writer: Mutex<OwnedWriteHalf>,
+    /// Current invoke ID, incremented for each request
+    invoke_id: AtomicU32,
+    /// Read timeout (for each single reply)
+    read_timeout: Option<Duration>,
+    /// The AMS address of the client
+    source: AmsAddr,
+    /// Requests waiting for their reply
+    pending: Pending,
+    /// The stream of notifications, until it is taken by the user
+    notif_stream: StdMutex<Option<NotificationStream>>,
+    /// The reader task's notification sink, to reconfigure the channel
+    notif_sink: NotifSink,
+    /// The background task reading replies and notifications
+    reader: JoinHandle<()>,
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        // Stop the reader task, which drops the reading half of the socket.
+        // The writing half is shut down when it is dropped.
+        self.reader.abort();
+    }
+}
+
+impl AsyncClient {
+    /// Open a new connection to an ADS server.
+    ///
+    /// See [`Client::new`](crate::Client::new) for the meaning of the
+    /// arguments.  Must be called within a tokio runtime.
+    ///
+    /// Unlike with the blocking client, notification handles and a port
+    /// opened with `Source::Request` are not explicitly closed when the client
+    /// is dropped, since this would require async communication.  The server
+    /// will clean them up when the connection is closed.
+    pub async fn connect(addr: impl ToSocketAddrs, timeouts: Timeouts,
+                         source: Source) -> Result<Self> {
+        let connect = TcpStream::connect(addr);
+        let mut socket = if let Some(timeout) = timeouts.connect {
+            tokio::time::timeout(timeout, connect).await
+                .map_err(|_| io::ErrorKind::TimedOut.into())
+                .ctx("connecting TCP socket with timeout")?
+                .ctx("connecting TCP socket with timeout")?
+        } else {
+            connect.await.ctx("connecting TCP socket")?
+        };
+
+        // Disable Nagle to ensure small requests are sent promptly.
+        socket.set_nodelay(true).ctx("setting NODELAY")?;
+
+        // Determine our source AMS address, like the blocking client does.
+        let my_addr = socket.local_addr().ctx("getting local socket address")?.ip();
+        let source = match source {
+            Source::Addr(id) => id,
+            Source::Auto => auto_source(my_addr, next_auto_port()),
+            Source::AutoPort(port) => auto_source(my_addr, port),
+            Source::Request => {
+                let mut reply = [0; frame::REQUEST_PORT_REPLY_SIZE];
+                socket.write_all(&frame::REQUEST_PORT_MSG).await
+                      .ctx("requesting port from router")?;
+                socket.read_exact(&mut reply).await.ctx("requesting port from router")?;
+                frame::decode_port_reply(&reply)?
+            }
+        };
+
+        let (reader, writer) = socket.into_split();
+        let pending = Pending::new(StdMutex::new(Some(HashMap::new())));
+        let (notif_send, notif_stream) = notification_channel(None);
+        let notif_sink = NotifSink::new(StdMutex::new(notif_send));
+        let mut source_bytes = [0; 8];
+        source.write_to(&mut &mut source_bytes[..]).expect("size");
+
+        // Start the reader task.
+        let reader = tokio::spawn(AsyncReader {
+            socket: reader,
+            source: source_bytes,
+            pending: pending.clone(),
+            notif_sink: notif_sink.clone(),
+        }.run());
+
+        Ok(AsyncClient {
+            writer: Mutex::new(writer),
+            invoke_id: AtomicU32::new(0),
+            read_timeout: timeouts.read,
+            source,
+            pending,
+            notif_stream: StdMutex::new(Some(notif_stream)),
+            notif_sink,
+            reader,
+        })
+    }
+
+    /// Return the source address the client is using.
+    pub fn source(&self) -> AmsAddr {
+        self.source
+    }
+
+    /// Get the stream of notifications.
+    ///
+    /// There is only one stream per client; this returns `None` if it has
+    /// already been taken.
+    pub fn get_notification_stream(&self) -> Option<NotificationStream> {
+        self.notif_stream.lock().expect("not poisoned").take()
+    }
+
+    /// Configure the channel for received notifications.
+    ///
+    /// By default, the channel is unbounded.  With `Some(capacity)`, at most
+    /// that many (but at least one) notifications are kept, and further
+    /// notifications are discarded until the stream has caught up.  Unlike
+    /// the blocking client, the reader task never waits for the stream, since
+    /// that would hold up replies as well.
+    ///
+    /// This replaces the channel: a stream obtained before won't get any more
+    /// notifications, and [`AsyncClient::get_notification_stream`] returns
+    /// the new one.
+    pub fn notification_buffer(&self, capacity: Option<usize>) {
+        let (send, stream) = notification_channel(capacity);
+        *self.notif_sink.lock().expect("not poisoned") = send;
+        *self.notif_stream.lock().expect("not poisoned") = Some(stream);
+    }
+
+    /// Return a wrapper that executes operations for a target device (known by
+    /// NetID and port).
+    ///
+    /// The local NetID `127.0.0.1.1.1` is mapped to the client's source NetID.
+    pub fn device(&self, addr: impl Into<AmsAddr>) -> AsyncDevice<'_> {
+        let mut addr = addr.into();
+        if addr.netid() == AmsNetId::local() {
+            addr = AmsAddr::new(self.source().netid(), addr.port());
+        }
+        AsyncDevice { client: self, addr }
+    }
+
+    /// Low-level function to execute an ADS command.
+    ///
+    /// This works the same as [`Client::communicate`](crate::Client::communicate).
+    pub async fn communicate(&self,
+                             cmd: Command,
+                             target: AmsAddr,
+                             data_in: &[&[u8]],
+                             data_out: &mut [&mut [u8]]) -> Result<usize> {
+        let invoke_id = self.invoke_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        let request = frame::encode_request(cmd as u16, 0, target, self.source,
+                                            invoke_id, data_in)?;
+
+        // Register ourselves for the reply before sending the request.
+        let (reply_send, reply_recv) = oneshot::channel();
+        match &mut *self.pending.lock().expect("not poisoned") {
+            Some(pending) => { pending.insert(invoke_id, reply_send); }
+            None => return Err(Error::Io("sending request",
+                                         io::ErrorKind::NotConnected.into())),
+        }
+
+        let result = self.send_and_receive(&request, reply_recv).await;
+        if result.is_err() {
+            // Nobody is interested in the reply anymore.
+            if let Some(pending) = &mut *self.pending.lock().expect("not poisoned") {
+                pending.remove(&invoke_id);
+            }
+        }
+        let reply = result?;
+
+        frame::decode_reply(cmd, &request, invoke_id, &reply, data_out)
+    }
+
+    async fn send_and_receive(&self, request: &[u8],
+                              reply_recv: oneshot::Receiver<Result<Vec<u8>>>) -> Result<Vec<u8>> {
+        #[cfg(feature = "tracing")]
+        frame::trace_frame("send", request);
+        self.writer.lock().await.write_all(request).await.ctx("sending request")?;
+
+        let reply = if let Some(tmo) = self.read_timeout {
+            tokio::time::timeout(tmo, reply_recv).await
+                .map_err(|_| io::ErrorKind::TimedOut.into())
+                .ctx("receiving reply (route set?)")?
+        } else {
+            reply_recv.await
+        };
+        reply.map_err(|_| io::ErrorKind::UnexpectedEof.into()).ctx("receiving reply (route set?)")?
+    }
+}
+
+// Implementation detail: reader task that distributes replies and notifications
+struct AsyncReader {
+    socket: OwnedReadHalf,
+    source: [u8; 8],
+    pending: Pending,
+    notif_sink: NotifSink,
+}
+
+impl AsyncReader {
+    async fn run(mut self) {
+        let result = self.run_inner().await;
+        // Fail all outstanding requests, and don't accept new ones.  The error
+        // is not Clone, so each request gets the kind of I/O error, if any.
+        let kind = match &result {
+            Err(Error::Io(_, e)) => e.kind(),
+            _ => io::ErrorKind::UnexpectedEof,
+        };
+        let pending = self.pending.lock().expect("not poisoned").take();
+        for (_, reply_send) in pending.into_iter().flatten() {
+            let _ = reply_send.send(Err(Error::Io("receiving reply", kind.into())));
+        }
+    }
+
+    async fn run_inner(&mut self) -> Result<()> {
+        loop {
+            // Read a header from the socket.
+            let mut buf = vec![0; TCP_HEADER_SIZE];
+            self.socket.read_exact(&mut buf).await.ctx("reading packet header")?;
+
+            // Read the rest of the packet.
+            let packet_length = frame::packet_length(&buf)?;
+            buf.resize(TCP_HEADER_SIZE + packet_length, 0);
+            self.socket.read_exact(&mut buf[TCP_HEADER_SIZE..]).await
+                       .ctx("reading rest of packet")?;
+            #[cfg(feature = "tracing")]
+            frame::trace_frame("recv", &buf);
+
+            match frame::classify_packet(&buf, &self.source)? {
+                frame::Packet::Ignore => (),
+                frame::Packet::Reply => {
+                    // Route the reply to the request with the same invoke ID.
+                    // Replies nobody waits for (anymore) are dropped.
+                    let invoke_id = LE::read_u32(&buf[AMS_HEADER_SIZE - 4..]);
+                    let reply_send = match &mut *self.pending.lock().expect("not poisoned") {
+                        Some(pending) => pending.remove(&invoke_id),
+                        None => None,
+                    };
+                    if let Some(reply_send) = reply_send {
+                        let _ = reply_send.send(Ok(buf));
+                    }
+                }
+                frame::Packet::Notification => {
+                    let (send, lost) = &*self.notif_sink.lock().expect("not poisoned");
+                    let discarded = match notif::Notification::new(buf) {
+                        // If the stream was dropped, nobody is interested.
+                        Ok(notif) => match send {
+                            NotifSender::Unbounded(send) => {
+                                let _ = send.send(notif);
+                                false
+                            }
+                            NotifSender::Bounded(send) => {
+                                matches!(send.try_send(notif), Err(TrySendError::Full(_)))
+                            }
+                        },
+                        Err(_) => true,
+                    };
+                    if discarded {
+                        lost.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Implementation detail: the sending half of the notification channel.
+enum NotifSender {
+    Unbounded(mpsc::UnboundedSender<notif::Notification>),
+    Bounded(mpsc::Sender<notif::Notification>),
+}
+
+// Implementation detail: the receiving half of the notification channel.
+enum NotifReceiver {
+    Unbounded(mpsc::UnboundedReceiver<notif::Notification>),
+    Bounded(mpsc::Receiver<notif::Notification>),
+}
+
+/// Create a new notification channel, with the sender (and the counter of
+/// lost notifications) for the reader task.
+fn notification_channel(capacity: Option<usize>) -> ((NotifSender, Arc<AtomicU64>),
+                                                        NotificationStream) {
+    let (send, recv) = match capacity {
+        Some(capacity) => {
+            let (send, recv) = mpsc::channel(capacity.max(1));
+            (NotifSender::Bounded(send), NotifReceiver::Bounded(recv))
+        }
+        None => {
+            let (send, recv) = mpsc::unbounded_channel();
+            (NotifSender::Unbounded(send), NotifReceiver::Unbounded(recv))
+        }
+    };
+    let lost = Arc::new(AtomicU64::new(0));
+    ((send, lost.clone()), NotificationStream { recv, lost })
+}
+
+/// A stream of notifications received by an `AsyncClient`.
+///
+/// With the `futures-core` feature, this implements `futures::Stream`, so
+/// that stream combinators can be used.  If notifications were lost since
+/// the last item, because the channel was full (see
+/// [`AsyncClient::notification_buffer`]) or a notification message was
+/// malformed, the next item is an error with the number of lost
+/// notifications.
+pub struct NotificationStream {
+    recv: NotifReceiver,
+    /// Number of notifications lost since the last item
+    lost: Arc<AtomicU64>,
+}
+
+impl NotificationStream {
+    /// Receive the next notification.
+    ///
+    /// Returns `None` if the connection has been closed.
+    pub async fn next(&mut self) -> Option<Result<notif::Notification>> {
+        if let Some(err) = self.take_lost() {
+            return Some(Err(err));
+        }
+        let notif = match &mut self.recv {
+            NotifReceiver::Unbounded(recv) => recv.recv().await,
+            NotifReceiver::Bounded(recv) => recv.recv().await,
+        };
+        notif.map(Ok)
+    }
+
+    fn take_lost(&self) -> Option<Error> {
+        match self.lost.swap(0, Ordering::Relaxed) {
+            0 => None,
+            n => Some(Error::Reply("receiving notifications", "notifications were lost",
+                                   n.try_into().unwrap_or(u32::MAX))),
+        }
+    }
+}
+
+#[cfg(feature = "futures-core")]
+impl Stream for NotificationStream {
+    type Item = Result<notif::Notification>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(err) = this.take_lost() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        let notif = match &mut this.recv {
+            NotifReceiver::Unbounded(recv) => recv.poll_recv(cx),
+            NotifReceiver::Bounded(recv) => recv.poll_recv(cx),
+        };
+        notif.map(|notif| notif.map(Ok))
+    }
+}
+
+/// An `AsyncClient` wrapper that talks to a specific ADS device.
+#[derive(Clone, Copy)]
+pub struct AsyncDevice<'c> {
+    /// The underlying `AsyncClient`.
+    pub client: &'c AsyncClient,
+    addr: AmsAddr,
+}
+
+impl<'c> AsyncDevice<'c> {
+    /// Return the address of the target device.
+    pub fn addr(&self) -> AmsAddr {
+        self.addr
+    }
+
+    /// Read the device's name + version.
+    pub async fn get_info(&self) -> Result<DeviceInfo> {
+        let mut data = DeviceInfoRaw::new_zeroed();
+        self.client.communicate(Command::DevInfo, self.addr,
+                                &[], &mut [data.as_bytes_mut()]).await?;
+
+        let name = data.name.iter().take_while(|&&ch| ch > 0)
+                                   .map(|&ch| ch as char).collect::<String>();
+        Ok(DeviceInfo {
+            major: data.major,
+            minor: data.minor,
+            version: data.version.get(),
+            name,
+        })
+    }
+
+    /// Read some data at a given index group/offset.  Returned data can be shorter than
+    /// the buffer, the length is the return value.
+    pub async fn read(&self, index_group: u32, index_offset: u32,
+                      data: &mut [u8]) -> Result<usize> {
+        let header = IndexLength {
+            index_group:  U32::new(index_group),
+            index_offset: U32::new(index_offset),
+            length:       U32::new(data.len().try_into()?),
+        };
+        let mut read_len = U32::<LE>::new(0);
+
+        self.client.communicate(Command::Read, self.addr, &[header.as_bytes()],
+                                &mut [read_len.as_bytes_mut(), data]).await
+            .map_err(|e| e.with_request(index_group, index_offset, data.len()))?;
+
+        Ok((read_len.get() as usize).min(data.len()))
+    }
+
+    /// Read some data at a given index group/offset, ensuring that the returned data has
+    /// exactly the size of the passed buffer.
+    pub async fn read_exact(&self, index_group: u32, index_offset: u32,
+                            data: &mut [u8]) -> Result<()> {
+        let len = self.read(index_group, index_offset, data).await?;
+        if len != data.len() {
+            return Err(Error::Reply("read data", "got less data than expected", len as u32));
+        }
+        Ok(())
+    }
+
+    /// Read data of given type.  See [`Device::read_value`](crate::Device::read_value).
+    pub async fn read_value<T: Default + AsBytes + FromBytes>(&self, index_group: u32,
+                                                              index_offset: u32) -> Result<T> {
+        let mut buf = T::default();
+        self.read_exact(index_group, index_offset, buf.as_bytes_mut()).await?;
+        Ok(buf)
+    }
+
+    /// Write some data to a given index group/offset.
+    pub async fn write(&self, index_group: u32, index_offset: u32, data: &[u8]) -> Result<()> {
+        let header = IndexLength {
+            index_group:  U32::new(index_group),
+            index_offset: U32::new(index_offset),
+            length:       U32::new(data.len().try_into()?),
+        };
+        self.client.communicate(Command::Write, self.addr,
+                                &[header.as_bytes(), data], &mut []).await
+            .map_err(|e| e.with_request(index_group, index_offset, data.len()))?;
+        Ok(())
+    }
+
+    /// Write data of given type.  See [`Device::write_value`](crate::Device::write_value).
+    pub async fn write_value<T: AsBytes>(&self, index_group: u32, index_offset: u32,
+                                         value: &T) -> Result<()> {
+        self.write(index_group, index_offset, value.as_bytes()).await
+    }
+
+    /// Write some data to a given index group/offset and then read back some
+    /// reply from there.  This is not the same as a write() followed by read();
+    /// it is used as a kind of RPC call.
+    pub async fn write_read(&self, index_group: u32, index_offset: u32, write_data: &[u8],
+                            read_data: &mut [u8]) -> Result<usize> {
+        let header = IndexLengthRW {
+            index_group:  U32::new(index_group),
+            index_offset: U32::new(index_offset),
+            read_length:  U32::new(read_data.len().try_into()?),
+            write_length: U32::new(write_data.len().try_into()?),
+        };
+        let mut read_len = U32::<LE>::new(0);
+        self.client.communicate(Command::ReadWrite, self.addr, &[header.as_bytes(), write_data],
+                                &mut [read_len.as_bytes_mut(), read_data]).await
+            .map_err(|e| e.with_request(index_group, index_offset, write_data.len()))?;
+        Ok((read_len.get() as usize).min(read_data.len()))
+    }
+
+    /// Return the ADS and device state of the device.
+    ///
+    /// See [`Device::get_state`](crate::Device::get_state) for unknown ADS
+    /// states.
+    pub async fn get_state(&self) -> Result<(AdsState, u16)> {
+        let (ads_state, dev_state) = self.get_state_raw().await?;
+
+        let ads_state = AdsState::try_from(ads_state)
+            .map_err(|e| Error::Reply("read state", e, ads_state.into()))?;
+
+        Ok((ads_state, dev_state))
+    }
+
+    /// Return the ADS and device state of the device, with the ADS state as
+    /// the raw value reported by the device.
+    pub async fn get_state_raw(&self) -> Result<(u16, u16)> {
+        let mut state = ReadState::new_zeroed();
+        self.client.communicate(Command::ReadState, self.addr,
+                                &[], &mut [state.as_bytes_mut()]).await?;
+        Ok((state.ads_state.get(), state.dev_state.get()))
+    }
+
+    /// (Try to) set the ADS and device state of the device.
+    pub async fn write_control(&self, ads_state: AdsState, dev_state: u16) -> Result<()> {
+        let header = WriteControl {
+            ads_state:   U16::new(ads_state as _),
+            dev_state:   U16::new(dev_state),
+            data_length: U32::new(0),
+        };
+        self.client.communicate(Command::WriteControl, self.addr,
+                                &[header.as_bytes()], &mut []).await?;
+        Ok(())
+    }
+
+    /// Add a notification handle for some index group/offset.
+    ///
+    /// Notifications are delivered via the client's `NotificationStream`.
+    pub async fn add_notification(&self, index_group: u32, index_offset: u32,
+                                  attributes: &notif::Attributes) -> Result<notif::Handle> {
+        let data = AddNotif {
+            index_group:  U32::new(index_group),
+            index_offset: U32::new(index_offset),
+            length:       U32::new(attributes.length.try_into()?),
+            trans_mode:   U32::new(attributes.trans_mode as u32),
+            max_delay:    U32::new(attributes.max_delay_ticks()?),
+            cycle_time:   U32::new(attributes.cycle_ticks()?),
+            reserved:     [0; 16],
+        };
+        let mut handle = U32::<LE>::new(0);
+        self.client.communicate(Command::AddNotification, self.addr,
+                                &[data.as_bytes()], &mut [handle.as_bytes_mut()]).await?;
+        Ok(handle.get())
+    }
+
+    /// Delete a notification with given handle.
+    pub async fn delete_notification(&self, handle: notif::Handle) -> Result<()> {
+        self.client.communicate(Command::DeleteNotification, self.addr,
+                                &[U32::<LE>::new(handle).as_bytes()], &mut []).await?;
+        Ok(())
+    }
+}
@@ -1,23 +1,28 @@
 //! Contains the TCP client to connect to an ADS server.
 
-use std::cell::{Cell, RefCell};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{TryFrom, TryInto};
 use std::io::{self, Read, Write};
 use std::mem::size_of;
-use std::net::{IpAddr, Shutdown, TcpStream, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use byteorder::{ByteOrder, ReadBytesExt, LE};
-use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use byteorder::{ByteOrder, LE};
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender, TrySendError};
 use itertools::Itertools;
 
 use crate::errors::{ads_error, ErrContext};
+use crate::frame;
 use crate::notif;
-use crate::{AmsAddr, AmsNetId, Error, Result};
+use crate::structs::{FromAds, ToAds};
+use crate::transport::Transport;
+use crate::{AmsAddr, AmsNetId, AmsPort, Error, Result};
 
-use zerocopy::byteorder::{U16, U32};
+use zerocopy::byteorder::{U16, U32, U64};
 use zerocopy::{AsBytes, FromBytes};
 
 /// An ADS protocol command.
@@ -48,7 +53,7 @@ pub enum Command {
 }
 
 impl Command {
-    fn action(self) -> &'static str {
+    pub(crate) fn action(self) -> &'static str {
         match self {
             Command::DevInfo => "get device info",
             Command::Read => "read data",
@@ -69,6 +74,51 @@ pub(crate) const TCP_HEADER_SIZE: usize = 6;
 pub(crate) const AMS_HEADER_SIZE: usize = 38;  // including AMS/TCP header
 pub(crate) const DEFAULT_BUFFER_SIZE: usize = 100;
 
+/// Default number of receive buffers kept for reuse, see
+/// [`Client::buffer_pool_size`].
+pub const DEFAULT_BUFFER_POOL_SIZE: usize = 10;
+/// Buffers larger than this are not kept for reuse.
+const MAX_POOLED_BUFFER_SIZE: usize = 0x10000;
+
+/// Maximum length of a received packet, after the AMS/TCP header.
+///
+/// A larger length in the AMS/TCP header is taken as a sign of a corrupt or
+/// desynchronized stream, and the connection is closed, instead of trying
+/// to allocate a huge buffer.
+pub const MAX_PACKET_LENGTH: usize = 0x400_0000;
+
+/// AMS state flag: the packet is an ADS command.  This is always set for
+/// requests.
+pub const STATE_FLAG_COMMAND: u16 = 0x04;
+/// AMS state flag: the target should not send a reply.
+pub const STATE_FLAG_NO_RETURN: u16 = 0x02;
+
+/// Maximum number of single requests in one sum-up request.
+///
+/// Batches given to [`Device::read_multi`] and the other `_multi` methods are
+/// transparently split into several sum-up requests if they have more
+/// single requests than this, or if their request or reply data would exceed
+/// [`MAX_SUM_DATA_SIZE`].  The results are reassembled in order.
+pub const MAX_SUM_REQUESTS: usize = 500;
+/// Maximum size of request or reply data for one sum-up request.
+///
+/// This is a conservative limit that should be accepted by all devices.  A
+/// single request that exceeds it on its own is still sent, in a sum-up
+/// request by itself.
+pub const MAX_SUM_DATA_SIZE: usize = 0x10000;
+
+/// Maximum time that [`Device::set_state`] waits for the state change, unless
+/// another one is given with [`Device::set_state_timeout`].
+pub const STATE_CHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between polls of the state in [`Device::set_state`].
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Invoke ID reserved for keepalive requests, whose replies are discarded.
+const KEEPALIVE_INVOKE_ID: u32 = u32::MAX;
+/// Invoke IDs of timed out requests are remembered until this many further
+/// requests have been made; later replies count as unknown.
+const TIMED_OUT_WINDOW: u32 = 1000;
+
 /// Holds the different timeouts that will be used by the Client.
 /// None means no timeout in every case.
 #[derive(Clone, Copy, Debug)]
@@ -96,61 +146,342 @@ impl Timeouts {
 /// Specifies the source AMS address to use.
 #[derive(Clone, Copy, Debug)]
 pub enum Source {
-    /// Auto-generate a source address from the local address and a random port.
+    /// Auto-generate a source address from the local address and a port.
+    ///
+    /// Each client in the process gets a different port, so that several
+    /// clients can talk to the same device.  The port is kept when the client
+    /// reconnects.
     Auto,
+    /// Auto-generate a source NetID from the local address, but use the given
+    /// port.
+    AutoPort(AmsPort),
     /// Use a specified source address.
+    ///
+    /// This is needed if the device has a static route for a specific NetID.
+    /// The address is used in all outgoing AMS headers; the client cannot
+    /// check if another client already uses the same address.
     Addr(AmsAddr),
     /// Request to open a port in the connected router and get the address from
     /// it.  This is necessary when connecting to a local PLC on `127.0.0.1`.
     Request,
 }
 
+/// The next port to use for `Source::Auto`.
+static NEXT_AUTO_PORT: AtomicU16 = AtomicU16::new(58913);
+
+/// Return a new port for `Source::Auto`, from the ephemeral port range.
+pub(crate) fn next_auto_port() -> AmsPort {
+    NEXT_AUTO_PORT.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                                |port| Some(port.checked_add(1).unwrap_or(49152)))
+                  .expect("always Some")
+}
+
+/// Construct the source address for `Source::Auto`.
+pub(crate) fn auto_source(local_ip: IpAddr, port: AmsPort) -> AmsAddr {
+    if let IpAddr::V4(ip) = local_ip {
+        let [a, b, c, d] = ip.octets();
+        AmsAddr::new(AmsNetId::new(a, b, c, d, 1, 1), port)
+    } else {
+        AmsAddr::new(AmsNetId::new(127, 0, 0, 1, 1, 1), port)
+    }
+}
+
+/// Specifies if and how a `Client` reconnects when the connection is lost.
+///
+/// After the connection has been lost, up to `max_attempts` attempts to
+/// reconnect are made; with `max_attempts` set to zero, the client never
+/// reconnects.  Before the first attempt, the client waits for `backoff`;
+/// the wait time is doubled for each further attempt.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectPolicy {
+    /// Maximum number of connection attempts
+    pub max_attempts: u32,
+    /// Time to wait before the first connection attempt
+    pub backoff: Duration,
+}
+
+/// Specifies if and how a [`Device`] retries requests that fail with
+/// transient ADS errors.  See [`Device::with_retry`].
+///
+/// A request is made up to `max_attempts` times, as long as it fails with one
+/// of the error codes in `retry_on`.  Before the first retry, the device waits
+/// for `delay`; the wait time is doubled for each further retry.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// ADS error codes that are considered transient, e.g. 0x70A
+    pub retry_on: &'static [u32],
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Time to wait before the first retry
+    pub delay: Duration,
+}
+
+/// Specifies what happens with a received notification if the notification
+/// channel is full.  See [`Client::notification_buffer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until there is room in the channel.  Note that this also stops
+    /// the reception of replies until then.
+    Block,
+    /// Discard the oldest notification in the channel.
+    DropOldest,
+    /// Discard the new notification.
+    DropNewest,
+}
+
+/// Callback that is called after a reconnect, with the target address, old
+/// and new handle of each reissued notification.
+type ReconnectCallback = Box<dyn FnMut(&[(AmsAddr, notif::Handle, notif::Handle)]) + Send>;
+
+/// Sender for the reply to one request, from the reader thread.
+type ReplySender = Sender<Result<Vec<u8>>>;
+
 /// Represents a connection to a ADS server.
 ///
-/// The Client's communication methods use `&self`, so that it can be freely
-/// shared within one thread, or sent, between threads.  Wrappers such as
-/// `Device` or `symbol::Handle` use a `&Client` as well.
+/// The Client's communication methods use `&self`, and the Client is `Send`
+/// and `Sync`, so that it can be freely shared within one thread and between
+/// threads.  Wrappers such as `Device` or `symbol::Handle` use a `&Client` as
+/// well; `Device` is `Copy`, so every thread can get its own.  To use the
+/// client from spawned threads, put it in an `Arc` and either create the
+/// devices within the threads, or hand out clones of a [`SharedDevice`].
+///
+/// Requests from multiple threads are sent concurrently over the one
+/// connection, without waiting for each other's replies.  Each reply is routed
+/// to its request by the invoke ID in the AMS header; replies arriving after
+/// their request has timed out, or that match no request, are discarded.
+/// Reconnecting (see [`Client::connect_with`]) waits until the other threads'
+/// requests on the lost connection have failed.
 pub struct Client {
-    /// TCP connection (duplicated with the reader)
-    socket: TcpStream,
+    /// The current connection, replaced when reconnecting
+    conn: RwLock<Connection>,
     /// Current invoke ID (identifies the request/reply pair), incremented
-    /// after each request
-    invoke_id: Cell<u32>,
+    /// with each request
+    invoke_id: AtomicU32,
     /// Read timeout (actually receive timeout for the channel)
-    read_timeout: Option<Duration>,
+    read_timeout: Mutex<Option<Duration>>,
+    /// Receiver for notifications: cloned and given out to interested parties
+    notif_recv: Mutex<Receiver<notif::Notification>>,
+    /// The reader threads' notification sink, to reconfigure the channel
+    notif_sink: Weak<Mutex<NotifSink>>,
+    /// Number of notifications that were lost
+    lost_notifs: Arc<AtomicU64>,
+    /// The error that closed the last connection, if any
+    last_error: Arc<Mutex<Option<Error>>>,
+    /// Receive buffers for reuse, shared by all connections
+    buffer_pool: Arc<BufferPool>,
+    /// Active notification handles with their request: these will be closed
+    /// on Drop, and reissued after a reconnect
+    notif_handles: Arc<Mutex<BTreeMap<(AmsAddr, notif::Handle), AddNotif>>>,
+    /// Statistics of received notifications
+    notif_counters: Arc<NotifCounters>,
+    /// Information needed to reconnect, if enabled
+    reconnect: Option<Reconnect>,
+    /// Keepalive interval, if enabled
+    keepalive: Mutex<Option<Duration>>,
+    /// Receiver for raw frames, once `recv_frame` has been called
+    raw_recv: Mutex<Option<Receiver<AmsFrame>>>,
+    /// Symbol handles and sizes for `Device::read_by_name`: these will be
+    /// released on Drop
+    symbol_handles: Mutex<BTreeMap<(AmsAddr, String), (u32, usize)>>,
+}
+
+// Implementation detail: the parts of the client that are specific to a single
+// TCP connection.
+struct Connection {
     /// The AMS address of the client
     source: AmsAddr,
-    /// Sender for used Vec buffers to the reader thread
-    buf_send: Sender<Vec<u8>>,
-    /// Receiver for synchronous replies: used in `communicate`
-    reply_recv: Receiver<Result<Vec<u8>>>,
-    /// Receiver for notifications: cloned and given out to interested parties
-    notif_recv: Receiver<notif::Notification>,
-    /// Active notification handles: these will be closed on Drop
-    notif_handles: RefCell<BTreeSet<(AmsAddr, notif::Handle)>>,
+    /// Pool of used Vec buffers for the reader thread
+    buffer_pool: Arc<BufferPool>,
+    /// Counts the connections made by a client, to reconnect only once
+    generation: u64,
     /// If we opened our local port with the router
     source_port_opened: bool,
+    /// Addresses of the TCP connection
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    /// State shared with the reader and keepalive threads
+    shared: Arc<ConnShared>,
+    /// Dropping this stops the keepalive thread, if running
+    keepalive_stop: Option<Sender<()>>,
+    /// The reader thread, to wait for it on shutdown
+    reader: Option<JoinHandle<()>>,
+}
+
+// Implementation detail: connection state shared between threads.
+struct ConnShared {
+    /// Time when the last packet was received
+    last_received: Mutex<Instant>,
+    /// Target of the last request, used for keepalive requests
+    last_target: Mutex<Option<AmsAddr>>,
+    /// The connection (duplicated with the reader), locked while writing a
+    /// request
+    writer: Mutex<Box<dyn Transport>>,
+    /// Another handle to the connection, to shut it down without waiting for
+    /// a write that is stuck
+    closer: Mutex<Box<dyn Transport>>,
+    /// Requests waiting for their reply, by invoke ID; None once the reader
+    /// thread has quit
+    pending: Mutex<Option<BTreeMap<u32, ReplySender>>>,
+    /// Invoke IDs of requests that timed out: their replies are discarded
+    timed_out: Mutex<BTreeSet<u32>>,
+    /// Number of replies that matched no request
+    unknown_replies: AtomicU64,
+    /// Set when the client closes the connection, which is not an error
+    closing: AtomicBool,
+    /// Cleared when the connection is found to be dead, by the reader or the
+    /// keepalive thread
+    connected: AtomicBool,
+    /// Why the keepalive thread closed the connection
+    keepalive_error: Mutex<Option<Error>>,
+}
+
+// Implementation detail: everything needed to reconnect.
+struct Reconnect {
+    addr: SocketAddr,
+    timeouts: Timeouts,
+    source: Source,
+    policy: ReconnectPolicy,
+    /// Sink for notifications, for the reader thread of the next connection
+    notif_sink: Arc<Mutex<NotifSink>>,
+    callback: Mutex<Option<ReconnectCallback>>,
+}
+
+// Implementation detail: where the reader threads put notifications.
+struct NotifSink {
+    send: Sender<notif::Notification>,
+    /// Receiver for the same channel, to discard the oldest notification
+    recv: Receiver<notif::Notification>,
+    policy: OverflowPolicy,
+    lost: Arc<AtomicU64>,
+    /// Callbacks for samples of notifications registered with `Device::watch`
+    watchers: BTreeMap<(AmsAddr, notif::Handle), Watcher>,
+    /// Number of watches being set up; while nonzero, samples without a
+    /// watcher are kept in `unclaimed`, since they could be for the new one
+    watches_pending: usize,
+    unclaimed: Vec<(AmsAddr, notif::OwnedSample)>,
+    /// If set by `Client::recv_frame`, all ADS frames are sent here
+    raw: Option<Sender<AmsFrame>>,
+    /// Set together with `raw`, so that the reader threads can check it
+    /// without locking the sink
+    raw_mode: Arc<AtomicBool>,
+    /// The error that made the last reader thread quit
+    last_error: Arc<Mutex<Option<Error>>>,
+    /// The client's active notification handles
+    handles: Arc<Mutex<BTreeMap<(AmsAddr, notif::Handle), AddNotif>>>,
+    counters: Arc<NotifCounters>,
+}
+
+// Implementation detail: counters for `Client::stats`.
+#[derive(Default)]
+struct NotifCounters {
+    delivered: AtomicU64,
+    unmatched: AtomicU64,
+    overflow: AtomicU64,
+    /// Number of notifications being added; while nonzero, samples for
+    /// unknown handles could be for the new ones
+    adds_pending: AtomicUsize,
+    /// Number of samples by unknown handle that arrived while notifications
+    /// were being added; counted as unmatched once all adds are done, if the
+    /// handle is still unknown
+    deferred: Mutex<BTreeMap<(AmsAddr, notif::Handle), u64>>,
+}
+
+type Watcher = Arc<dyn Fn(&notif::Sample) + Send + Sync>;
+
+// Implementation detail: a bounded free list of receive buffers.  Buffers are
+// returned by the requesting threads after decoding replies, and by dropped
+// notifications.
+pub(crate) struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    capacity: AtomicUsize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { free: Mutex::default(), capacity: AtomicUsize::new(capacity) }
+    }
+
+    /// Get a buffer from the pool, or a new one if it is empty.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.free.lock().expect("not poisoned").pop()
+                        .unwrap_or_else(|| Vec::with_capacity(DEFAULT_BUFFER_SIZE))
+    }
+
+    /// Return a buffer to the pool, unless it is full or the buffer is too
+    /// large to keep around.
+    pub(crate) fn put(&self, mut buf: Vec<u8>) {
+        let mut free = self.free.lock().expect("not poisoned");
+        if free.len() < self.capacity.load(Ordering::Relaxed) &&
+            buf.capacity() <= MAX_POOLED_BUFFER_SIZE
+        {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+        self.free.lock().expect("not poisoned").truncate(capacity);
+    }
+}
+
+/// Maximum number of unclaimed samples kept while setting up a watch.
+const MAX_UNCLAIMED_SAMPLES: usize = 100;
+
+impl NotifSink {
+    /// Return the samples of the notification that have a watcher, together
+    /// with it.  The watchers are called by the caller, without holding the
+    /// lock on the sink.
+    fn watched_samples<'n>(&mut self, source: AmsAddr, notif: &'n notif::Notification)
+                           -> Vec<(Watcher, notif::Sample<'n>)> {
+        let mut watched = Vec::new();
+        if self.watchers.is_empty() && self.watches_pending == 0 {
+            return watched;
+        }
+        for sample in notif.samples() {
+            if let Some(watcher) = self.watchers.get(&(source, sample.handle)) {
+                watched.push((watcher.clone(), sample));
+            } else if self.watches_pending > 0 && self.unclaimed.len() < MAX_UNCLAIMED_SAMPLES {
+                self.unclaimed.push((source, sample.into_owned()));
+            }
+        }
+        watched
+    }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
+        // Release all cached symbol handles.
+        let handles = std::mem::take(self.symbol_handles.get_mut().expect("not poisoned"));
+        for ((addr, _), (handle, _)) in handles {
+            let _ = self.release_symbol_handle(addr, handle);
+        }
+
         // Close all open notification handles.
-        let handles = std::mem::take(&mut *self.notif_handles.borrow_mut());
-        for (addr, handle) in handles {
-            let _ = self.device(addr).delete_notification(handle);
+        let handles = std::mem::take(&mut *self.notif_handles.lock().expect("not poisoned"));
+        for (addr, handle) in handles.into_keys() {
+            let _ = self.communicate_once(Command::DeleteNotification, addr,
+                                          &[U32::<LE>::new(handle).as_bytes()], &mut []);
         }
+    }
+}
 
-        // Remove our port from the router, if necessary.
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Remove our port from the router, if necessary.  Don't wait for a
+        // write in progress, which could be stuck; the router also frees the
+        // port when the connection is closed.
         if self.source_port_opened {
-            let mut close_port_msg = [1, 0, 2, 0, 0, 0, 0, 0];
-            LE::write_u16(&mut close_port_msg[6..], self.source.port());
-            let _ = self.socket.write_all(&close_port_msg);
+            if let Ok(mut writer) = self.shared.writer.try_lock() {
+                let _ = writer.write_all(&frame::close_port_msg(self.source.port()));
+            }
         }
 
         // Need to shutdown the connection since the socket is duplicated in the
         // reader thread.  This will cause the read() in the thread to return
         // with no data.
-        let _ = self.socket.shutdown(Shutdown::Both);
+        self.shared.closing.store(true, Ordering::SeqCst);
+        self.shared.shutdown();
     }
 }
 
@@ -169,8 +500,9 @@ impl Client {
     /// IPv4 address, `127.0.0.1.1.1` is used.
     ///
     /// The AMS port of `source` is not important, as long as it is not a
-    /// well-known service port; an ephemeral port number > 49152 is
-    /// recommended.  If Auto, the port is set to 58913.
+    /// well-known service port and not used by another client on the same
+    /// host; an ephemeral port number > 49152 is recommended.  If Auto, each
+    /// client gets a different port, starting at 58913.
     ///
     /// If you are connecting to the local PLC, you need to set `source` to
     /// `Source::Request`.  This will ask the local AMS router for a new
@@ -181,114 +513,487 @@ impl Client {
     /// clients should make sure to replicate this behavior, as opening a second
     /// connection will close the first.
     pub fn new(addr: impl ToSocketAddrs, timeouts: Timeouts, source: Source) -> Result<Self> {
+        Self::new_inner(addr, timeouts, source, None)
+    }
+
+    /// Open a new connection to an ADS server, which is automatically
+    /// reestablished when it is lost.
+    ///
+    /// The arguments are the same as for [`Client::new`], plus the policy for
+    /// reconnecting.  When a request fails because the connection was closed
+    /// or reset, the client reconnects and the request is sent again.  Note
+    /// that this means that a request can be executed twice, if the
+    /// connection was lost after the server had received it.
+    ///
+    /// Since the server forgets all notifications when the connection is lost,
+    /// the notifications added by this client are added again after
+    /// reconnecting.  They get new handles; use [`Client::on_reconnect`] to be
+    /// informed of the new handles.
+    pub fn connect_with(addr: impl ToSocketAddrs, timeouts: Timeouts, source: Source,
+                        policy: ReconnectPolicy) -> Result<Self> {
+        Self::new_inner(addr, timeouts, source, Some(policy))
+    }
+
+    /// Open a new connection to an ADS server, and check that the `target`
+    /// device is reachable.
+    ///
+    /// This is the same as [`Client::new`], but additionally sends a ReadState
+    /// request to `target`.  If, for example, the target port is not running
+    /// or there is no route to the target, this returns an error right away,
+    /// instead of when the first real request is made.  ADS errors are
+    /// returned with the action "checking target", and describe the problem,
+    /// e.g. "Target port not found".
+    pub fn connect_checked(addr: impl ToSocketAddrs, timeouts: Timeouts, source: Source,
+                           target: AmsAddr) -> Result<Self> {
+        let client = Self::new(addr, timeouts, source)?;
+        let result = client.device(target).get_state();
+        match result {
+            Ok(_) => Ok(client),
+            Err(Error::Ads(_, _, code)) => ads_error("checking target", code),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Connect to the PLC with the given IP address, with the usual defaults.
+    ///
+    /// This is the same as [`connect_ip_port`](Self::connect_ip_port) with
+    /// the port of the first TwinCAT 3 PLC runtime, 851.
+    pub fn connect_ip(ip: Ipv4Addr, timeouts: Timeouts) -> Result<(Self, AmsAddr)> {
+        Self::connect_ip_port(ip, crate::ports::Port::Tc3Plc1, timeouts)
+    }
+
+    /// Connect to an ADS device on the host with the given IP address, with
+    /// the usual defaults.
+    ///
+    /// This assumes the common configuration, where the NetID of the host is
+    /// its IP address with `.1.1` appended, and its AMS router listens on the
+    /// default TCP port [`PORT`](crate::PORT).  The source address is chosen
+    /// as with `Source::Auto`.  Returns the client and the address of the
+    /// target device, to use with [`device`](Self::device).
+    ///
+    /// If the NetID of the host is different, use [`Client::new`] with an
+    /// explicit [`AmsAddr`].
+    pub fn connect_ip_port(ip: Ipv4Addr, port: impl Into<AmsPort>,
+                           timeouts: Timeouts) -> Result<(Self, AmsAddr)> {
+        let (socket_addr, target) = ip_target(ip, port.into());
+        Ok((Self::new(socket_addr, timeouts, Source::Auto)?, target))
+    }
+
+    /// Run the ADS protocol over a custom transport, instead of a TCP
+    /// connection opened by the client.
+    ///
+    /// `remote_addr` is only used as the peer address reported by
+    /// [`connection_info`](Self::connection_info).  The connect and write
+    /// timeouts have no effect here; they need to be configured on the
+    /// transport, if it supports them.  The source address is determined as
+    /// for [`Client::new`], using [`Transport::local_addr`]; for transports
+    /// that are not sockets, set it with `Source::Addr`.
+    ///
+    /// The transport must support independent handles for reading, writing
+    /// and shutting down, see [`Transport`].  Since the transport can't be
+    /// opened again, the client doesn't reconnect.
+    pub fn with_transport(transport: impl Transport, remote_addr: SocketAddr,
+                          timeouts: Timeouts, source: Source) -> Result<Self> {
+        Self::build(timeouts, source, None, |source, notif_sink, buffer_pool| {
+            Connection::new(Box::new(transport), remote_addr, source, notif_sink, buffer_pool)
+        })
+    }
+
+    fn new_inner(addr: impl ToSocketAddrs, timeouts: Timeouts, source: Source,
+                 policy: Option<ReconnectPolicy>) -> Result<Self> {
         // Connect, taking the timeout into account.  Unfortunately
         // connect_timeout wants a single SocketAddr.
         let addr = addr.to_socket_addrs().ctx("converting address to SocketAddr")?
                                          .next().expect("at least one SocketAddr");
-        let mut socket = if let Some(timeout) = timeouts.connect {
-            TcpStream::connect_timeout(&addr, timeout).ctx("connecting TCP socket with timeout")?
-        } else {
-            TcpStream::connect(&addr).ctx("connecting TCP socket")?
+        let reconnect = policy.map(|policy| (addr, policy));
+        Self::build(timeouts, source, reconnect, |source, notif_sink, buffer_pool| {
+            Connection::connect(addr, timeouts, source, notif_sink, buffer_pool)
+        })
+    }
+
+    fn build(timeouts: Timeouts, source: Source, reconnect: Option<(SocketAddr, ReconnectPolicy)>,
+             connect: impl FnOnce(Source, Arc<Mutex<NotifSink>>, Arc<BufferPool>)
+                                  -> Result<Connection>) -> Result<Self> {
+        let (notif_send, notif_recv) = unbounded();
+        let lost_notifs = Arc::new(AtomicU64::new(0));
+        let last_error = Arc::new(Mutex::new(None));
+        let notif_handles = Arc::new(Mutex::default());
+        let notif_counters = Arc::new(NotifCounters::default());
+        let notif_sink = Arc::new(Mutex::new(NotifSink {
+            send: notif_send,
+            recv: notif_recv.clone(),
+            policy: OverflowPolicy::Block,
+            lost: lost_notifs.clone(),
+            watchers: BTreeMap::new(),
+            watches_pending: 0,
+            unclaimed: Vec::new(),
+            raw: None,
+            raw_mode: Arc::new(AtomicBool::new(false)),
+            last_error: last_error.clone(),
+            handles: notif_handles.clone(),
+            counters: notif_counters.clone(),
+        }));
+        // Allocate the port once, so that it is kept on reconnect.
+        let source = match source {
+            Source::Auto => Source::AutoPort(next_auto_port()),
+            source => source,
         };
+        let buffer_pool = Arc::new(BufferPool::new(DEFAULT_BUFFER_POOL_SIZE));
+        let conn = connect(source, notif_sink.clone(), buffer_pool.clone())?;
+
+        // Only keep the sender for notifications if we need to start new
+        // readers; otherwise the channel should be disconnected when the
+        // reader thread quits.
+        let weak_sink = Arc::downgrade(&notif_sink);
+        let reconnect = reconnect.map(|(addr, policy)| Reconnect {
+            addr,
+            timeouts,
+            source,
+            policy,
+            notif_sink,
+            callback: Mutex::new(None),
+        });
 
-        // Disable Nagle to ensure small requests are sent promptly; we're
-        // playing ping-pong with request reply, so no pipelining.
-        socket.set_nodelay(true).ctx("setting NODELAY")?;
-        socket.set_write_timeout(timeouts.write).ctx("setting write timeout")?;
+        Ok(Client {
+            conn: RwLock::new(conn),
+            notif_recv: Mutex::new(notif_recv),
+            notif_sink: weak_sink,
+            lost_notifs,
+            last_error,
+            buffer_pool,
+            invoke_id: AtomicU32::new(0),
+            read_timeout: Mutex::new(timeouts.read),
+            notif_handles,
+            notif_counters,
+            reconnect,
+            keepalive: Mutex::new(None),
+            raw_recv: Mutex::new(None),
+            symbol_handles: Mutex::default(),
+        })
+    }
 
-        // Determine our source AMS address.  If it's not specified, try to use
-        // the socket's local IPv4 address, if it's IPv6 (not sure if Beckhoff
-        // devices support that) use `127.0.0.1` as the last resort.
-        //
-        // If source is Request, send an AMS port open message to the connected
-        // router to get our source address.  This is required when connecting
-        // via localhost, apparently.
-        let mut source_port_opened = false;
-        let source = match source {
-            Source::Addr(id) => id,
-            Source::Auto => {
-                let my_addr = socket.local_addr().ctx("getting local socket address")?.ip();
-                if let IpAddr::V4(ip) = my_addr {
-                    let [a, b, c, d] = ip.octets();
-                    // use some random ephemeral port
-                    AmsAddr::new(AmsNetId::new(a, b, c, d, 1, 1), 58913)
-                } else {
-                    AmsAddr::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 58913)
-                }
-            }
-            Source::Request => {
-                let request_port_msg = [0, 16, 2, 0, 0, 0, 0, 0];
-                let mut reply = [0; 14];
-                socket.write_all(&request_port_msg).ctx("requesting port from router")?;
-                socket.read_exact(&mut reply).ctx("requesting port from router")?;
-                if reply[..6] != [0, 16, 8, 0, 0, 0] {
-                    return Err(Error::Reply("requesting port", "unexpected reply header", 0));
-                }
-                source_port_opened = true;
-                AmsAddr::new(AmsNetId::from_slice(&reply[6..12]).expect("size"),
-                             LE::read_u16(&reply[12..14]))
+    /// Close the connection cleanly.
+    ///
+    /// All notifications added by this client are deleted, with one sum-up
+    /// request per target device, then the connection is closed and the
+    /// reader thread is stopped.  Notifications that are still in the
+    /// notification channel are discarded.
+    ///
+    /// Dropping the client also deletes the notifications, but one by one and
+    /// ignoring errors.  Here, all handles are deleted even if some fail, and
+    /// the first error is returned.
+    pub fn shutdown(mut self) -> Result<()> {
+        let handles = std::mem::take(&mut *self.notif_handles.lock().expect("not poisoned"));
+        let mut by_target = BTreeMap::<_, Vec<_>>::new();
+        for (addr, handle) in handles.into_keys() {
+            by_target.entry(addr).or_default().push(handle);
+        }
+        let mut result = Ok(());
+        for (addr, handles) in by_target {
+            let res = self.device(addr).delete_notification_list(&handles)
+                .and_then(|results| results.into_iter().collect());
+            if result.is_ok() {
+                result = res;
             }
-        };
+        }
 
-        // Clone the socket for the reader thread and create our channels for
-        // bidirectional communication.
-        let socket_clone = socket.try_clone().ctx("cloning TCP socket")?;
-        let (buf_send, buf_recv) = bounded(10);
-        let (reply_send, reply_recv) = bounded(1);
-        let (notif_send, notif_recv) = unbounded();
-        let mut source_bytes = [0; 8];
-        source.write_to(&mut &mut source_bytes[..]).expect("size");
+        let reader = self.conn.get_mut().expect("not poisoned").reader.take();
+        let notif_recv = self.get_notification_channel();
+        // This closes the connection, which stops the reader thread.
+        drop(self);
+        // Make sure the reader is not blocked on a full notification channel.
+        // The channel is disconnected when it quits.
+        while notif_recv.recv().is_ok() {}
+        if let Some(reader) = reader {
+            let _ = reader.join();
+        }
+        result
+    }
 
-        // Start the reader thread.
-        let reader = Reader {
-            socket: socket_clone,
-            source: source_bytes,
-            buf_recv,
-            reply_send,
-            notif_send,
-        };
-        std::thread::spawn(|| reader.run());
+    /// Set a callback that is called after the client has reconnected.
+    ///
+    /// The callback gets a list of reissued notifications, with the target
+    /// address, the old and the new notification handle.  Notifications that
+    /// could not be added again are not in the list.
+    ///
+    /// This has no effect if the client was not created with
+    /// [`Client::connect_with`].
+    pub fn on_reconnect(&self, callback: impl FnMut(&[(AmsAddr, notif::Handle, notif::Handle)])
+                                              + Send + 'static) {
+        if let Some(reconnect) = &self.reconnect {
+            *reconnect.callback.lock().expect("not poisoned") = Some(Box::new(callback));
+        }
+    }
 
-        Ok(Client {
-            socket,
-            source,
-            buf_send,
-            reply_recv,
-            notif_recv,
-            invoke_id: Cell::new(0),
-            read_timeout: timeouts.read,
-            notif_handles: RefCell::default(),
-            source_port_opened,
-        })
+    /// Set the timeout for waiting for the reply to each request.
+    ///
+    /// If a reply doesn't arrive in time, the request returns an I/O error of
+    /// kind `TimedOut`.  `None` means to wait forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) {
+        *self.read_timeout.lock().expect("not poisoned") = timeout;
+    }
+
+    /// Return the current timeout for waiting for replies.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        *self.read_timeout.lock().expect("not poisoned")
+    }
+
+    /// Periodically check if the connection is still alive.
+    ///
+    /// If nothing has been received from the server for `interval`, a
+    /// ReadState request is sent to the target of the last request.  If there
+    /// is still nothing received after another `interval`, the connection is
+    /// considered dead and shut down, so that the next request fails right
+    /// away instead of waiting for a timeout.  With
+    /// [`Client::connect_with`], this causes the next request to reconnect.
+    ///
+    /// The keepalive requests use a reserved invoke ID, and their replies are
+    /// discarded.  Nothing is sent before the first request.
+    pub fn enable_keepalive(&self, interval: Duration) {
+        *self.keepalive.lock().expect("not poisoned") = Some(interval);
+        self.conn.write().expect("not poisoned").start_keepalive(interval);
     }
 
     /// Return the source address the client is using.
+    ///
+    /// With `Source::Auto` or `Source::Request`, this is the address that was
+    /// determined when connecting.  If the route to the server is missing, this
+    /// is the NetID that must be added.
     pub fn source(&self) -> AmsAddr {
-        self.source
+        self.conn.read().expect("not poisoned").source
+    }
+
+    /// Return whether the connection is still alive, without sending a request.
+    ///
+    /// This reflects the last observed state: it becomes false once the
+    /// connection was closed or failed, as noticed by the thread receiving
+    /// replies, or by the keepalive (see [`enable_keepalive`](Self::enable_keepalive)).
+    /// A connection that just went down silently is only detected when the
+    /// next request or keepalive fails.  After a successful reconnect, it is
+    /// true again.
+    pub fn is_connected(&self) -> bool {
+        self.conn.read().expect("not poisoned").shared.connected.load(Ordering::SeqCst)
+    }
+
+    /// Return information about the current connection, e.g. for diagnostics.
+    pub fn connection_info(&self) -> ConnectionInfo {
+        let conn = self.conn.read().expect("not poisoned");
+        ConnectionInfo {
+            peer_addr: conn.peer_addr,
+            local_addr: conn.local_addr,
+            source: conn.source,
+            source_requested: conn.source_port_opened,
+            reconnects: conn.generation,
+            unknown_replies: conn.shared.unknown_replies.load(Ordering::Relaxed),
+        }
     }
 
     /// Get a receiver for notifications.
     pub fn get_notification_channel(&self) -> Receiver<notif::Notification> {
-        self.notif_recv.clone()
+        self.notif_recv.lock().expect("not poisoned").clone()
+    }
+
+    /// Get a receiver for notifications that reports why the connection was lost.
+    ///
+    /// Unlike the plain channel from
+    /// [`get_notification_channel`](Self::get_notification_channel), which
+    /// is just disconnected, this returns the error that made the connection
+    /// unusable, and distinguishes it from the client being closed.  See
+    /// [`notif::NotificationReceiver`].
+    pub fn notification_receiver(&self) -> notif::NotificationReceiver {
+        notif::NotificationReceiver::new(self.get_notification_channel(),
+                                         self.last_error.clone())
+    }
+
+    /// Return the error that made the last connection unusable.
+    ///
+    /// This is `None` until a connection has failed, and stays `None` if the
+    /// connection was closed by the client.  With a reconnecting client, the
+    /// error of the lost connection is still returned after a successful
+    /// reconnect, even though the new connection is fine.
+    pub fn last_error(&self) -> Option<Error> {
+        self.last_error.lock().expect("not poisoned").as_ref().map(Error::duplicate)
+    }
+
+    /// Configure the channel for received notifications.
+    ///
+    /// By default, the channel is unbounded.  With `Some(capacity)`, at most
+    /// that many (but at least one) notifications are kept, and `policy`
+    /// determines what happens with further notifications.  Discarded
+    /// notifications are counted by [`Client::lost_notifications`].
+    ///
+    /// This replaces the channel: receivers obtained before from
+    /// [`Client::get_notification_channel`] won't get any more notifications.
+    pub fn notification_buffer(&self, capacity: Option<usize>, policy: OverflowPolicy) {
+        let (send, recv) = match capacity {
+            Some(capacity) => bounded(capacity.max(1)),
+            None => unbounded(),
+        };
+        // If the reader thread has quit, the new channel is disconnected
+        // right away, like the old one.
+        if let Some(sink) = self.notif_sink.upgrade() {
+            let mut sink = sink.lock().expect("not poisoned");
+            sink.send = send;
+            sink.recv = recv.clone();
+            sink.policy = policy;
+        }
+        *self.notif_recv.lock().expect("not poisoned") = recv;
+    }
+
+    /// Register a watcher for the samples of a notification, which is added
+    /// by `add`.
+    ///
+    /// Samples that arrive before the watcher is registered are replayed.
+    fn watch_notification(&self, addr: AmsAddr,
+                          add: impl FnOnce() -> Result<notif::Handle>,
+                          watcher: Watcher) -> Result<notif::Handle> {
+        let sink = self.notif_sink.upgrade().ok_or_else(
+            || Error::Io("watching notification", io::ErrorKind::NotConnected.into()))?;
+        sink.lock().expect("not poisoned").watches_pending += 1;
+        let result = add();
+        let mut sink = sink.lock().expect("not poisoned");
+        if let Ok(handle) = result {
+            for (source, sample) in &sink.unclaimed {
+                if (*source, sample.handle) == (addr, handle) {
+                    watcher(&sample.borrowed());
+                }
+            }
+            sink.watchers.insert((addr, handle), watcher);
+        }
+        sink.watches_pending -= 1;
+        if sink.watches_pending == 0 {
+            sink.unclaimed.clear();
+        }
+        result
+    }
+
+    /// Remove the watcher of a notification, which closes its channel.
+    fn unwatch_notification(&self, addr: AmsAddr, handle: notif::Handle) {
+        if let Some(sink) = self.notif_sink.upgrade() {
+            sink.lock().expect("not poisoned").watchers.remove(&(addr, handle));
+        }
+    }
+
+    /// Set the number of receive buffers that are kept for reuse.
+    ///
+    /// Packets from the server are read into buffers taken from a pool,
+    /// which are returned after a reply has been decoded, or when a
+    /// notification is dropped.  This avoids an allocation per packet, in
+    /// particular with high rates of notifications.  At most `size` buffers
+    /// are kept (the default is [`DEFAULT_BUFFER_POOL_SIZE`]); zero disables
+    /// the reuse.  Buffers larger than 64 KiB are never kept.
+    pub fn buffer_pool_size(&self, size: usize) {
+        self.buffer_pool.set_capacity(size);
+    }
+
+    /// Receive the next ADS frame from the connection, with its full AMS header.
+    ///
+    /// This is a low-level API for gateways and routers.  On the first call,
+    /// the client switches to raw mode: from then on, all received ADS
+    /// frames, whether replies, notifications or frames addressed to other
+    /// AMS addresses, are queued for this function instead of being
+    /// dispatched.
+    ///
+    /// Mixing this with the high-level API (`read`, `write`, notifications
+    /// etc.) on the same client is unsupported: replies to requests are not
+    /// delivered anymore, so these time out.
+    ///
+    /// ADS frames with an inconsistent AMS header are dropped.  Other AMS/TCP
+    /// packets are handled as usual, so that an unknown AMS command or an
+    /// invalid packet length still closes the connection.
+    ///
+    /// The read timeout applies; if the connection is closed, an error of
+    /// kind `UnexpectedEof` is returned.
+    pub fn recv_frame(&self) -> Result<AmsFrame> {
+        let recv = {
+            let mut raw_recv = self.raw_recv.lock().expect("not poisoned");
+            if raw_recv.is_none() {
+                let sink = self.notif_sink.upgrade().ok_or_else(
+                    || Error::Io("receiving frame", io::ErrorKind::NotConnected.into()))?;
+                let (send, recv) = unbounded();
+                let mut sink = sink.lock().expect("not poisoned");
+                sink.raw = Some(send);
+                sink.raw_mode.store(true, Ordering::SeqCst);
+                *raw_recv = Some(recv);
+            }
+            raw_recv.clone().expect("set above")
+        };
+        if let Some(tmo) = self.read_timeout() {
+            recv.recv_timeout(tmo).map_err(|e| match e {
+                RecvTimeoutError::Timeout => io::ErrorKind::TimedOut.into(),
+                RecvTimeoutError::Disconnected => io::ErrorKind::UnexpectedEof.into(),
+            }).ctx("receiving frame")
+        } else {
+            recv.recv().map_err(|_| io::ErrorKind::UnexpectedEof.into()).ctx("receiving frame")
+        }
+    }
+
+    /// Return the number of notifications that were lost.
+    ///
+    /// This counts notifications discarded because the notification channel
+    /// was full, and notification messages from the server that carried an
+    /// error code (e.g. because the server ran out of memory) or were
+    /// malformed.
+    pub fn lost_notifications(&self) -> u64 {
+        self.lost_notifs.load(Ordering::Relaxed)
+    }
+
+    /// Return statistics about the received notifications.
+    ///
+    /// This helps to diagnose, for example, why notifications still arrive
+    /// for handles that have been deleted.
+    pub fn stats(&self) -> NotificationStats {
+        let counters = &self.notif_counters;
+        NotificationStats {
+            delivered: counters.delivered.load(Ordering::Relaxed),
+            unmatched: counters.unmatched.load(Ordering::Relaxed),
+            overflow: counters.overflow.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run `add`, which adds notifications, without counting samples for the
+    /// new handles as unmatched before they are registered.
+    fn adding_notifications<T>(&self, add: impl FnOnce() -> T) -> T {
+        let counters = &self.notif_counters;
+        counters.adds_pending.fetch_add(1, Ordering::SeqCst);
+        let result = add();
+        let mut deferred = counters.deferred.lock().expect("not poisoned");
+        if counters.adds_pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let handles = self.notif_handles.lock().expect("not poisoned");
+            let unmatched: u64 = std::mem::take(&mut *deferred).into_iter()
+                .filter(|(key, _)| !handles.contains_key(key))
+                .map(|(_, count)| count)
+                .sum();
+            counters.unmatched.fetch_add(unmatched, Ordering::Relaxed);
+        }
+        result
     }
 
     /// Return a wrapper that executes operations for a target device (known by
     /// NetID and port).
     ///
+    /// The address can also be given as a tuple of NetID and port, where the
+    /// port is either a number or a [`Port`](crate::ports::Port).
+    ///
     /// The local NetID `127.0.0.1.1.1` is mapped to the client's source NetID,
     /// so that you can connect to a local PLC using:
     ///
     /// ```rust,ignore
     /// let client = Client::new("127.0.0.1", ..., Source::Request);
-    /// let device = client.device(AmsAddr::new(AmsNetId::local(), 851));
+    /// let device = client.device((AmsNetId::local(), Port::Tc3Plc1));
     /// ```
     ///
     /// without knowing its NetID.
-    pub fn device(&self, mut addr: AmsAddr) -> Device<'_> {
+    ///
+    /// Any number of devices, e.g. with the same NetID but different ports
+    /// for multiple PLC runtimes, can be used at the same time.  They all share
+    /// this client's connection.
+    pub fn device(&self, addr: impl Into<AmsAddr>) -> Device<'_> {
+        let mut addr = addr.into();
         if addr.netid() == AmsNetId::local() {
             addr = AmsAddr::new(self.source().netid(), addr.port());
         }
-        Device { client: self, addr }
+        Device { client: self, addr, retry: None }
     }
 
     /// Low-level function to execute an ADS command.
@@ -302,234 +1007,712 @@ impl Client {
                        target: AmsAddr,
                        data_in: &[&[u8]],
                        data_out: &mut [&mut [u8]]) -> Result<usize> {
-        // Increase the invoke ID.  We could also generate a random u32, but
-        // this way the sequence of packets can be tracked.
-        self.invoke_id.set(self.invoke_id.get().wrapping_add(1));
-
-        // The data we send is the sum of all data_in buffers.
-        let data_in_len = data_in.iter().map(|v| v.len()).sum::<usize>();
-
-        // Create outgoing header.
-        let ads_data_len = AMS_HEADER_SIZE - TCP_HEADER_SIZE + data_in_len;
-        let header = AdsHeader {
-            ams_cmd:     0,  // send command
-            length:      U32::new(ads_data_len.try_into()?),
-            dest_netid:  target.netid(),
-            dest_port:   U16::new(target.port()),
-            src_netid:   self.source.netid(),
-            src_port:    U16::new(self.source.port()),
-            command:     U16::new(cmd as u16),
-            state_flags: U16::new(4),                   // state flags (4 = send command)
-            data_length: U32::new(data_in_len as u32),  // overflow checked above
-            error_code:  U32::new(0),
-            invoke_id:   U32::new(self.invoke_id.get()),
-        };
+        self.with_reconnect(|| self.communicate_once(cmd, target, data_in, data_out))
+    }
 
-        // Collect the outgoing data.  Note, allocating a Vec and calling
-        // `socket.write_all` only once is faster than writing in multiple
-        // steps, even with TCP_NODELAY.
-        let mut request = Vec::with_capacity(ads_data_len);
-        request.extend_from_slice(header.as_bytes());
-        for buf in data_in {
-            request.extend_from_slice(buf);
+    /// Low-level function to execute an ADS command given by its numeric ID.
+    ///
+    /// This can be used for commands not covered by [`Command`].  The caller is
+    /// responsible for the layout of the request data.  Only the error code in
+    /// the AMS header is checked; the complete reply data following the header
+    /// (including the result field that most commands have) is returned.
+    pub fn communicate_raw(&self, command_id: u16, target: AmsAddr,
+                           data_in: &[u8]) -> Result<Vec<u8>> {
+        self.communicate_with_flags(command_id, 0, target, data_in)
+    }
+
+    /// Like [`communicate_raw`](Self::communicate_raw), but with additional
+    /// AMS state flags for the request.
+    ///
+    /// [`STATE_FLAG_COMMAND`] is always added to the flags.  If
+    /// [`STATE_FLAG_NO_RETURN`] is included, the function returns an empty
+    /// vector directly after sending the request, without waiting for a
+    /// reply.
+    pub fn communicate_with_flags(&self, command_id: u16, state_flags: u16,
+                                  target: AmsAddr, data_in: &[u8]) -> Result<Vec<u8>> {
+        if state_flags & STATE_FLAG_NO_RETURN != 0 {
+            return self.with_reconnect(|| self.send_request(
+                &self.conn.read().expect("not poisoned"), command_id, state_flags, target,
+                &[data_in], None
+            ).map(|_| Vec::new()));
         }
-        // &T impls Write for T: Write, so no &mut self required.
-        (&self.socket).write_all(&request).ctx("sending request")?;
+        self.with_reconnect(|| self.transact(command_id, state_flags, target, &[data_in],
+                                             |request, invoke_id, reply| {
+            frame::check_reply(command_id, "raw command", request, invoke_id, reply)?;
+            Ok(reply[AMS_HEADER_SIZE..].to_vec())
+        }))
+    }
+
+    /// Run the request, and if the connection was lost and reconnecting is
+    /// enabled, reconnect and try once more.
+    fn with_reconnect<T>(&self, mut request: impl FnMut() -> Result<T>) -> Result<T> {
+        let generation = self.conn.read().expect("not poisoned").generation;
+        match request() {
+            Err(Error::Io(_, e)) if self.reconnect_enabled() && is_connection_lost(&e) => {
+                self.reconnect(generation)?;
+                request()
+            }
+            result => result,
+        }
+    }
+
+    fn communicate_once(&self,
+                        cmd: Command,
+                        target: AmsAddr,
+                        data_in: &[&[u8]],
+                        data_out: &mut [&mut [u8]]) -> Result<usize> {
+        self.transact(cmd as u16, 0, target, data_in, |request, invoke_id, reply| {
+            frame::decode_reply(cmd, request, invoke_id, reply, data_out)
+        })
+    }
+
+    /// Release a symbol handle on the current connection only.  A handle is
+    /// invalid after a reconnect anyway, and dropping the client should not
+    /// reconnect.
+    fn release_symbol_handle(&self, target: AmsAddr, handle: u32) -> Result<()> {
+        let handle = U32::<LE>::new(handle);
+        let header = IndexLength {
+            index_group:  U32::new(crate::index::RELEASE_SYMHANDLE),
+            index_offset: U32::new(0),
+            length:       U32::new(handle.as_bytes().len() as u32),
+        };
+        self.communicate_once(Command::Write, target,
+                              &[header.as_bytes(), handle.as_bytes()], &mut [])?;
+        Ok(())
+    }
+
+    /// Send a request and wait for the reply, which is then given to the
+    /// `decode` function together with the request and its invoke ID.
+    fn transact<T>(&self,
+                   command_id: u16,
+                   state_flags: u16,
+                   target: AmsAddr,
+                   data_in: &[&[u8]],
+                   decode: impl FnOnce(&[u8], u32, &[u8]) -> Result<T>) -> Result<T> {
+        let conn = self.conn.read().expect("not poisoned");
+        let (reply_send, reply_recv) = bounded(1);
+        let (invoke_id, request) = self.send_request(&conn, command_id, state_flags, target,
+                                                     data_in, Some(reply_send))?;
 
         // Get a reply from the reader thread, with timeout or not.
-        let reply = if let Some(tmo) = self.read_timeout {
-            self.reply_recv.recv_timeout(tmo).map_err(|_| io::ErrorKind::TimedOut.into())
-                                             .ctx("receiving reply (route set?)")?
+        let timeout = *self.read_timeout.lock().expect("not poisoned");
+        let reply = if let Some(tmo) = timeout {
+            reply_recv.recv_timeout(tmo).map_err(|_| io::ErrorKind::TimedOut.into())
+                                        .ctx("receiving reply (route set?)")
         } else {
-            self.reply_recv.recv().map_err(|_| io::ErrorKind::UnexpectedEof.into())
-                                  .ctx("receiving reply (route set?)")?
-        }?;
+            reply_recv.recv().map_err(|_| io::ErrorKind::UnexpectedEof.into())
+                             .ctx("receiving reply (route set?)")
+        };
+        let reply = match reply {
+            Ok(reply) => reply?,
+            Err(e) => {
+                // Remember to discard the reply if it arrives later.
+                conn.shared.time_out(invoke_id);
+                return Err(e);
+            }
+        };
 
         // Validate the incoming reply.  The reader thread already made sure that
         // it is consistent and addressed to us.
+        let result = decode(&request, invoke_id, &reply);
 
-        // The source netid/port must match what we sent.
-        if reply[14..22] != request[6..14] {
-            return Err(Error::Reply(cmd.action(), "unexpected source address", 0));
-        }
-        // Read the other fields we need.
-        assert!(reply.len() >= AMS_HEADER_SIZE);
-        let mut ptr = &reply[22..];
-        let ret_cmd = ptr.read_u16::<LE>().expect("size");
-        let state_flags = ptr.read_u16::<LE>().expect("size");
-        let data_len = ptr.read_u32::<LE>().expect("size");
-        let error_code = ptr.read_u32::<LE>().expect("size");
-        let invoke_id = ptr.read_u32::<LE>().expect("size");
-        let result = if reply.len() >= AMS_HEADER_SIZE + 4 {
-            ptr.read_u32::<LE>().expect("size")
-        } else {
-            0  // this must be because an error code is already set
-        };
+        // Send back the Vec buffer to the reader thread.
+        conn.buffer_pool.put(reply);
 
-        // Command must match.
-        if ret_cmd != cmd as u16 {
-            return Err(Error::Reply(cmd.action(), "unexpected command", ret_cmd.into()));
+        // Return either the error or the length of data.
+        result
+    }
+
+    /// Send a request with a new invoke ID, and return the invoke ID and the
+    /// request frame.  If given, `reply_send` is registered to receive the
+    /// reply.
+    fn send_request(&self,
+                    conn: &Connection,
+                    command_id: u16,
+                    state_flags: u16,
+                    target: AmsAddr,
+                    data_in: &[&[u8]],
+                    reply_send: Option<ReplySender>) -> Result<(u32, Vec<u8>)> {
+        // Increase the invoke ID.  We could also generate a random u32, but
+        // this way the sequence of packets can be tracked.
+        let mut invoke_id = self.invoke_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        if invoke_id == KEEPALIVE_INVOKE_ID {
+            invoke_id = self.invoke_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
         }
-        // State flags must be "4 | 1".
-        if state_flags != 5 {
-            return Err(Error::Reply(cmd.action(), "unexpected state flags", state_flags.into()));
+
+        let request = frame::encode_request(command_id, state_flags, target, conn.source,
+                                            invoke_id, data_in)?;
+        *conn.shared.last_target.lock().expect("not poisoned") = Some(target);
+
+        // Register for the reply before sending the request.  If the reader
+        // thread has quit, the sender is dropped and receiving fails.
+        if let Some(reply_send) = reply_send {
+            if let Some(pending) = &mut *conn.shared.pending.lock().expect("not poisoned") {
+                pending.insert(invoke_id, reply_send);
+            }
         }
-        // Invoke ID must match what we sent.
-        if invoke_id != self.invoke_id.get() {
-            return Err(Error::Reply(cmd.action(), "unexpected invoke ID", invoke_id));
+
+        #[cfg(feature = "tracing")]
+        frame::trace_frame("send", &request);
+        let mut writer = conn.shared.writer.lock().expect("not poisoned");
+        if let Err(e) = writer.write_all(&request).ctx("sending request") {
+            if let Some(pending) = &mut *conn.shared.pending.lock().expect("not poisoned") {
+                pending.remove(&invoke_id);
+            }
+            return Err(e);
         }
-        // Check error code in AMS header.
-        if error_code != 0 {
-            return ads_error(cmd.action(), error_code);
+        Ok((invoke_id, request))
+    }
+
+    /// Return true if the client is configured to reconnect at all.
+    fn reconnect_enabled(&self) -> bool {
+        self.reconnect.as_ref().map_or(false, |r| r.policy.max_attempts > 0)
+    }
+
+    /// Reestablish the connection and reissue notifications, unless another
+    /// thread has already replaced the connection of the given generation.
+    fn reconnect(&self, generation: u64) -> Result<()> {
+        let reconnect = self.reconnect.as_ref().expect("reconnect enabled");
+        let policy = reconnect.policy;
+
+        // Keep other requests from using the connection until we're done.
+        let mut old_conn = self.conn.write().expect("not poisoned");
+        if old_conn.generation != generation {
+            return Ok(());
         }
-        // Check result field in payload, only relevant if error_code == 0.
-        if result != 0 {
-            return ads_error(cmd.action(), result);
+
+        let mut delay = policy.backoff;
+        let mut attempt = 0;
+        let mut conn = loop {
+            std::thread::sleep(delay);
+            attempt += 1;
+            match Connection::connect(reconnect.addr, reconnect.timeouts, reconnect.source,
+                                      reconnect.notif_sink.clone(), self.buffer_pool.clone()) {
+                Ok(conn) => break conn,
+                Err(e) if attempt >= policy.max_attempts => return Err(e),
+                Err(_) => delay = delay.saturating_mul(2),
+            }
+        };
+        if let Some(interval) = *self.keepalive.lock().expect("not poisoned") {
+            conn.start_keepalive(interval);
+        }
+        conn.generation = generation + 1;
+        // This drops the old connection.
+        *old_conn = conn;
+        drop(old_conn);
+
+        // Add all notifications again.  The new handles can differ from the
+        // old ones.
+        let handles = std::mem::take(&mut *self.notif_handles.lock().expect("not poisoned"));
+        let mut reissued = Vec::with_capacity(handles.len());
+        self.adding_notifications(|| for ((addr, old_handle), request) in handles {
+            let mut handle = U32::<LE>::new(0);
+            if self.communicate_once(Command::AddNotification, addr, &[request.as_bytes()],
+                                     &mut [handle.as_bytes_mut()]).is_ok() {
+                self.notif_handles.lock().expect("not poisoned").insert((addr, handle.get()),
+                                                                        request);
+                reissued.push((addr, old_handle, handle.get()));
+            }
+        });
+
+        // Move the watchers to the new handles.  Those of notifications that
+        // couldn't be added again are dropped, which closes their channels.
+        {
+            let mut sink = reconnect.notif_sink.lock().expect("not poisoned");
+            let mut watchers = std::mem::take(&mut sink.watchers);
+            for &(addr, old_handle, new_handle) in &reissued {
+                if let Some(watcher) = watchers.remove(&(addr, old_handle)) {
+                    sink.watchers.insert((addr, new_handle), watcher);
+                }
+            }
         }
 
-        // If we don't want return data, we're done.
-        if data_out.is_empty() {
-            let _ = self.buf_send.send(reply);
-            return Ok(0);
+        if let Some(callback) = &mut *reconnect.callback.lock().expect("not poisoned") {
+            callback(&reissued);
         }
+        Ok(())
+    }
+}
+
+/// Return the TCP address and the AMS address of a device on the host with the
+/// given IP, as used by `Client::connect_ip_port`.
+pub(crate) fn ip_target(ip: Ipv4Addr, port: AmsPort) -> (SocketAddr, AmsAddr) {
+    ((ip, crate::PORT).into(), AmsAddr::new(ip.into(), port))
+}
+
+/// Parse an entry of the route list of an AMS router.
+fn parse_route_entry(data: &[u8]) -> Result<RouteInfo> {
+    let invalid = || Error::Reply("listing routes", "invalid route entry", data.len() as u32);
+    if data.len() < 44 {
+        return Err(invalid());
+    }
+    let host_len = LE::read_u32(&data[32..]) as usize;
+    let name_len = LE::read_u32(&data[36..]) as usize;
+    let host = data[44..].get(..host_len).ok_or_else(invalid)?;
+    let name = data[44 + host_len..].get(..name_len).ok_or_else(invalid)?;
+    // The strings are null-terminated.
+    let decode = |s: &[u8]| {
+        let len = s.iter().position(|&b| b == 0).unwrap_or(s.len());
+        String::from_utf8_lossy(&s[..len]).into_owned()
+    };
+    Ok(RouteInfo {
+        name: decode(name),
+        netid: AmsNetId::from_slice(&data[..6]).expect("size"),
+        address: decode(host),
+        flags: LE::read_u32(&data[8..]),
+    })
+}
+
+/// Return true if the I/O error means that the connection was lost.
+fn is_connection_lost(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+                         io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof)
+}
+
+impl Connection {
+    fn connect(addr: SocketAddr, timeouts: Timeouts, source: Source,
+               notif_sink: Arc<Mutex<NotifSink>>, buffer_pool: Arc<BufferPool>) -> Result<Self> {
+        let socket = if let Some(timeout) = timeouts.connect {
+            TcpStream::connect_timeout(&addr, timeout).ctx("connecting TCP socket with timeout")?
+        } else {
+            TcpStream::connect(&addr).ctx("connecting TCP socket")?
+        };
+
+        // Disable Nagle to ensure small requests are sent promptly; we're
+        // playing ping-pong with request reply, so no pipelining.
+        socket.set_nodelay(true).ctx("setting NODELAY")?;
+        socket.set_write_timeout(timeouts.write).ctx("setting write timeout")?;
+
+        Self::new(Box::new(socket), addr, source, notif_sink, buffer_pool)
+    }
+
+    fn new(mut socket: Box<dyn Transport>, addr: SocketAddr, source: Source,
+           notif_sink: Arc<Mutex<NotifSink>>, buffer_pool: Arc<BufferPool>) -> Result<Self> {
+        // Determine our source AMS address.  If it's not specified, try to use
+        // the socket's local IPv4 address, if it's IPv6 (not sure if Beckhoff
+        // devices support that) use `127.0.0.1` as the last resort.
+        //
+        // If source is Request, send an AMS port open message to the connected
+        // router to get our source address.  This is required when connecting
+        // via localhost, apparently.
+        let local_addr = socket.local_addr().ctx("getting local socket address")?;
+        let mut source_port_opened = false;
+        let source = match source {
+            Source::Addr(id) => id,
+            Source::Auto => auto_source(local_addr.ip(), next_auto_port()),
+            Source::AutoPort(port) => auto_source(local_addr.ip(), port),
+            Source::Request => {
+                let mut reply = [0; frame::REQUEST_PORT_REPLY_SIZE];
+                socket.write_all(&frame::REQUEST_PORT_MSG).ctx("requesting port from router")?;
+                socket.read_exact(&mut reply).ctx("requesting port from router")?;
+                source_port_opened = true;
+                frame::decode_port_reply(&reply)?
+            }
+        };
+
+        // Clone the socket for the reader thread and create our channels for
+        // bidirectional communication.
+        let socket_clone = socket.try_clone().ctx("cloning connection")?;
+        let closer = socket.try_clone().ctx("cloning connection")?;
+        let mut source_bytes = [0; 8];
+        source.write_to(&mut &mut source_bytes[..]).expect("size");
+        let shared = Arc::new(ConnShared {
+            last_received: Mutex::new(Instant::now()),
+            last_target: Mutex::new(None),
+            writer: Mutex::new(socket),
+            closer: Mutex::new(closer),
+            pending: Mutex::new(Some(BTreeMap::new())),
+            timed_out: Mutex::default(),
+            unknown_replies: AtomicU64::new(0),
+            closing: AtomicBool::new(false),
+            connected: AtomicBool::new(true),
+            keepalive_error: Mutex::new(None),
+        });
 
-        // Check returned length, it needs to fill at least the first data_out
-        // buffer.  This also ensures that we had a result field.
-        if (data_len as usize) < data_out[0].len() + 4 {
-            return Err(Error::Reply(cmd.action(), "got less data than expected", data_len));
+        // Start the reader thread.
+        let (raw_mode, counters) = {
+            let sink = notif_sink.lock().expect("not poisoned");
+            (sink.raw_mode.clone(), sink.counters.clone())
+        };
+        let reader = Reader {
+            socket: socket_clone,
+            source: source_bytes,
+            buffer_pool: buffer_pool.clone(),
+            notif_sink,
+            raw_mode,
+            counters,
+            shared: shared.clone(),
+        };
+        let reader = std::thread::spawn(|| reader.run());
+
+        Ok(Connection { source, buffer_pool, generation: 0, source_port_opened,
+                        peer_addr: addr, local_addr, shared, keepalive_stop: None,
+                        reader: Some(reader) })
+    }
+
+    /// Start a thread that sends keepalive requests, replacing a previous one.
+    fn start_keepalive(&mut self, interval: Duration) {
+        let (stop_send, stop_recv) = bounded(0);
+        let keepalive = Keepalive {
+            source: self.source,
+            interval,
+            stop: stop_recv,
+            shared: self.shared.clone(),
+        };
+        std::thread::spawn(|| keepalive.run());
+        self.keepalive_stop = Some(stop_send);
+    }
+}
+
+impl ConnShared {
+    /// Close the connection, which makes the reader thread quit.
+    fn shutdown(&self) {
+        let _ = self.closer.lock().expect("not poisoned").shutdown();
+    }
+
+    /// Unregister a request whose reply didn't arrive in time.
+    fn time_out(&self, invoke_id: u32) {
+        if let Some(pending) = &mut *self.pending.lock().expect("not poisoned") {
+            if pending.remove(&invoke_id).is_some() {
+                let mut timed_out = self.timed_out.lock().expect("not poisoned");
+                timed_out.insert(invoke_id);
+                if timed_out.len() > TIMED_OUT_WINDOW as usize {
+                    timed_out.retain(|&id| invoke_id.wrapping_sub(id) < TIMED_OUT_WINDOW);
+                }
+            }
         }
+    }
 
-        // The pure user data length, without the result field.
-        let data_len = data_len as usize - 4;
+    /// Determine where to send a reply with the given invoke ID.
+    ///
+    /// Replies to requests that have timed out, and to keepalive requests, are
+    /// discarded.  Replies with an unknown invoke ID are discarded as well, and
+    /// counted.
+    fn take_reply_sender(&self, invoke_id: u32) -> Option<ReplySender> {
+        if invoke_id == KEEPALIVE_INVOKE_ID ||
+            self.timed_out.lock().expect("not poisoned").remove(&invoke_id) {
+            return None;
+        }
+        let sender = self.pending.lock().expect("not poisoned").as_mut()?.remove(&invoke_id);
+        if sender.is_none() {
+            self.unknown_replies.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: "ads", invoke_id = invoke_id, "discarding unknown reply");
+        }
+        sender
+    }
 
-        // Distribute the data into the user output buffers, up to the returned
-        // data length.
-        let mut offset = AMS_HEADER_SIZE + 4;
-        let mut rest_len = data_len;
-        for buf in data_out {
-            let n = buf.len().min(rest_len);
-            buf[..n].copy_from_slice(&reply[offset..][..n]);
-            offset += n;
-            rest_len -= n;
-            if rest_len == 0 {
-                break;
+    /// Give an error to the oldest pending request.
+    fn fail_oldest(&self, err: Error) {
+        if let Some(pending) = &mut *self.pending.lock().expect("not poisoned") {
+            if let Some(key) = pending.keys().next().copied() {
+                let _ = pending.remove(&key).expect("exists").send(Err(err));
             }
         }
+    }
+}
 
-        // Send back the Vec buffer to the reader thread.
-        let _ = self.buf_send.send(reply);
+// Implementation detail: thread that checks if the connection is alive.
+struct Keepalive {
+    source: AmsAddr,
+    interval: Duration,
+    stop: Receiver<()>,
+    shared: Arc<ConnShared>,
+}
 
-        // Return either the error or the length of data.
-        Ok(data_len)
+impl Keepalive {
+    fn run(self) {
+        let mut ping_sent = None;
+        // The channel is disconnected when the connection is dropped.
+        while let Err(RecvTimeoutError::Timeout) = self.stop.recv_timeout(self.interval) {
+            let last_received = *self.shared.last_received.lock().expect("not poisoned");
+            if let Some(ping_sent) = ping_sent {
+                if last_received < ping_sent {
+                    // No reply: consider the connection dead.
+                    *self.shared.keepalive_error.lock().expect("not poisoned") = Some(Error::Io(
+                        "checking connection",
+                        io::Error::new(io::ErrorKind::TimedOut, "no reply to keepalive")));
+                    self.shared.connected.store(false, Ordering::SeqCst);
+                    self.shared.shutdown();
+                    return;
+                }
+            }
+            ping_sent = None;
+            if last_received.elapsed() < self.interval {
+                continue;
+            }
+            let target = match *self.shared.last_target.lock().expect("not poisoned") {
+                Some(target) => target,
+                None => continue,
+            };
+            let request = frame::encode_request(Command::ReadState as u16, 0, target,
+                                                self.source, KEEPALIVE_INVOKE_ID, &[])
+                .expect("no data");
+            #[cfg(feature = "tracing")]
+            frame::trace_frame("send", &request);
+            // The reply can arrive before `write_all` returns.
+            ping_sent = Some(Instant::now());
+            // If a request is being sent, don't wait for it: should it be stuck,
+            // the missing reply to the keepalive closes the connection.
+            let result = match self.shared.writer.try_lock() {
+                Ok(mut writer) => writer.write_all(&request),
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = result {
+                *self.shared.keepalive_error.lock().expect("not poisoned") =
+                    Some(Error::Io("sending keepalive request", e));
+                self.shared.connected.store(false, Ordering::SeqCst);
+                self.shared.shutdown();
+                return;
+            }
+        }
     }
 }
 
 // Implementation detail: reader thread that takes replies and notifications
 // and distributes them accordingly.
 struct Reader {
-    socket: TcpStream,
+    socket: Box<dyn Transport>,
     source: [u8; 8],
-    buf_recv: Receiver<Vec<u8>>,
-    reply_send: Sender<Result<Vec<u8>>>,
-    notif_send: Sender<notif::Notification>,
+    buffer_pool: Arc<BufferPool>,
+    notif_sink: Arc<Mutex<NotifSink>>,
+    raw_mode: Arc<AtomicBool>,
+    counters: Arc<NotifCounters>,
+    shared: Arc<ConnShared>,
 }
 
 impl Reader {
     fn run(mut self) {
-        self.run_inner();
-        // We can't do much here.  But try to shut down the socket so that
+        if let Some(err) = self.run_inner() {
+            let last_error = self.notif_sink.lock().expect("not poisoned").last_error.clone();
+            *last_error.lock().expect("not poisoned") = Some(err);
+        }
+        self.shared.connected.store(false, Ordering::SeqCst);
+        // Fail all outstanding requests (their channels are disconnected), and
+        // don't accept new ones.
+        self.shared.pending.lock().expect("not poisoned").take();
+        // We can't do much else here.  But try to shut down the socket so that
         // the main client can't be used anymore either.
-        let _ = self.socket.shutdown(Shutdown::Both);
+        let _ = self.socket.shutdown();
     }
 
-    fn run_inner(&mut self) {
+    /// Read and dispatch packets until the connection fails or is closed.
+    ///
+    /// Returns the error that made the connection unusable, or `None` if the
+    /// client closed it.
+    fn run_inner(&mut self) -> Option<Error> {
         loop {
-            // Get a buffer from the free-channel or create a new one.
-            let mut buf = self.buf_recv.try_recv()
-                                       .unwrap_or_else(|_| Vec::with_capacity(DEFAULT_BUFFER_SIZE));
+            // Get a buffer from the pool or create a new one.
+            let mut buf = self.buffer_pool.take();
 
             // Read a header from the socket.
             buf.resize(TCP_HEADER_SIZE, 0);
-            if self.socket.read_exact(&mut buf).ctx("reading AMS packet header").is_err() {
+            if let Err(e) = self.socket.read_exact(&mut buf) {
                 // Not sending an error back; we don't know if something was
                 // requested or the socket was just closed from either side.
-                return;
+                if self.shared.closing.load(Ordering::SeqCst) {
+                    return None;
+                }
+                let keepalive_error = self.shared.keepalive_error.lock().expect("not poisoned").take();
+                if keepalive_error.is_some() {
+                    return keepalive_error;
+                }
+                let e = if e.kind() == io::ErrorKind::UnexpectedEof {
+                    io::Error::new(e.kind(), "connection closed by server")
+                } else {
+                    e
+                };
+                return Some(Error::Io("reading AMS packet header", e));
             }
 
             // Read the rest of the packet.
-            let packet_length = LE::read_u32(&buf[2..6]) as usize;
+            let packet_length = match frame::packet_length(&buf) {
+                Ok(length) => length,
+                Err(e) => {
+                    self.shared.fail_oldest(e.duplicate());
+                    return Some(e);
+                }
+            };
             buf.resize(TCP_HEADER_SIZE + packet_length, 0);
             if let Err(e) = self.socket.read_exact(&mut buf[6..])
                                        .ctx("reading rest of packet") {
-                let _ = self.reply_send.send(Err(e));
-                return;
+                self.shared.fail_oldest(e.duplicate());
+                return Some(e);
             }
+            *self.shared.last_received.lock().expect("not poisoned") = Instant::now();
+            #[cfg(feature = "tracing")]
+            frame::trace_frame("recv", &buf);
 
-            // Is it something other than an ADS command packet?
-            let ams_cmd = LE::read_u16(&buf);
-            if ams_cmd != 0 {
-                // if it's a known packet type, continue
-                if matches!(ams_cmd, 1 | 4096 | 4097 | 4098) {
+            // In raw mode, ADS frames are passed on without further checks.
+            if self.raw_mode.load(Ordering::Relaxed) && self.send_raw(&buf) {
+                self.buffer_pool.put(buf);
+                continue;
+            }
+
+            match frame::classify_packet(&buf, &self.source) {
+                Err(e) => {
+                    self.shared.fail_oldest(e.duplicate());
+                    return Some(e);
+                }
+                Ok(frame::Packet::Ignore) => {
+                    self.buffer_pool.put(buf);
                     continue;
                 }
-                let _ = self.reply_send.send(Err(Error::Reply(
-                    "reading packet", "invalid packet or unknown AMS command", ams_cmd as _)));
-                return;
+                Ok(frame::Packet::Reply) => {
+                    // Send it back to the requesting thread, it will handle
+                    // further validation.
+                    let invoke_id = LE::read_u32(&buf[34..38]);
+                    if let Some(reply_send) = self.shared.take_reply_sender(invoke_id) {
+                        let _ = reply_send.send(Ok(buf));
+                    }
+                }
+                Ok(frame::Packet::Notification) => {
+                    let source = AmsAddr::read_from(&mut &buf[14..22]).expect("size");
+                    // Send the notification to whoever wants to receive it.
+                    match notif::Notification::new_pooled(buf, self.buffer_pool.clone()) {
+                        Ok(notif) => {
+                            let (watched, handles) = {
+                                let mut sink = self.notif_sink.lock().expect("not poisoned");
+                                (sink.watched_samples(source, &notif), sink.handles.clone())
+                            };
+                            for (watcher, sample) in watched {
+                                watcher(&sample);
+                            }
+                            self.count_unmatched(source, &notif, &handles);
+                            self.send_notification(notif);
+                        }
+                        Err(_) => self.count_lost_notification(),
+                    }
+                }
             }
+        }
+    }
 
-            // If the header length fields aren't self-consistent, abort the connection.
-            let rest_length = LE::read_u32(&buf[26..30]) as usize;
-            if rest_length != packet_length + TCP_HEADER_SIZE - AMS_HEADER_SIZE {
-                let _ = self.reply_send.send(Err(Error::Reply("reading packet",
-                                                              "inconsistent packet", 0)));
-                return;
+    /// If in raw mode, send an ADS frame to the raw channel and return true.
+    /// Frames with an inconsistent AMS header are dropped.
+    fn send_raw(&self, buf: &[u8]) -> bool {
+        let sink = self.notif_sink.lock().expect("not poisoned");
+        match &sink.raw {
+            Some(send) if LE::read_u16(buf) == 0 => {
+                if let Ok(frame) = AmsFrame::parse(buf) {
+                    let _ = send.send(frame);
+                }
+                true
             }
+            _ => false,
+        }
+    }
 
-            // Check that the packet is meant for us.
-            if buf[6..14] != self.source {
-                continue;
+    fn send_notification(&self, notif: notif::Notification) {
+        // Don't hold the lock while possibly blocking.
+        let (send, recv, policy) = {
+            let sink = self.notif_sink.lock().expect("not poisoned");
+            (sink.send.clone(), sink.recv.clone(), sink.policy)
+        };
+        let discarded = match policy {
+            OverflowPolicy::Block => {
+                // The sink holds a receiver, so this can't fail.
+                let _ = send.send(notif);
+                false
             }
-
-            // If it looks like a reply, send it back to the requesting thread,
-            // it will handle further validation.
-            if LE::read_u16(&buf[22..24]) != Command::Notification as u16 {
-                if self.reply_send.send(Ok(buf)).is_err() {
-                    // Client must have been shut down.
-                    return;
-                }
-                continue;
+            OverflowPolicy::DropNewest => {
+                matches!(send.try_send(notif), Err(TrySendError::Full(_)))
             }
-
-            // Validate notification message fields.
-            let state_flags = LE::read_u16(&buf[24..26]);
-            let error_code = LE::read_u32(&buf[30..34]);
-            let length = LE::read_u32(&buf[38..42]) as usize;
-            if state_flags != 4 || error_code != 0 || length != rest_length - 4 || length < 4 {
-                continue;
+            OverflowPolicy::DropOldest => {
+                let mut notif = notif;
+                let mut discarded = false;
+                while let Err(TrySendError::Full(rejected)) = send.try_send(notif) {
+                    discarded |= recv.try_recv().is_ok();
+                    notif = rejected;
+                }
+                discarded
             }
+        };
+        if discarded {
+            self.counters.overflow.fetch_add(1, Ordering::Relaxed);
+            self.count_lost_notification();
+        }
+        // Only with DropNewest, the discarded notification is the new one.
+        if !(discarded && policy == OverflowPolicy::DropNewest) {
+            self.counters.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
 
-            // Send the notification to whoever wants to receive it.
-            if let Ok(notif) = notif::Notification::new(buf) {
-                self.notif_send.send(notif).expect("never disconnects");
+    /// Count the samples for handles that the client doesn't know (anymore).
+    ///
+    /// While notifications are being added, such samples could be for the new
+    /// handles; they are counted later, see `Client::adding_notifications`.
+    fn count_unmatched(&self, source: AmsAddr, notif: &notif::Notification,
+                       handles: &Mutex<BTreeMap<(AmsAddr, notif::Handle), AddNotif>>) {
+        let mut deferred = self.counters.deferred.lock().expect("not poisoned");
+        let handles = handles.lock().expect("not poisoned");
+        let unknown = notif.samples().map(|s| (source, s.handle))
+                                     .filter(|key| !handles.contains_key(key));
+        if self.counters.adds_pending.load(Ordering::SeqCst) > 0 {
+            for key in unknown {
+                *deferred.entry(key).or_default() += 1;
             }
+        } else {
+            self.counters.unmatched.fetch_add(unknown.count() as u64, Ordering::Relaxed);
         }
     }
+
+    fn count_lost_notification(&self) {
+        self.notif_sink.lock().expect("not poisoned").lost.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 /// A `Client` wrapper that talks to a specific ADS device.
+///
+/// Devices are cheap to copy.  Since the `Client` is `Sync`, copies of a
+/// device can be used from multiple threads at the same time; their requests
+/// share the client's connection.
 #[derive(Clone, Copy)]
 pub struct Device<'c> {
     /// The underlying `Client`.
     pub client: &'c Client,
     addr: AmsAddr,
+    retry: Option<RetryPolicy>,
 }
 
 impl<'c> Device<'c> {
+    /// Return the address of the target device.
+    pub fn addr(&self) -> AmsAddr {
+        self.addr
+    }
+
+    /// Return a device that retries requests failing with transient errors,
+    /// according to `policy`.
+    ///
+    /// Errors with codes not in `policy.retry_on` are returned immediately.
+    /// Note that a retried write might be executed twice.
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        Device { retry: Some(policy), ..self }
+    }
+
+    /// Execute a command for this device, retrying it if configured.
+    fn communicate(&self, cmd: Command, data_in: &[&[u8]],
+                   data_out: &mut [&mut [u8]]) -> Result<usize> {
+        let mut attempt = 1;
+        let mut delay = self.retry.map_or(Duration::ZERO, |policy| policy.delay);
+        loop {
+            match self.client.communicate(cmd, self.addr, data_in, data_out) {
+                Err(Error::Ads(_, _, code)) if self.retry.map_or(false, |policy| {
+                    attempt < policy.max_attempts && policy.retry_on.contains(&code)
+                }) => {
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
     /// Read the device's name + version.
     pub fn get_info(&self) -> Result<DeviceInfo> {
         let mut data = DeviceInfoRaw::new_zeroed();
-        self.client.communicate(Command::DevInfo, self.addr,
-                                &[], &mut [data.as_bytes_mut()])?;
+        self.communicate(Command::DevInfo, &[], &mut [data.as_bytes_mut()])?;
 
         // Decode the name string, which is null-terminated.  Technically it's
         // Windows-1252, but in practice no non-ASCII occurs.
@@ -553,10 +1736,12 @@ impl<'c> Device<'c> {
         };
         let mut read_len = U32::<LE>::new(0);
 
-        self.client.communicate(Command::Read, self.addr,
-                                &[header.as_bytes()], &mut [read_len.as_bytes_mut(), data])?;
+        self.communicate(Command::Read,
+                         &[header.as_bytes()], &mut [read_len.as_bytes_mut(), data])
+            .map_err(|e| e.with_request(index_group, index_offset, data.len()))?;
 
-        Ok(read_len.get() as usize)
+        // Never report more data than fits into the buffer.
+        Ok((read_len.get() as usize).min(data.len()))
     }
 
     /// Read some data at a given index group/offset, ensuring that the returned data has
@@ -569,6 +1754,18 @@ impl<'c> Device<'c> {
         Ok(())
     }
 
+    /// Read up to `max_len` bytes at a given index group/offset into a new
+    /// vector, which is truncated to the length of data actually returned.
+    ///
+    /// This is useful when the exact size of the data is not known in advance.
+    /// Use [`Device::read`] to read into a reusable buffer instead.
+    pub fn read_vec(&self, index_group: u32, index_offset: u32, max_len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; max_len];
+        let len = self.read(index_group, index_offset, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
     /// Read data of given type.
     ///
     /// Any type that supports `zerocopy::FromBytes` can be read.  You can also
@@ -576,7 +1773,9 @@ impl<'c> Device<'c> {
     /// directly from the symbol.
     ///
     /// Note: to be independent of the host's byte order, use the integer types
-    /// defined in `zerocopy::byteorder`.
+    /// defined in `zerocopy::byteorder`.  A PLC `BOOL` can be read as `u8`.
+    ///
+    /// Returns an error if less data than the size of the type is returned.
     pub fn read_value<T: Default + AsBytes + FromBytes>(&self, index_group: u32,
                                                         index_offset: u32) -> Result<T> {
         let mut buf = T::default();
@@ -586,6 +1785,10 @@ impl<'c> Device<'c> {
 
     /// Read multiple index groups/offsets with one ADS request (a "sum-up" request).
     ///
+    /// Large batches are split into several sum-up requests, see
+    /// [`MAX_SUM_REQUESTS`].  The batch is then not atomic: if one of the
+    /// sum-up requests fails, the ones before it have already been executed.
+    ///
     /// The returned data can be shorter than the buffer in each case, the `length`
     /// member of the `ReadRequest` is set to the returned length.
     ///
@@ -611,6 +1814,10 @@ impl<'c> Device<'c> {
     /// let res_2 = req_2.data()?;
     /// ```
     pub fn read_multi(&self, requests: &mut [ReadRequest]) -> Result<()> {
+        split_sum_request(requests, ReadRequest::sizes, |chunk| self.read_multi_once(chunk))
+    }
+
+    fn read_multi_once(&self, requests: &mut [ReadRequest]) -> Result<()> {
         let nreq = requests.len();
         let rlen = requests.iter().map(|r| size_of::<ResultLength>() + r.rbuf.len()).sum::<usize>();
         let wlen = size_of::<IndexLength>() * nreq;
@@ -632,38 +1839,234 @@ impl<'c> Device<'c> {
             r_buffers[1 + i] = req.res.as_bytes_mut();
             r_buffers[1 + nreq + i] = req.rbuf;
         }
-        self.client.communicate(Command::ReadWrite, self.addr, &w_buffers, &mut r_buffers)?;
-        Ok(())
+        self.communicate(Command::ReadWrite, &w_buffers, &mut r_buffers)?;
+        Ok(())
+    }
+
+    /// Write some data to a given index group/offset.
+    pub fn write(&self, index_group: u32, index_offset: u32, data: &[u8]) -> Result<()> {
+        let header = IndexLength {
+            index_group:  U32::new(index_group),
+            index_offset: U32::new(index_offset),
+            length:       U32::new(data.len().try_into()?),
+        };
+        self.communicate(Command::Write, &[header.as_bytes(), data], &mut [])
+            .map_err(|e| e.with_request(index_group, index_offset, data.len()))?;
+        Ok(())
+    }
+
+    /// Write some data to a given index group/offset, without waiting for a
+    /// reply.
+    ///
+    /// The request is sent with the "no return" AMS state flag, so that the
+    /// target doesn't send a reply.  This is useful for high-rate writes that
+    /// don't need confirmation, but errors returned by the target (such as an
+    /// invalid index) can't be detected in this mode.
+    pub fn write_no_reply(&self, index_group: u32, index_offset: u32, data: &[u8]) -> Result<()> {
+        let header = IndexLength {
+            index_group:  U32::new(index_group),
+            index_offset: U32::new(index_offset),
+            length:       U32::new(data.len().try_into()?),
+        };
+        let mut request = header.as_bytes().to_vec();
+        request.extend_from_slice(data);
+        self.client.communicate_with_flags(Command::Write as u16, STATE_FLAG_NO_RETURN,
+                                           self.addr, &request)?;
+        Ok(())
+    }
+
+    /// Write data of given type.
+    ///
+    /// See `read_value` for details.
+    pub fn write_value<T: AsBytes>(&self, index_group: u32, index_offset: u32,
+                                   value: &T) -> Result<()> {
+        self.write(index_group, index_offset, value.as_bytes())
+    }
+
+    /// Read a structure that is decoded field by field.
+    ///
+    /// See the [`structs`](crate::structs) module for details.  Returns an
+    /// error if less data than the size of the structure is returned.
+    pub fn read_struct<T: FromAds>(&self, index_group: u32, index_offset: u32) -> Result<T> {
+        let mut buf = vec![0; T::ADS_SIZE];
+        self.read_exact(index_group, index_offset, &mut buf)?;
+        T::from_ads(&buf)
+    }
+
+    /// Write a structure that is encoded field by field.
+    ///
+    /// See the [`structs`](crate::structs) module for details.
+    pub fn write_struct<T: ToAds>(&self, index_group: u32, index_offset: u32,
+                                  value: &T) -> Result<()> {
+        let mut buf = vec![0; T::ADS_SIZE];
+        value.to_ads(&mut buf)?;
+        self.write(index_group, index_offset, &buf)
+    }
+
+    /// Read an array of `count` elements of given type.
+    ///
+    /// The same considerations as for `read_value` apply to the element type.
+    ///
+    /// Returns an error if less data than the size of the array is returned.
+    pub fn read_array<T: FromBytes>(&self, index_group: u32, index_offset: u32,
+                                    count: usize) -> Result<Vec<T>> {
+        let elsize = size_of::<T>();
+        let mut buf = vec![0; count.checked_mul(elsize).ok_or_else(
+            || Error::Io("reading array", io::ErrorKind::InvalidInput.into()))?];
+        self.read_exact(index_group, index_offset, &mut buf)?;
+        if elsize == 0 {
+            return Ok(std::iter::repeat_with(T::new_zeroed).take(count).collect());
+        }
+        Ok(buf.chunks_exact(elsize).map(|chunk| T::read_from(chunk).expect("size")).collect())
+    }
+
+    /// Read `count` bits packed into bytes, such as a `WORD` used as flags.
+    ///
+    /// This reads `count / 8` bytes, rounded up, and returns the bits of each
+    /// byte least significant bit first.  For a little-endian `WORD`, element
+    /// `i` of the result is therefore bit `i` of the value.
+    pub fn read_bits(&self, index_group: u32, index_offset: u32, count: usize) -> Result<Vec<bool>> {
+        let mut data = vec![0; (count + 7) / 8];
+        self.read_exact(index_group, index_offset, &mut data)?;
+        Ok(notif::unpack_bits(&data, count))
+    }
+
+    /// Write an array of elements of given type.
+    pub fn write_array<T: AsBytes>(&self, index_group: u32, index_offset: u32,
+                                   values: &[T]) -> Result<()> {
+        self.write(index_group, index_offset, values.as_bytes())
+    }
+
+    /// Read a PLC `STRING(capacity)` from the given index group/offset.
+    ///
+    /// This reads `capacity + 1` bytes and cuts the string at the first null
+    /// byte, or after `capacity` bytes.  Bytes that are not valid UTF-8 are
+    /// replaced.
+    pub fn read_string(&self, index_group: u32, index_offset: u32,
+                       capacity: usize) -> Result<String> {
+        let mut buf = vec![0; capacity + 1];
+        let len = self.read(index_group, index_offset, &mut buf)?.min(capacity);
+        let len = buf[..len].iter().position(|&b| b == 0).unwrap_or(len);
+        Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    /// Write a PLC `STRING(capacity)` to the given index group/offset.
+    ///
+    /// The string is padded with null bytes to `capacity + 1` bytes.  If it
+    /// is longer than `capacity` bytes, an error is returned.
+    pub fn write_string(&self, index_group: u32, index_offset: u32,
+                        capacity: usize, value: &str) -> Result<()> {
+        if value.len() > capacity {
+            return Err(Error::Io("writing string", io::Error::new(
+                io::ErrorKind::InvalidInput, "string exceeds capacity")));
+        }
+        let mut buf = vec![0; capacity + 1];
+        buf[..value.len()].copy_from_slice(value.as_bytes());
+        self.write(index_group, index_offset, &buf)
     }
 
-    /// Write some data to a given index group/offset.
-    pub fn write(&self, index_group: u32, index_offset: u32, data: &[u8]) -> Result<()> {
-        let header = IndexLength {
-            index_group:  U32::new(index_group),
-            index_offset: U32::new(index_offset),
-            length:       U32::new(data.len().try_into()?),
-        };
-        self.client.communicate(Command::Write, self.addr,
-                                &[header.as_bytes(), data], &mut [])?;
-        Ok(())
+    /// Read a PLC `WSTRING(capacity)` from the given index group/offset.
+    ///
+    /// This reads `capacity + 1` UTF-16 code units and cuts the string at the
+    /// first null, or after `capacity` code units.  Invalid code units are
+    /// replaced.
+    pub fn read_wstring(&self, index_group: u32, index_offset: u32,
+                        capacity: usize) -> Result<String> {
+        let mut buf = vec![0; 2 * (capacity + 1)];
+        let len = self.read(index_group, index_offset, &mut buf)?;
+        let units = buf[..len].chunks_exact(2).take(capacity).map(LE::read_u16)
+                                              .take_while(|&u| u != 0);
+        Ok(std::char::decode_utf16(units)
+           .map(|ch| ch.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+           .collect())
     }
 
-    /// Write data of given type.
+    /// Write a PLC `WSTRING(capacity)` to the given index group/offset.
     ///
-    /// See `read_value` for details.
-    pub fn write_value<T: AsBytes>(&self, index_group: u32, index_offset: u32,
-                                   value: &T) -> Result<()> {
-        self.write(index_group, index_offset, value.as_bytes())
+    /// The string is encoded as UTF-16 and padded with nulls to `capacity + 1`
+    /// code units.  If it is longer than `capacity` code units, an error is
+    /// returned.
+    pub fn write_wstring(&self, index_group: u32, index_offset: u32,
+                         capacity: usize, value: &str) -> Result<()> {
+        let mut buf = vec![0; 2 * (capacity + 1)];
+        for (i, unit) in value.encode_utf16().enumerate() {
+            if i >= capacity {
+                return Err(Error::Io("writing string", io::Error::new(
+                    io::ErrorKind::InvalidInput, "string exceeds capacity")));
+            }
+            LE::write_u16(&mut buf[2*i..], unit);
+        }
+        self.write(index_group, index_offset, &buf)
+    }
+
+    /// Read a PLC `TIME`, which is a 32-bit number of milliseconds.
+    pub fn read_time(&self, index_group: u32, index_offset: u32) -> Result<Duration> {
+        let millis = self.read_value::<U32<LE>>(index_group, index_offset)?;
+        Ok(Duration::from_millis(millis.get().into()))
+    }
+
+    /// Write a PLC `TIME`, which is a 32-bit number of milliseconds.
+    ///
+    /// Fractions of milliseconds are cut off.  Returns an error if the
+    /// duration exceeds the range of `TIME`, about 49 days.
+    pub fn write_time(&self, index_group: u32, index_offset: u32, value: Duration) -> Result<()> {
+        let millis: u32 = value.as_millis().try_into()?;
+        self.write_value(index_group, index_offset, &U32::<LE>::new(millis))
+    }
+
+    /// Read a PLC `LTIME`, which is a 64-bit number of nanoseconds.
+    pub fn read_ltime(&self, index_group: u32, index_offset: u32) -> Result<Duration> {
+        let nanos = self.read_value::<U64<LE>>(index_group, index_offset)?;
+        Ok(Duration::from_nanos(nanos.get()))
+    }
+
+    /// Write a PLC `LTIME`, which is a 64-bit number of nanoseconds.
+    ///
+    /// Returns an error if the duration exceeds the range of `LTIME`, about
+    /// 584 years.
+    pub fn write_ltime(&self, index_group: u32, index_offset: u32, value: Duration) -> Result<()> {
+        let nanos: u64 = value.as_nanos().try_into()?;
+        self.write_value(index_group, index_offset, &U64::<LE>::new(nanos))
+    }
+
+    /// Read a PLC `DATE` or `DT` (`DATE_AND_TIME`), which are a 32-bit number
+    /// of seconds since 1970-01-01 00:00 (for `DATE`, the time is always
+    /// midnight).
+    pub fn read_date(&self, index_group: u32, index_offset: u32) -> Result<SystemTime> {
+        let secs = self.read_value::<U32<LE>>(index_group, index_offset)?;
+        Ok(UNIX_EPOCH + Duration::from_secs(secs.get().into()))
+    }
+
+    /// Write a PLC `DATE` or `DT` (`DATE_AND_TIME`), see
+    /// [`read_date`](Self::read_date).
+    ///
+    /// Fractions of seconds are cut off; for a `DATE`, the PLC also ignores
+    /// the time of day.  Returns an error if the time is before 1970 or
+    /// after 2106, which `DT` can't represent.
+    pub fn write_date(&self, index_group: u32, index_offset: u32, value: SystemTime) -> Result<()> {
+        let since_epoch = value.duration_since(UNIX_EPOCH).map_err(
+            |_| Error::Io("writing date", io::Error::new(
+                io::ErrorKind::InvalidInput, "time is before 1970")))?;
+        let secs: u32 = since_epoch.as_secs().try_into()?;
+        self.write_value(index_group, index_offset, &U32::<LE>::new(secs))
     }
 
     /// Write multiple index groups/offsets with one ADS request (a "sum-up" request).
     ///
+    /// Large batches are split into several sum-up requests, see
+    /// [`MAX_SUM_REQUESTS`].  The batch is then not atomic: if one of the
+    /// sum-up requests fails, the ones before it have already been executed.
+    ///
     /// This function only returns Err on errors that cause the whole sum-up
     /// request to fail (e.g. if the device doesn't support such requests).  If
     /// the request as a whole succeeds, each single write can have returned its
     /// own error.  The [`WriteRequest::ensure`] method will return the error for
-    /// each write.
+    /// each write; a write that returned an error was not applied.
     pub fn write_multi(&self, requests: &mut [WriteRequest]) -> Result<()> {
+        split_sum_request(requests, WriteRequest::sizes, |chunk| self.write_multi_once(chunk))
+    }
+
+    fn write_multi_once(&self, requests: &mut [WriteRequest]) -> Result<()> {
         let nreq = requests.len();
         let rlen = size_of::<u32>() * nreq;
         let wlen = requests.iter().map(|r| size_of::<IndexLength>() + r.wbuf.len()).sum::<usize>();
@@ -682,13 +2085,16 @@ impl<'c> Device<'c> {
             w_buffers[1 + nreq + i] = req.wbuf;
             r_buffers.push(req.res.as_bytes_mut());
         }
-        self.client.communicate(Command::ReadWrite, self.addr, &w_buffers, &mut r_buffers)?;
+        self.communicate(Command::ReadWrite, &w_buffers, &mut r_buffers)?;
         Ok(())
     }
 
     /// Write some data to a given index group/offset and then read back some
     /// reply from there.  This is not the same as a write() followed by read();
     /// it is used as a kind of RPC call.
+    ///
+    /// Returned data can be shorter than the read buffer, the length is the
+    /// return value.
     pub fn write_read(&self, index_group: u32, index_offset: u32, write_data: &[u8],
                       read_data: &mut [u8]) -> Result<usize> {
         let header = IndexLengthRW {
@@ -698,10 +2104,11 @@ impl<'c> Device<'c> {
             write_length: U32::new(write_data.len().try_into()?),
         };
         let mut read_len = U32::<LE>::new(0);
-        self.client.communicate(Command::ReadWrite, self.addr,
-                                &[header.as_bytes(), write_data],
-                                &mut [read_len.as_bytes_mut(), read_data])?;
-        Ok(read_len.get() as usize)
+        self.communicate(Command::ReadWrite, &[header.as_bytes(), write_data],
+                         &mut [read_len.as_bytes_mut(), read_data])
+            .map_err(|e| e.with_request(index_group, index_offset, write_data.len()))?;
+        // Never report more data than fits into the buffer.
+        Ok((read_len.get() as usize).min(read_data.len()))
     }
 
     /// Like `write_read`, but ensure the returned data length matches the output buffer.
@@ -714,8 +2121,22 @@ impl<'c> Device<'c> {
         Ok(())
     }
 
+    /// Like `write_read`, but read up to `max_len` bytes into a new vector,
+    /// which is truncated to the length of data actually returned.
+    pub fn write_read_vec(&self, index_group: u32, index_offset: u32, write_data: &[u8],
+                          max_len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; max_len];
+        let len = self.write_read(index_group, index_offset, write_data, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
     /// Write multiple index groups/offsets with one ADS request (a "sum-up" request).
     ///
+    /// Large batches are split into several sum-up requests, see
+    /// [`MAX_SUM_REQUESTS`].  The batch is then not atomic: if one of the
+    /// sum-up requests fails, the ones before it have already been executed.
+    ///
     /// This function only returns Err on errors that cause the whole sum-up
     /// request to fail (e.g. if the device doesn't support such requests).  If
     /// the request as a whole succeeds, each single write/read can have
@@ -723,6 +2144,11 @@ impl<'c> Device<'c> {
     /// return either the properly truncated returned data or the error for each
     /// write/read.
     pub fn write_read_multi(&self, requests: &mut [WriteReadRequest]) -> Result<()> {
+        split_sum_request(requests, WriteReadRequest::sizes,
+                          |chunk| self.write_read_multi_once(chunk))
+    }
+
+    fn write_read_multi_once(&self, requests: &mut [WriteReadRequest]) -> Result<()> {
         let nreq = requests.len();
         let rlen = requests.iter().map(|r| size_of::<ResultLength>() + r.rbuf.len()).sum::<usize>();
         let wlen = requests.iter().map(|r| size_of::<IndexLengthRW>() + r.wbuf.len()).sum::<usize>();
@@ -743,35 +2169,260 @@ impl<'c> Device<'c> {
             r_buffers[1 + i] = req.res.as_bytes_mut();
             r_buffers[1 + nreq + i] = req.rbuf;
         }
-        self.client.communicate(Command::ReadWrite, self.addr, &w_buffers, &mut r_buffers)?;
+        self.communicate(Command::ReadWrite, &w_buffers, &mut r_buffers)?;
         // unfortunately SUMUP_READWRITE returns only the actual read bytes for each
         // request, so if there are short reads the buffers got filled wrongly
         fixup_write_read_return_buffers(requests);
         Ok(())
     }
 
+    /// Write the values of multiple symbols, given by name.
+    ///
+    /// This uses one sum-up request each to get handles for all symbols, to
+    /// write the values, and to release the handles again, instead of three
+    /// requests per symbol.
+    ///
+    /// This function only returns Err on errors that cause a whole sum-up
+    /// request to fail.  Otherwise, the result of each single write is
+    /// returned; symbols whose name can't be resolved return an error, while
+    /// the other values are still written.
+    pub fn write_named<D: AsRef<[u8]>>(&self, values: &[(&str, D)]) -> Result<Vec<Result<()>>> {
+        if values.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // Get handles for all symbols.
+        let mut handle_bufs = vec![[0; 4]; values.len()];
+        let mut requests = values.iter().zip(&mut handle_bufs).map(|((name, _), buf)| {
+            WriteReadRequest::new(crate::index::GET_SYMHANDLE_BYNAME, 0, name.as_bytes(), buf)
+        }).collect_vec();
+        self.write_read_multi(&mut requests)?;
+        let handles = requests.iter().map(|req| match req.data()? {
+            [a, b, c, d] => Ok([*a, *b, *c, *d]),
+            data => Err(Error::Reply("getting handle", "unexpected handle length",
+                                     data.len() as u32)),
+        }).collect_vec();
+
+        // Write the values for all resolved handles.
+        let mut writes = values.iter().zip(&handles).filter_map(|((_, data), handle)| {
+            let handle = u32::from_le_bytes(*handle.as_ref().ok()?);
+            Some(WriteRequest::new(crate::index::RW_SYMVAL_BYHANDLE, handle, data.as_ref()))
+        }).collect_vec();
+        if !writes.is_empty() {
+            // Release the handles even if writing fails.
+            let result = self.write_multi(&mut writes);
+            let mut releases = handles.iter().filter_map(|handle| {
+                Some(WriteRequest::new(crate::index::RELEASE_SYMHANDLE, 0, handle.as_ref().ok()?))
+            }).collect_vec();
+            let _ = self.write_multi(&mut releases);
+            result?;
+        }
+
+        let mut writes = writes.iter();
+        Ok(handles.into_iter().map(|handle| match handle {
+            Ok(_) => writes.next().expect("one write per handle").ensure(),
+            Err(e) => Err(e),
+        }).collect())
+    }
+
+    /// Read `len` bytes from a PLC memory area, starting at `byte_offset`.
+    pub fn read_area(&self, area: MemoryArea, byte_offset: u32, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.read_exact(area.byte_group(), byte_offset, &mut data)?;
+        Ok(data)
+    }
+
+    /// Write data to a PLC memory area, starting at `byte_offset`.
+    pub fn write_area(&self, area: MemoryArea, byte_offset: u32, data: &[u8]) -> Result<()> {
+        self.write(area.byte_group(), byte_offset, data)
+    }
+
+    /// Read a single bit from a PLC memory area.
+    ///
+    /// The `bit_offset` is the bit address, i.e. `byte * 8 + bit` for
+    /// `%MX<byte>.<bit>`.
+    pub fn read_area_bit(&self, area: MemoryArea, bit_offset: u32) -> Result<bool> {
+        let mut data = [0];
+        self.read_exact(area.bit_group(), bit_offset, &mut data)?;
+        Ok(data[0] != 0)
+    }
+
+    /// Write a single bit to a PLC memory area.
+    ///
+    /// See [`read_area_bit`](Self::read_area_bit) for the bit offset.
+    pub fn write_area_bit(&self, area: MemoryArea, bit_offset: u32, value: bool) -> Result<()> {
+        self.write(area.bit_group(), bit_offset, &[value as u8])
+    }
+
+    /// Read `len` bytes from the marker area (`%MB<byte_offset>` and following).
+    pub fn read_marker(&self, byte_offset: u32, len: usize) -> Result<Vec<u8>> {
+        self.read_area(MemoryArea::Marker, byte_offset, len)
+    }
+
+    /// Write data to the marker area (`%MB<byte_offset>` and following).
+    pub fn write_marker(&self, byte_offset: u32, data: &[u8]) -> Result<()> {
+        self.write_area(MemoryArea::Marker, byte_offset, data)
+    }
+
+    /// Read a single bit from the marker area.
+    ///
+    /// See [`read_area_bit`](Self::read_area_bit) for the bit offset.
+    pub fn read_marker_bit(&self, bit_offset: u32) -> Result<bool> {
+        self.read_area_bit(MemoryArea::Marker, bit_offset)
+    }
+
+    /// Write a single bit to the marker area.
+    ///
+    /// See [`read_area_bit`](Self::read_area_bit) for the bit offset.
+    pub fn write_marker_bit(&self, bit_offset: u32, value: bool) -> Result<()> {
+        self.write_area_bit(MemoryArea::Marker, bit_offset, value)
+    }
+
+    /// Return the symbol version of the PLC.
+    ///
+    /// This changes whenever a new PLC program is downloaded, which makes
+    /// cached symbol handles and symbol info invalid.
+    pub fn symbol_version(&self) -> Result<u8> {
+        self.read_value(crate::index::GET_SYMVERSION, 0)
+    }
+
+    /// Return the name of the PLC application running on the device.
+    ///
+    /// The name is read from the `TwinCAT_SystemInfoVarList._AppInfo`
+    /// structure, so the device must be a TwinCAT 3 PLC runtime (e.g. port
+    /// 851).  Other devices return an ADS error, such as 0x710 (symbol not
+    /// found), with the action "reading application name".
+    pub fn app_name(&self) -> Result<String> {
+        // The application name is a STRING(63).
+        let mut buf = [0; 64];
+        let len = self.write_read(crate::index::GET_SYMVAL_BYNAME, 0,
+                                  b"TwinCAT_SystemInfoVarList._AppInfo.AppName", &mut buf)
+                      .map_err(|e| match e {
+                          Error::Ads(_, msg, code) | Error::Request { message: msg, code, .. } =>
+                              Error::Ads("reading application name", msg, code),
+                          e => e,
+                      })?;
+        let len = buf[..len].iter().position(|&b| b == 0).unwrap_or(len);
+        Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    /// Read the current system time of the target.
+    ///
+    /// The device must be the system service of the target, i.e. its port
+    /// must be [`ports::SYSTEM_SERVICE`](crate::ports::SYSTEM_SERVICE).
+    ///
+    /// The target reports its local time as a Windows `SYSTEMTIME`, see
+    /// [`index::SYSTEM_TIME`](crate::index::SYSTEM_TIME).  Like for
+    /// [`read_date`](Self::read_date), the date and time of day are converted
+    /// as if they were UTC, so the result only matches notification
+    /// timestamps if the target's clock is set to UTC.
+    pub fn read_plc_time(&self) -> Result<SystemTime> {
+        if self.addr.port() != crate::ports::SYSTEM_SERVICE {
+            return Err(Error::Io("reading PLC time", io::Error::new(
+                io::ErrorKind::InvalidInput, "device is not the system service")));
+        }
+        let time = self.read_value::<SystemTimeStruct>(crate::index::SYSTEM_TIME, 1)?;
+        time.to_system_time()
+    }
+
+    /// Return the routes configured in the AMS router of the target.
+    ///
+    /// The device must be the system service of the target, i.e. its port
+    /// must be [`ports::SYSTEM_SERVICE`](crate::ports::SYSTEM_SERVICE).  If
+    /// the target doesn't allow reading the routes, the ADS error is returned
+    /// with the action "listing routes".
+    pub fn list_routes(&self) -> Result<Vec<RouteInfo>> {
+        if self.addr.port() != crate::ports::SYSTEM_SERVICE {
+            return Err(Error::Io("listing routes", io::Error::new(
+                io::ErrorKind::InvalidInput, "device is not the system service")));
+        }
+        let mut routes = Vec::new();
+        let mut buf = [0; 2048];
+        let mut index = 0;
+        loop {
+            match self.read(crate::index::ROUTE_LIST, index, &mut buf) {
+                // There are no more routes.
+                Err(e) if e.ads_code() == Some(0x716) => return Ok(routes),
+                Err(Error::Request { message, code, .. }) =>
+                    return Err(Error::Ads("listing routes", message, code)),
+                Err(e) => return Err(e),
+                Ok(len) => routes.push(parse_route_entry(&buf[..len])?),
+            }
+            index += 1;
+        }
+    }
+
     /// Return the ADS and device state of the device.
+    ///
+    /// If the device reports an ADS state that is not known as an
+    /// [`AdsState`], an error is returned; use
+    /// [`get_state_raw`](Self::get_state_raw) to get the value anyway.
     pub fn get_state(&self) -> Result<(AdsState, u16)> {
-        let mut state = ReadState::new_zeroed();
-        self.client.communicate(Command::ReadState, self.addr,
-                                &[], &mut [state.as_bytes_mut()])?;
+        let (ads_state, dev_state) = self.get_state_raw()?;
 
         // Convert ADS state to the enum type
-        let ads_state = AdsState::try_from(state.ads_state.get())
-            .map_err(|e| Error::Reply("read state", e, state.ads_state.get().into()))?;
+        let ads_state = AdsState::try_from(ads_state)
+            .map_err(|e| Error::Reply("read state", e, ads_state.into()))?;
+
+        Ok((ads_state, dev_state))
+    }
 
-        Ok((ads_state, state.dev_state.get()))
+    /// Return the ADS and device state of the device, with the ADS state as
+    /// the raw value reported by the device.
+    pub fn get_state_raw(&self) -> Result<(u16, u16)> {
+        let mut state = ReadState::new_zeroed();
+        self.communicate(Command::ReadState, &[], &mut [state.as_bytes_mut()])?;
+        Ok((state.ads_state.get(), state.dev_state.get()))
     }
 
     /// (Try to) set the ADS and device state of the device.
     pub fn write_control(&self, ads_state: AdsState, dev_state: u16) -> Result<()> {
-        let data = WriteControl {
+        self.write_control_data(ads_state, dev_state, &[])
+    }
+
+    /// Set the ADS state of the device, and wait until it has been reached.
+    ///
+    /// The device state is kept.  After the control request, the state is
+    /// polled until it matches `target`, which is then returned.  If that
+    /// doesn't happen within [`STATE_CHANGE_TIMEOUT`], an error with the last
+    /// observed state is returned.
+    ///
+    /// Commands like `Reset` or `Reconfig` are never observed as a state; use
+    /// [`write_control`](Self::write_control) for them and check the
+    /// resulting state separately.
+    pub fn set_state(&self, target: AdsState) -> Result<AdsState> {
+        self.set_state_timeout(target, STATE_CHANGE_TIMEOUT)
+    }
+
+    /// Like [`set_state`](Self::set_state), but wait at most `timeout` for
+    /// the state change.
+    pub fn set_state_timeout(&self, target: AdsState, timeout: Duration) -> Result<AdsState> {
+        let (_, dev_state) = self.get_state()?;
+        self.write_control(target, dev_state)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let (state, _) = self.get_state()?;
+            if state == target {
+                return Ok(state);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Reply("setting state", "state did not change in time",
+                                        state as u32));
+            }
+            std::thread::sleep(STATE_POLL_INTERVAL);
+        }
+    }
+
+    /// (Try to) set the ADS and device state of the device, sending additional
+    /// data with the request.
+    pub fn write_control_data(&self, ads_state: AdsState, dev_state: u16,
+                              data: &[u8]) -> Result<()> {
+        let header = WriteControl {
             ads_state:   U16::new(ads_state as _),
             dev_state:   U16::new(dev_state),
-            data_length: U32::new(0),
+            data_length: U32::new(data.len().try_into()?),
         };
-        self.client.communicate(Command::WriteControl, self.addr,
-                                &[data.as_bytes()], &mut [])?;
+        self.communicate(Command::WriteControl, &[header.as_bytes(), data], &mut [])?;
         Ok(())
     }
 
@@ -790,25 +2441,193 @@ impl<'c> Device<'c> {
             index_offset: U32::new(index_offset),
             length:       U32::new(attributes.length.try_into()?),
             trans_mode:   U32::new(attributes.trans_mode as u32),
-            max_delay:    U32::new(attributes.max_delay.as_millis().try_into()?),
-            cycle_time:   U32::new(attributes.cycle_time.as_millis().try_into()?),
+            max_delay:    U32::new(attributes.max_delay_ticks()?),
+            cycle_time:   U32::new(attributes.cycle_ticks()?),
             reserved:     [0; 16],
         };
         let mut handle = U32::<LE>::new(0);
-        self.client.communicate(Command::AddNotification, self.addr,
-                                &[data.as_bytes()], &mut [handle.as_bytes_mut()])?;
-        self.client.notif_handles.borrow_mut().insert((self.addr, handle.get()));
-        Ok(handle.get())
+        self.client.adding_notifications(|| {
+            self.communicate(Command::AddNotification,
+                             &[data.as_bytes()], &mut [handle.as_bytes_mut()])?;
+            self.client.notif_handles.lock().expect("not poisoned")
+                                      .insert((self.addr, handle.get()), data);
+            Ok(handle.get())
+        })
+    }
+
+    /// Get the symbol information for a single symbol by name.
+    ///
+    /// See [`symbol::get_symbol`](crate::symbol::get_symbol) for details.
+    pub fn symbol_info(&self, name: &str) -> Result<crate::symbol::Symbol> {
+        crate::symbol::get_symbol(*self, name)
+    }
+
+    /// Read the value of a named symbol.
+    ///
+    /// The symbol's handle and size are requested on first access, and
+    /// cached by the client until it is dropped.  If the size of `T` doesn't
+    /// match the size of the symbol, an `Error::Reply` with the symbol's size
+    /// is returned instead of misinterpreting the data.
+    ///
+    /// See [`read_value`](Self::read_value) for the supported types.
+    pub fn read_by_name<T: Default + AsBytes + FromBytes>(&self, name: &str) -> Result<T> {
+        self.with_symbol_handle(name, size_of::<T>(), |handle| {
+            self.read_value(crate::index::RW_SYMVAL_BYHANDLE, handle)
+        })
+    }
+
+    /// Write the value of a named symbol.
+    ///
+    /// See [`read_by_name`](Self::read_by_name) for details.
+    pub fn write_by_name<T: AsBytes>(&self, name: &str, value: &T) -> Result<()> {
+        self.with_symbol_handle(name, size_of::<T>(), |handle| {
+            self.write_value(crate::index::RW_SYMVAL_BYHANDLE, handle, value)
+        })
+    }
+
+    /// Read part of the value of a named symbol.
+    ///
+    /// `len` bytes are read, starting at `offset` into the symbol's value,
+    /// for example a single member of a large structure.  The location and
+    /// size of the symbol are requested with
+    /// [`symbol_info`](Self::symbol_info) first; if the range exceeds the
+    /// symbol's size, an error of kind `InvalidInput` is returned.
+    ///
+    /// To read parts of the same symbol repeatedly, get the symbol info once
+    /// and use [`read`](Self::read) with its index group and offset.
+    pub fn read_by_name_partial(&self, name: &str, offset: usize, len: usize) -> Result<Vec<u8>> {
+        let symbol = self.symbol_info(name)?;
+        if offset.checked_add(len).map_or(true, |end| end > symbol.size) {
+            return Err(Error::Io("reading part of symbol", io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("range exceeds the symbol size of {} bytes", symbol.size))));
+        }
+        let index_offset = symbol.ix_offset.checked_add(offset.try_into()?).ok_or(
+            Error::Reply("reading part of symbol", "index offset overflows", symbol.ix_offset))?;
+        let mut data = vec![0; len];
+        self.read_exact(symbol.ix_group, index_offset, &mut data)?;
+        Ok(data)
+    }
+
+    /// Run `f` with the cached handle of a symbol, after checking its size.
+    fn with_symbol_handle<T>(&self, name: &str, size: usize,
+                             f: impl Fn(u32) -> Result<T>) -> Result<T> {
+        let check_size = |symbol_size: usize| if size == symbol_size {
+            Ok(())
+        } else {
+            Err(Error::Reply("accessing symbol by name", "type size does not match symbol size",
+                             symbol_size as u32))
+        };
+        let key = (self.addr, name.to_owned());
+        let cached = self.client.symbol_handles.lock().expect("not poisoned").get(&key).copied();
+        if let Some((handle, symbol_size)) = cached {
+            check_size(symbol_size)?;
+            match f(handle) {
+                // The handle is not valid anymore, request a new one.
+                Err(e) if matches!(e.ads_code(), Some(0x710 | 0x711)) => {}
+                result => return result,
+            }
+        }
+        let symbol_size = self.symbol_info(name)?.size;
+        let mut handle = [0; 4];
+        self.write_read_exact(crate::index::GET_SYMHANDLE_BYNAME, 0, name.as_bytes(),
+                              &mut handle)?;
+        let handle = u32::from_le_bytes(handle);
+
+        // Another thread can have cached a new handle in the meantime: then
+        // use that one, and release ours.
+        let stale = cached.map(|(handle, _)| handle);
+        let other = {
+            let mut handles = self.client.symbol_handles.lock().expect("not poisoned");
+            match handles.get(&key) {
+                Some(&other) if Some(other.0) != stale => Some(other),
+                _ => {
+                    handles.insert(key, (handle, symbol_size));
+                    None
+                }
+            }
+        };
+        let (handle, symbol_size) = match other {
+            Some(other) => {
+                let _ = self.client.release_symbol_handle(self.addr, handle);
+                other
+            }
+            None => (handle, symbol_size),
+        };
+        check_size(symbol_size)?;
+        f(handle)
+    }
+
+    /// Add a notification for a named symbol.
+    ///
+    /// The symbol's size is queried from the device and used as the data
+    /// length, and the notification is registered on a symbol handle.  If the
+    /// symbol is not found, an ADS error with code 0x710 is returned.
+    ///
+    /// The returned guard deletes the notification and releases the symbol
+    /// handle on drop.
+    pub fn notify_symbol(&self, name: &str, trans_mode: notif::TransmissionMode,
+                         max_delay: Duration, cycle_time: Duration)
+                         -> Result<notif::NotificationGuard<'c>> {
+        let size = self.symbol_info(name)?.size;
+        let symbol = crate::symbol::Handle::new(*self, name)?;
+        let attributes = notif::Attributes::new(size, trans_mode, max_delay, cycle_time);
+        notif::NotificationGuard::with_symbol(*self, symbol, &attributes)
+    }
+
+    /// Watch a named symbol for changes, and receive its decoded values.
+    ///
+    /// This adds a notification for the symbol like
+    /// [`notify_symbol`](Self::notify_symbol), without delay and checked in
+    /// every server cycle.  Each sample is decoded as `T` (see
+    /// [`Sample::read_value`](notif::Sample::read_value)) and sent to the
+    /// returned channel; samples that can't be decoded are skipped.  The
+    /// samples are also delivered to the client's notification channel as
+    /// usual.
+    ///
+    /// When the notification is deleted, e.g. by dropping the guard, the
+    /// channel is disconnected.  After a reconnect, the channel receives the
+    /// samples of the reissued notification, or is disconnected if it could
+    /// not be added again.
+    pub fn watch<T>(&self, name: &str, trans_mode: notif::TransmissionMode)
+                    -> Result<(notif::NotificationGuard<'c>, Receiver<T>)>
+    where T: FromBytes + Send + 'static
+    {
+        let (send, recv) = unbounded();
+        let watcher = Arc::new(move |sample: &notif::Sample| {
+            if let Ok(value) = sample.read_value::<T>() {
+                let _ = send.send(value);
+            }
+        });
+        let mut guard = None;
+        self.client.watch_notification(self.addr, || {
+            let new = self.notify_symbol(name, trans_mode, Duration::ZERO, Duration::ZERO)?;
+            let handle = new.handle();
+            guard = Some(new);
+            Ok(handle)
+        }, watcher)?;
+        Ok((guard.expect("notification added"), recv))
     }
 
     /// Add multiple notification handles.
     ///
+    /// Large batches are split into several sum-up requests, see
+    /// [`MAX_SUM_REQUESTS`].  The batch is then not atomic: if one of the
+    /// sum-up requests fails, the handles of the ones before it have already
+    /// been added.
+    ///
     /// This function only returns Err on errors that cause the whole sum-up
     /// request to fail (e.g. if the device doesn't support such requests).  If
     /// the request as a whole succeeds, each single read can have returned its
     /// own error.  The [`AddNotifRequest::handle`] method will return either
     /// the returned handle or the error for each read.
     pub fn add_notification_multi(&self, requests: &mut [AddNotifRequest]) -> Result<()> {
+        split_sum_request(requests, |_| (size_of::<AddNotif>(), size_of::<ResultLength>()),
+                          |chunk| self.client.adding_notifications(
+                              || self.add_notification_multi_once(chunk)))
+    }
+
+    fn add_notification_multi_once(&self, requests: &mut [AddNotifRequest]) -> Result<()> {
         let nreq = requests.len();
         let rlen = size_of::<ResultLength>() * nreq;
         let wlen = size_of::<AddNotif>() * nreq;
@@ -825,10 +2644,11 @@ impl<'c> Device<'c> {
             w_buffers.push(req.req.as_bytes());
             r_buffers.push(req.res.as_bytes_mut());
         }
-        self.client.communicate(Command::ReadWrite, self.addr, &w_buffers, &mut r_buffers)?;
+        self.communicate(Command::ReadWrite, &w_buffers, &mut r_buffers)?;
         for req in requests {
             if let Ok(handle) = req.handle() {
-                self.client.notif_handles.borrow_mut().insert((self.addr, handle));
+                self.client.notif_handles.lock().expect("not poisoned")
+                                          .insert((self.addr, handle), req.req);
             }
         }
         Ok(())
@@ -836,20 +2656,45 @@ impl<'c> Device<'c> {
 
     /// Delete a notification with given handle.
     pub fn delete_notification(&self, handle: notif::Handle) -> Result<()> {
-        self.client.communicate(Command::DeleteNotification, self.addr,
-                                &[U32::<LE>::new(handle).as_bytes()], &mut [])?;
-        self.client.notif_handles.borrow_mut().remove(&(self.addr, handle));
+        self.client.unwatch_notification(self.addr, handle);
+        self.communicate(Command::DeleteNotification,
+                         &[U32::<LE>::new(handle).as_bytes()], &mut [])?;
+        self.client.notif_handles.lock().expect("not poisoned").remove(&(self.addr, handle));
         Ok(())
     }
 
     /// Delete multiple notification handles.
     ///
+    /// Large batches are split into several sum-up requests, see
+    /// [`MAX_SUM_REQUESTS`].  The batch is then not atomic: if one of the
+    /// sum-up requests fails, the handles of the ones before it have already
+    /// been deleted.
+    ///
     /// This function only returns Err on errors that cause the whole sum-up
     /// request to fail (e.g. if the device doesn't support such requests).  If
     /// the request as a whole succeeds, each single read can have returned its
     /// own error.  The [`DelNotifRequest::ensure`] method will return either the
     /// returned data or the error for each read.
     pub fn delete_notification_multi(&self, requests: &mut [DelNotifRequest]) -> Result<()> {
+        split_sum_request(requests, |_| (size_of::<u32>(), size_of::<u32>()),
+                          |chunk| self.delete_notification_multi_once(chunk))
+    }
+
+    /// Delete a list of notification handles, with as few requests as
+    /// possible.
+    ///
+    /// Like [`delete_notification_multi`](Self::delete_notification_multi),
+    /// this only returns Err if the whole request failed.  Otherwise, the
+    /// result for each handle is returned in the same order, e.g. an error
+    /// for a handle that was already deleted.
+    pub fn delete_notification_list(&self, handles: &[notif::Handle])
+                                    -> Result<Vec<Result<()>>> {
+        let mut requests = handles.iter().map(|&h| DelNotifRequest::new(h)).collect_vec();
+        self.delete_notification_multi(&mut requests)?;
+        Ok(requests.iter().map(DelNotifRequest::ensure).collect())
+    }
+
+    fn delete_notification_multi_once(&self, requests: &mut [DelNotifRequest]) -> Result<()> {
         let nreq = requests.len();
         let rlen = size_of::<u32>() * nreq;
         let wlen = size_of::<u32>() * nreq;
@@ -863,21 +2708,68 @@ impl<'c> Device<'c> {
         let mut w_buffers = vec![header.as_bytes()];
         let mut r_buffers = vec![read_len.as_bytes_mut()];
         for req in requests.iter_mut() {
+            self.client.unwatch_notification(self.addr, req.req.get());
             w_buffers.push(req.req.as_bytes());
             r_buffers.push(req.res.as_bytes_mut());
         }
-        self.client.communicate(Command::ReadWrite, self.addr, &w_buffers, &mut r_buffers)?;
+        self.communicate(Command::ReadWrite, &w_buffers, &mut r_buffers)?;
         for req in requests {
             if req.ensure().is_ok() {
-                self.client.notif_handles.borrow_mut().remove(&(self.addr, req.req.get()));
+                self.client.notif_handles.lock().expect("not poisoned")
+                                          .remove(&(self.addr, req.req.get()));
             }
         }
         Ok(())
     }
+
+    /// Execute an ADS command given by its numeric ID, which is not otherwise
+    /// supported by this crate.
+    ///
+    /// The caller is responsible for the layout of the payload and the
+    /// returned reply data.  See [`Client::communicate_raw`] for details.
+    pub fn raw_command(&self, command_id: u16, payload: &[u8]) -> Result<Vec<u8>> {
+        self.client.communicate_raw(command_id, self.addr, payload)
+    }
+}
+
+/// A device that owns a reference to its `Client`.
+///
+/// Unlike [`Device`], this is not bound to the lifetime of a `&Client`, and
+/// can be cloned and moved into spawned threads.  All clones share the
+/// client's connection.
+#[derive(Clone)]
+pub struct SharedDevice {
+    client: Arc<Client>,
+    addr: AmsAddr,
+    retry: Option<RetryPolicy>,
+}
+
+impl SharedDevice {
+    /// Create a shared device for the given client and target address.
+    pub fn new(client: Arc<Client>, addr: impl Into<AmsAddr>) -> Self {
+        let addr = client.device(addr).addr;
+        SharedDevice { client, addr, retry: None }
+    }
+
+    /// Return a shared device that retries requests, see [`Device::with_retry`].
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        SharedDevice { retry: Some(policy), ..self }
+    }
+
+    /// Return the underlying client.
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    /// Return a `Device` to make requests with.
+    pub fn device(&self) -> Device<'_> {
+        Device { client: &self.client, addr: self.addr, retry: self.retry }
+    }
 }
 
 /// Device info returned from an ADS server.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DeviceInfo {
     /// Name of the ADS device/service.
     pub name: String,
@@ -889,6 +2781,207 @@ pub struct DeviceInfo {
     pub version: u16,
 }
 
+/// The AMS header of a frame received with [`Client::recv_frame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmsHeader {
+    /// Address the frame is sent to.
+    pub target: AmsAddr,
+    /// Address the frame is sent from.
+    pub source: AmsAddr,
+    /// The ADS command ID, see [`Command`].
+    pub command: u16,
+    /// The AMS state flags, see `STATE_FLAG_*`.
+    pub state_flags: u16,
+    /// Length of the data following the header.
+    pub data_length: u32,
+    /// The error code, nonzero for failed requests.
+    pub error_code: u32,
+    /// The invoke ID, identifying the request and its reply.
+    pub invoke_id: u32,
+}
+
+/// A complete ADS frame received with [`Client::recv_frame`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmsFrame {
+    /// The AMS header.
+    pub header: AmsHeader,
+    /// The data following the header.
+    pub payload: Vec<u8>,
+}
+
+impl AmsFrame {
+    /// Parse a frame including its AMS/TCP header.
+    ///
+    /// Returns an error if the frame is not an ADS command, or if the lengths
+    /// given in the headers are inconsistent.
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let header = AdsHeader::read_from_prefix(buf).ok_or_else(
+            || Error::Reply("parsing frame", "frame too short", buf.len() as u32))?;
+        if header.ams_cmd != 0 {
+            return Err(Error::Reply("parsing frame", "not an ADS command", header.ams_cmd as _));
+        }
+        let payload = &buf[AMS_HEADER_SIZE..];
+        if header.length.get() as usize != buf.len() - TCP_HEADER_SIZE ||
+            header.data_length.get() as usize != payload.len()
+        {
+            return Err(Error::Reply("parsing frame", "inconsistent frame length",
+                                    header.data_length.get()));
+        }
+        Ok(AmsFrame {
+            header: AmsHeader {
+                target: AmsAddr::new(header.dest_netid, header.dest_port.get()),
+                source: AmsAddr::new(header.src_netid, header.src_port.get()),
+                command: header.command.get(),
+                state_flags: header.state_flags.get(),
+                data_length: header.data_length.get(),
+                error_code: header.error_code.get(),
+                invoke_id: header.invoke_id.get(),
+            },
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// A route configured in an AMS router, returned by [`Device::list_routes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RouteInfo {
+    /// Name of the route.
+    pub name: String,
+    /// NetID of the remote system.
+    pub netid: AmsNetId,
+    /// Host name or IP address of the remote system.
+    pub address: String,
+    /// Route flags:
+    /// - 0x01 - temporary
+    /// - 0x80 - unidirectional
+    /// - 0x100 - virtual/NAT
+    pub flags: u32,
+}
+
+/// Statistics about received notifications, returned by [`Client::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NotificationStats {
+    /// Number of notification messages put into the notification channel.
+    pub delivered: u64,
+    /// Number of samples for handles that were not added by this client, or
+    /// have already been deleted.
+    pub unmatched: u64,
+    /// Number of notification messages discarded because the channel was
+    /// full, see [`Client::notification_buffer`].
+    pub overflow: u64,
+}
+
+/// Information about a client's connection, returned by
+/// [`Client::connection_info`].
+///
+/// Note that ADS doesn't negotiate a protocol version or other parameters:
+/// the AMS/TCP header has no version field, and the same commands are used
+/// with TwinCAT 2 and 3.  To find out about the server, use
+/// [`Device::get_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionInfo {
+    /// Address of the ADS server's TCP socket.
+    pub peer_addr: SocketAddr,
+    /// Local address of the TCP socket.
+    pub local_addr: SocketAddr,
+    /// The AMS address used as the source of requests.
+    pub source: AmsAddr,
+    /// If the source port was requested from the AMS router, with
+    /// `Source::Request`.
+    pub source_requested: bool,
+    /// Number of times the client has reconnected.
+    pub reconnects: u64,
+    /// Number of replies on the current connection that matched no pending
+    /// request, and were discarded.
+    pub unknown_replies: u64,
+}
+
+/// A PLC memory area that can be addressed by byte or by bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryArea {
+    /// The marker area (`%M`).
+    Marker,
+    /// The process image of physical inputs (`%I`).
+    Input,
+    /// The process image of physical outputs (`%Q`).
+    Output,
+}
+
+impl MemoryArea {
+    /// Return the index group for byte access, where the index offset is the
+    /// byte address.
+    pub fn byte_group(self) -> u32 {
+        match self {
+            MemoryArea::Marker => crate::index::PLC_RW_M,
+            MemoryArea::Input => crate::index::IO_RW_I,
+            MemoryArea::Output => crate::index::IO_RW_Q,
+        }
+    }
+
+    /// Return the index group for bit access, where the index offset is the
+    /// bit address (`byte * 8 + bit`).
+    pub fn bit_group(self) -> u32 {
+        match self {
+            MemoryArea::Marker => crate::index::PLC_RW_MX,
+            MemoryArea::Input => crate::index::IO_RW_IX,
+            MemoryArea::Output => crate::index::IO_RW_QX,
+        }
+    }
+}
+
+/// The Windows `SYSTEMTIME` structure returned by the time service.
+#[derive(AsBytes, FromBytes, Default)]
+#[repr(C)]
+struct SystemTimeStruct {
+    year: U16<LE>,
+    month: U16<LE>,
+    day_of_week: U16<LE>,
+    day: U16<LE>,
+    hour: U16<LE>,
+    minute: U16<LE>,
+    second: U16<LE>,
+    millisecond: U16<LE>,
+}
+
+impl SystemTimeStruct {
+    fn to_system_time(&self) -> Result<SystemTime> {
+        let invalid = |value: U16<LE>| {
+            Error::Reply("reading PLC time", "invalid time", value.get().into())
+        };
+        let (year, month, day) = (i64::from(self.year.get()), self.month.get(), self.day.get());
+        if !(1..=12).contains(&month) {
+            return Err(invalid(self.month));
+        }
+        if !(1..=31).contains(&day) {
+            return Err(invalid(self.day));
+        }
+        for &(value, limit) in &[(self.hour, 24), (self.minute, 60), (self.second, 60),
+                                 (self.millisecond, 1000)] {
+            if value.get() >= limit {
+                return Err(invalid(value));
+            }
+        }
+
+        // Days since 1970-01-01 of the proleptic Gregorian calendar, see
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * i64::from(month) + 2) / 5 + i64::from(day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        let millis = 1000 * (86_400 * days + 3600 * i64::from(self.hour.get())
+                             + 60 * i64::from(self.minute.get()) + i64::from(self.second.get()))
+            + i64::from(self.millisecond.get());
+        let offset = Duration::from_millis(millis.unsigned_abs());
+        Ok(if millis >= 0 { UNIX_EPOCH + offset } else { UNIX_EPOCH - offset })
+    }
+}
+
 /// The ADS state of a device.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -1057,7 +3150,7 @@ pub(crate) struct WriteControl {
     pub data_length: U32<LE>,
 }
 
-#[derive(FromBytes, AsBytes)]
+#[derive(FromBytes, AsBytes, Clone, Copy)]
 #[repr(C)]
 pub(crate) struct AddNotif {
     pub index_group:  U32<LE>,
@@ -1100,6 +3193,18 @@ impl<'buf> ReadRequest<'buf> {
             Ok(&self.rbuf[..self.res.length.get() as usize])
         }
     }
+
+    /// Return the size of the ADS data for a single sum-up request containing
+    /// all `requests`, and of its reply, excluding the AMS headers.
+    ///
+    /// [`Device::read_multi`] splits batches that exceed [`MAX_SUM_DATA_SIZE`].
+    pub fn sum_size(requests: &[Self]) -> (usize, usize) {
+        sum_size(requests, Self::sizes)
+    }
+
+    fn sizes(&self) -> (usize, usize) {
+        (size_of::<IndexLength>(), size_of::<ResultLength>() + self.rbuf.len())
+    }
 }
 
 /// A single request for a [`Device::write_multi`] request.
@@ -1133,6 +3238,18 @@ impl<'buf> WriteRequest<'buf> {
             Ok(())
         }
     }
+
+    /// Return the size of the ADS data for a single sum-up request containing
+    /// all `requests`, and of its reply, excluding the AMS headers.
+    ///
+    /// [`Device::write_multi`] splits batches that exceed [`MAX_SUM_DATA_SIZE`].
+    pub fn sum_size(requests: &[Self]) -> (usize, usize) {
+        sum_size(requests, Self::sizes)
+    }
+
+    fn sizes(&self) -> (usize, usize) {
+        (size_of::<IndexLength>() + self.wbuf.len(), size_of::<u32>())
+    }
 }
 
 /// A single request for a [`Device::write_read_multi`] request.
@@ -1171,6 +3288,19 @@ impl<'buf> WriteReadRequest<'buf> {
             Ok(&self.rbuf[..self.res.length.get() as usize])
         }
     }
+
+    /// Return the size of the ADS data for a single sum-up request containing
+    /// all `requests`, and of its reply, excluding the AMS headers.
+    ///
+    /// [`Device::write_read_multi`] splits batches that exceed
+    /// [`MAX_SUM_DATA_SIZE`].
+    pub fn sum_size(requests: &[Self]) -> (usize, usize) {
+        sum_size(requests, Self::sizes)
+    }
+
+    fn sizes(&self) -> (usize, usize) {
+        (size_of::<IndexLengthRW>() + self.wbuf.len(), size_of::<ResultLength>() + self.rbuf.len())
+    }
 }
 
 /// A single request for a [`Device::add_notification_multi`] request.
@@ -1182,6 +3312,9 @@ pub struct AddNotifRequest {
 impl AddNotifRequest {
     /// Create the request with given index group, index offset and notification
     /// attributes.
+    ///
+    /// Durations that can't be represented are clamped to the maximum; use
+    /// [`notif::Attributes::builder`] to validate them beforehand.
     pub fn new(index_group: u32, index_offset: u32, attributes: &notif::Attributes) -> Self {
         Self {
             req: AddNotif {
@@ -1189,8 +3322,8 @@ impl AddNotifRequest {
                 index_offset: U32::new(index_offset),
                 length:       U32::new(attributes.length as u32),
                 trans_mode:   U32::new(attributes.trans_mode as u32),
-                max_delay:    U32::new(attributes.max_delay.as_millis() as u32),
-                cycle_time:   U32::new(attributes.cycle_time.as_millis() as u32),
+                max_delay:    U32::new(attributes.max_delay_ticks().unwrap_or(u32::MAX)),
+                cycle_time:   U32::new(attributes.cycle_ticks().unwrap_or(u32::MAX)),
                 reserved:     [0; 16],
             },
             res: ResultLength::new_zeroed(),
@@ -1237,6 +3370,41 @@ impl DelNotifRequest {
     }
 }
 
+/// Split a batch of sum-up requests into chunks that respect the limits, and
+/// execute each chunk with `run`.
+fn split_sum_request<R>(requests: &mut [R], sizes: impl Fn(&R) -> (usize, usize),
+                        mut run: impl FnMut(&mut [R]) -> Result<()>) -> Result<()> {
+    let mut rest = requests;
+    while !rest.is_empty() {
+        let n = sum_chunk_len(rest.iter().map(&sizes));
+        let (chunk, tail) = std::mem::take(&mut rest).split_at_mut(n);
+        run(chunk)?;
+        rest = tail;
+    }
+    Ok(())
+}
+
+/// Return how many requests with the given (request, reply) data sizes fit
+/// into one sum-up request.  This is at least one, if there are any requests.
+fn sum_chunk_len(sizes: impl Iterator<Item = (usize, usize)>) -> usize {
+    let (mut wlen, mut rlen, mut count) = (0, 0, 0);
+    for (w, r) in sizes.take(MAX_SUM_REQUESTS) {
+        wlen += w;
+        rlen += r;
+        if count > 0 && (wlen > MAX_SUM_DATA_SIZE || rlen > MAX_SUM_DATA_SIZE) {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Return the data sizes of a sum-up request for the given single requests,
+/// and of its reply.
+fn sum_size<R>(requests: &[R], sizes: impl Fn(&R) -> (usize, usize)) -> (usize, usize) {
+    requests.iter().map(sizes).fold((size_of::<IndexLengthRW>(), size_of::<ResultLength>()),
+                                    |(wlen, rlen), (w, r)| (wlen + w, rlen + r))
+}
 
 fn fixup_write_read_return_buffers(requests: &mut [WriteReadRequest]) {
     // Calculate the initial (using buffer sizes) and actual (using result
@@ -1317,3 +3485,52 @@ fn test_fixup_buffers() {
     assert!(&reqs[1].rbuf[..6] == b"ABCDEF");
     assert!(&reqs[0].rbuf[..8] == b"12345678");
 }
+
+#[test]
+fn test_ads_state_conversion() {
+    for value in 0..=19 {
+        assert_eq!(AdsState::try_from(value).unwrap() as u16, value);
+    }
+    assert_eq!(AdsState::try_from(15), Ok(AdsState::Config));
+    assert!(AdsState::try_from(20).is_err());
+}
+
+#[test]
+fn test_trans_mode_encoding() {
+    use notif::TransmissionMode::*;
+    for &(mode, value) in &[(NoTrans, 0), (ClientCycle, 1), (ClientOnChange, 2),
+                            (ServerCycle, 3), (ServerOnChange, 4), (ServerCycle2, 5),
+                            (ServerOnChange2, 6), (Client1Req, 10)] {
+        let attrib = notif::Attributes::new(4, mode, Duration::from_secs(1),
+                                            Duration::from_secs(1));
+        let req = AddNotifRequest::new(crate::index::PLC_RW_M, 0, &attrib);
+        assert_eq!(req.req.as_bytes()[12..16], U32::<LE>::new(value).as_bytes()[..]);
+    }
+}
+
+#[test]
+fn test_add_notif_layout() {
+    // group, offset, length, mode, max delay, cycle time (in 100ns), reserved
+    let expected = |mode: u8| {
+        let mut bytes = vec![0x20, 0x40, 0, 0, 8, 0, 0, 0, 2, 0, 0, 0, mode, 0, 0, 0,
+                             0x20, 0xA1, 0x07, 0, 0xA0, 0x86, 0x01, 0];
+        bytes.resize(40, 0);
+        bytes
+    };
+    let attrib = notif::Attributes::builder().length(2).on_change()
+                                             .max_delay(Duration::from_millis(50))
+                                             .cycle(Duration::from_millis(10))
+                                             .build().unwrap();
+    let req = AddNotifRequest::new(0x4020, 8, &attrib);
+    assert_eq!(req.req.as_bytes(), expected(4));
+
+    let attrib = notif::Attributes::new(2, notif::TransmissionMode::ServerCycle,
+                                        Duration::from_millis(50), Duration::from_millis(10));
+    let req = AddNotifRequest::new(0x4020, 8, &attrib);
+    assert_eq!(req.req.as_bytes(), expected(3));
+
+    // 100us cycle time, 1000 ticks
+    let attrib = notif::Attributes::cyclic(2, Duration::from_micros(100));
+    let req = AddNotifRequest::new(0x4020, 8, &attrib);
+    assert_eq!(req.req.as_bytes()[20..24], [0xE8, 0x03, 0, 0]);
+}
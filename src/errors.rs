@@ -4,7 +4,11 @@
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// A collection of different errors that can happen with ADS requests.
+///
+/// More variants can be added in the future, so matches on this type need a
+/// wildcard arm.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
     /// An IO error occurred.
     #[error("{0}: {1}")]
@@ -14,6 +18,28 @@ pub enum Error {
     #[error("{0}: {1} ({2:#x})")]
     Ads(&'static str, &'static str, u32),
 
+    /// The ADS server responded with an error code to a request for an index
+    /// group and offset, such as a read or write.
+    ///
+    /// The `length` is the number of bytes being written, or for reads, the
+    /// number of bytes requested.
+    #[error("{action}: {message} ({code:#x}) at index group {index_group:#x}, \
+             offset {index_offset:#x}, {length} bytes")]
+    Request {
+        /// The action that failed.
+        action: &'static str,
+        /// The message for the ADS error code.
+        message: &'static str,
+        /// The ADS error code.
+        code: u32,
+        /// The index group of the request.
+        index_group: u32,
+        /// The index offset of the request.
+        index_offset: u32,
+        /// The data length of the request.
+        length: usize,
+    },
+
     /// An unexpected or inconsistent reply was received.
     #[error("{0}: {1} ({2})")]
     Reply(&'static str, &'static str, u32),
@@ -23,6 +49,41 @@ pub enum Error {
     Overflow(#[from] std::num::TryFromIntError),
 }
 
+impl Error {
+    /// Return the ADS error code, if the error was returned by the ADS server.
+    pub fn ads_code(&self) -> Option<u32> {
+        match self {
+            Error::Ads(_, _, code) | Error::Request { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Return a copy of the error.  IO errors keep their kind and message,
+    /// but not the underlying error.
+    pub(crate) fn duplicate(&self) -> Error {
+        match self {
+            Error::Io(ctx, e) => Error::Io(ctx, std::io::Error::new(e.kind(), e.to_string())),
+            Error::Ads(ctx, msg, code) => Error::Ads(ctx, msg, *code),
+            Error::Request { action, message, code, index_group, index_offset, length } =>
+                Error::Request { action, message, code: *code, index_group: *index_group,
+                                 index_offset: *index_offset, length: *length },
+            Error::Reply(ctx, msg, value) => Error::Reply(ctx, msg, *value),
+            Error::Overflow(e) => Error::Overflow(*e),
+        }
+    }
+
+    /// Attach the index group, offset and data length of the failed request
+    /// to an error returned by the ADS server.  Other errors are unchanged.
+    pub(crate) fn with_request(self, index_group: u32, index_offset: u32,
+                               length: usize) -> Error {
+        match self {
+            Error::Ads(action, message, code) =>
+                Error::Request { action, message, code, index_group, index_offset, length },
+            other => other,
+        }
+    }
+}
+
 pub(crate) trait ErrContext {
     type Success;
     fn ctx(self, context: &'static str) -> Result<Self::Success>;
@@ -178,10 +239,38 @@ pub const ADS_ERRORS: &[(u32, &str)] = &[
     (0x101A, "Activation of Intel VT-x failed"),
 ];
 
+/// Return the documented message for the given ADS result code, if known.
+pub fn ads_error_message(err: u32) -> Option<&'static str> {
+    ADS_ERRORS.binary_search_by_key(&err, |e| e.0).ok().map(|idx| ADS_ERRORS[idx].1)
+}
+
 /// Return an `Error` corresponding to the given ADS result code.
 pub fn ads_error<T>(action: &'static str, err: u32) -> Result<T> {
-    match ADS_ERRORS.binary_search_by_key(&err, |e| e.0) {
-        Ok(idx) => Err(Error::Ads(action, ADS_ERRORS[idx].1, err)),
-        Err(_) => Err(Error::Ads(action, "Unknown error code", err)),
+    Err(Error::Ads(action, ads_error_message(err).unwrap_or("Unknown error code"), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ads_error_display() {
+        // binary search requires a sorted table
+        assert!(ADS_ERRORS.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let fmt = |code| ads_error::<()>("reading", code).unwrap_err().to_string();
+        assert_eq!(fmt(0x6), "reading: Target port not found, possibly ADS server not started (0x6)");
+        assert_eq!(fmt(0x702), "reading: Invalid index group (0x702)");
+        assert_eq!(fmt(0x710), "reading: Symbol not found (0x710)");
+        assert_eq!(fmt(0x745), "reading: Timeout elapsed -> check route setting (0x745)");
+        assert_eq!(fmt(0x9999), "reading: Unknown error code (0x9999)");
+
+        assert_eq!(ads_error::<()>("x", 0x705).unwrap_err().ads_code(), Some(0x705));
+        assert_eq!(ads_error_message(0x9999), None);
+
+        let err = ads_error::<()>("write data", 0x703).unwrap_err().with_request(0x4020, 8, 2);
+        assert_eq!(err.to_string(), "write data: Invalid index offset (0x703) at index group \
+                                     0x4020, offset 0x8, 2 bytes");
+        assert_eq!(err.ads_code(), Some(0x703));
     }
 }
@@ -1,6 +1,7 @@
 //! File access over ADS.
 
 use std::io;
+use std::time::SystemTime;
 
 use byteorder::{ByteOrder, LE};
 use crate::index;
@@ -9,6 +10,9 @@ use crate::{Device, Error, Result};
 /// A file opened within the PLC.  Files implement `Read` and `Write`, so they
 /// can be used like normal files in Rust APIs.
 ///
+/// Each read and write transfers at most [`MAX_CHUNK_SIZE`] bytes, so that
+/// large files can be streamed with `std::io::copy` and a buffer of any size.
+///
 /// The file is closed automatically on drop.
 pub struct File<'c> {
     device: Device<'c>,
@@ -26,11 +30,55 @@ impl<'c> File<'c> {
         })
     }
 
+    /// Close the file, returning any error that occurs.
+    ///
+    /// Dropping the file also closes it, but ignores errors.
+    pub fn close(self) -> Result<()> {
+        let result = self.device.write_read(index::FILE_CLOSE, self.handle, &[], &mut []);
+        // Don't close the handle again on drop.
+        std::mem::forget(self);
+        result.map(drop)
+    }
+
+    /// Return the raw file handle.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
     /// Delete a file.  `flags` must be combined from the constants in this module.
     pub fn delete(device: Device, filename: impl AsRef<[u8]>, flags: u32) -> Result<()> {
         device.write_read(index::FILE_DELETE, flags, filename.as_ref(), &mut []).map(drop)
     }
+}
 
+/// An entry of a directory listing, as returned by [`read_dir`].
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// The file name.  It is not a String since it is likely encoded in
+    /// Windows-1252.
+    pub name: Vec<u8>,
+    /// The file attributes, see e.g. [`DIRECTORY`].
+    pub attributes: u32,
+    /// The file size.
+    pub size: u64,
+    /// The creation time, as a Windows FILETIME.
+    pub created: u64,
+    /// The last access time, as a Windows FILETIME.
+    pub accessed: u64,
+    /// The last modification time, as a Windows FILETIME.
+    pub modified: u64,
+}
+
+impl DirEntry {
+    /// Return true if the entry is a directory.
+    pub fn is_directory(&self) -> bool {
+        self.attributes & DIRECTORY != 0
+    }
+
+    /// Return the last modification time as a `SystemTime`.
+    pub fn modified_time(&self) -> SystemTime {
+        crate::notif::filetime_to_system_time(self.modified)
+    }
 }
 
 /// Return a list of files in the named directory.
@@ -40,6 +88,13 @@ impl<'c> File<'c> {
 pub fn listdir(device: Device, dirname: impl AsRef<[u8]>)
      -> Result<Vec<(Vec<u8>, u32, u64)>>
 {
+    Ok(read_dir(device, dirname)?.into_iter()
+                                 .map(|entry| (entry.name, entry.attributes, entry.size))
+                                 .collect())
+}
+
+/// Return the entries of the named directory.
+pub fn read_dir(device: Device, dirname: impl AsRef<[u8]>) -> Result<Vec<DirEntry>> {
     let mut files = Vec::new();
     let mut buf = [0; 324];
     // Initial offset.  Offset 4 would start at the TwinCAT Boot directory instead.
@@ -51,15 +106,19 @@ pub fn listdir(device: Device, dirname: impl AsRef<[u8]>)
     loop {
         match device.write_read_exact(index::FILE_BROWSE, offset, &argument, &mut buf) {
             Ok(_) => {
-                let attrs = LE::read_u32(&buf[4..8]);
                 let sizeh = LE::read_u32(&buf[32..36]);
                 let sizel = LE::read_u32(&buf[36..40]);
-                let size = (sizeh as u64) << 32 | sizel as u64;
-                let name = buf[48..].iter().copied().take_while(|&b| b != 0).collect();
-                files.push((name, attrs, size));
+                files.push(DirEntry {
+                    name: buf[48..].iter().copied().take_while(|&b| b != 0).collect(),
+                    attributes: LE::read_u32(&buf[4..8]),
+                    size: (sizeh as u64) << 32 | sizel as u64,
+                    created: LE::read_u64(&buf[8..16]),
+                    accessed: LE::read_u64(&buf[16..24]),
+                    modified: LE::read_u64(&buf[24..32]),
+                });
             }
             // Error "not found" means the end of the list.
-            Err(Error::Ads(_, _, 0x70c)) => return Ok(files),
+            Err(e) if e.ads_code() == Some(0x70c) => return Ok(files),
             Err(e) => return Err(e),
         }
         offset = LE::read_u32(&buf[..4]);
@@ -67,8 +126,12 @@ pub fn listdir(device: Device, dirname: impl AsRef<[u8]>)
     }
 }
 
+/// The maximum number of bytes transferred by one read or write request.
+pub const MAX_CHUNK_SIZE: usize = 0x10000;
+
 impl<'a> io::Write for File<'a> {
     fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let data = &data[..data.len().min(MAX_CHUNK_SIZE)];
         self.device.write_read(index::FILE_WRITE, self.handle, data, &mut [])
                    // need to convert errors back to io::Error
                    .map_err(map_error)
@@ -82,8 +145,11 @@ impl<'a> io::Write for File<'a> {
 }
 
 impl<'a> std::io::Read for File<'a> {
+    /// Returns 0 at the end of the file, when the server returns no more data.
     fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
-        self.device.write_read(index::FILE_READ, self.handle, &[], data).map_err(map_error)
+        let len = data.len().min(MAX_CHUNK_SIZE);
+        self.device.write_read(index::FILE_READ, self.handle, &[], &mut data[..len])
+                   .map_err(map_error)
     }
 }
 
@@ -97,8 +163,8 @@ impl<'a> Drop for File<'a> {
 fn map_error(e: Error) -> io::Error {
     match e {
         Error::Io(_, io_error) => io_error,
-        Error::Ads(_, _, 0x704) => io::ErrorKind::InvalidInput.into(),
-        Error::Ads(_, _, 0x70C) => io::ErrorKind::NotFound.into(),
+        e if e.ads_code() == Some(0x704) => io::ErrorKind::InvalidInput.into(),
+        e if e.ads_code() == Some(0x70C) => io::ErrorKind::NotFound.into(),
         _ => io::Error::new(io::ErrorKind::Other, e.to_string()),
     }
 }
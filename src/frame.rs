@@ -0,0 +1,302 @@
+//! Encoding and decoding of AMS/TCP frames, independent of the transport.
+//!
+//! This is shared between the blocking and the async client.
+
+use std::convert::TryInto;
+
+use byteorder::{ByteOrder, ReadBytesExt, LE};
+use zerocopy::byteorder::{U16, U32};
+use zerocopy::AsBytes;
+
+use crate::client::{AdsHeader, Command, AMS_HEADER_SIZE, MAX_PACKET_LENGTH, STATE_FLAG_COMMAND,
+                    TCP_HEADER_SIZE};
+use crate::errors::ads_error;
+use crate::{AmsAddr, Error, Result};
+
+/// The message to send to a router to request a free port.
+pub(crate) const REQUEST_PORT_MSG: [u8; 8] = [0, 16, 2, 0, 0, 0, 0, 0];
+/// The size of the router's reply to `REQUEST_PORT_MSG`.
+pub(crate) const REQUEST_PORT_REPLY_SIZE: usize = 14;
+
+/// Decode the reply to `REQUEST_PORT_MSG`.
+pub(crate) fn decode_port_reply(reply: &[u8; REQUEST_PORT_REPLY_SIZE]) -> Result<AmsAddr> {
+    if reply[..6] != [0, 16, 8, 0, 0, 0] {
+        return Err(Error::Reply("requesting port", "unexpected reply header", 0));
+    }
+    Ok(AmsAddr::new(crate::AmsNetId::from_slice(&reply[6..12]).expect("size"),
+                    LE::read_u16(&reply[12..14])))
+}
+
+/// Return the message to send to a router to close a previously requested port.
+pub(crate) fn close_port_msg(port: u16) -> [u8; 8] {
+    let mut close_port_msg = [1, 0, 2, 0, 0, 0, 0, 0];
+    LE::write_u16(&mut close_port_msg[6..], port);
+    close_port_msg
+}
+
+/// Create the complete request frame for an ADS command.
+pub(crate) fn encode_request(command_id: u16, state_flags: u16, target: AmsAddr,
+                             source: AmsAddr, invoke_id: u32,
+                             data_in: &[&[u8]]) -> Result<Vec<u8>> {
+    // The data we send is the sum of all data_in buffers.
+    let data_in_len = data_in.iter().map(|v| v.len()).sum::<usize>();
+
+    // Create outgoing header.
+    let ads_data_len = AMS_HEADER_SIZE - TCP_HEADER_SIZE + data_in_len;
+    let header = AdsHeader {
+        ams_cmd:     0,  // send command
+        length:      U32::new(ads_data_len.try_into()?),
+        dest_netid:  target.netid(),
+        dest_port:   U16::new(target.port()),
+        src_netid:   source.netid(),
+        src_port:    U16::new(source.port()),
+        command:     U16::new(command_id),
+        state_flags: U16::new(state_flags | STATE_FLAG_COMMAND),
+        data_length: U32::new(data_in_len as u32),  // overflow checked above
+        error_code:  U32::new(0),
+        invoke_id:   U32::new(invoke_id),
+    };
+
+    // Collect the outgoing data.  Note, allocating a Vec and writing it to
+    // the socket only once is faster than writing in multiple steps, even
+    // with TCP_NODELAY.
+    let mut request = Vec::with_capacity(TCP_HEADER_SIZE + ads_data_len);
+    request.extend_from_slice(header.as_bytes());
+    for buf in data_in {
+        request.extend_from_slice(buf);
+    }
+    Ok(request)
+}
+
+/// Return the length of the packet following the AMS/TCP header.
+///
+/// Returns an error if the length exceeds [`MAX_PACKET_LENGTH`].
+pub(crate) fn packet_length(tcp_header: &[u8]) -> Result<usize> {
+    let length = LE::read_u32(&tcp_header[2..6]);
+    if length as usize > MAX_PACKET_LENGTH {
+        return Err(Error::Reply("reading packet", "packet length exceeds maximum", length));
+    }
+    Ok(length as usize)
+}
+
+/// The kind of a received packet.
+pub(crate) enum Packet {
+    /// A reply to one of our requests.
+    Reply,
+    /// A notification message.
+    Notification,
+    /// Something that we don't need to handle.
+    Ignore,
+}
+
+/// Determine the kind of a complete received packet, addressed to `source`.
+///
+/// Returns an error if the packet is invalid, which means that the connection
+/// cannot be used anymore.
+pub(crate) fn classify_packet(buf: &[u8], source: &[u8; 8]) -> Result<Packet> {
+    // Is it something other than an ADS command packet?
+    let ams_cmd = LE::read_u16(buf);
+    if ams_cmd != 0 {
+        // if it's a known packet type, continue
+        if matches!(ams_cmd, 1 | 4096 | 4097 | 4098) {
+            return Ok(Packet::Ignore);
+        }
+        return Err(Error::Reply("reading packet", "invalid packet or unknown AMS command",
+                                ams_cmd as _));
+    }
+
+    // If the header length fields aren't self-consistent, abort the connection.
+    if buf.len() < AMS_HEADER_SIZE {
+        return Err(Error::Reply("reading packet", "inconsistent packet", 0));
+    }
+    let rest_length = LE::read_u32(&buf[26..30]) as usize;
+    if rest_length != buf.len() - AMS_HEADER_SIZE {
+        return Err(Error::Reply("reading packet", "inconsistent packet", 0));
+    }
+
+    // Check that the packet is meant for us.
+    if buf[6..14] != source[..] {
+        return Ok(Packet::Ignore);
+    }
+
+    // If it looks like a reply, the requester will handle further validation.
+    if LE::read_u16(&buf[22..24]) != Command::Notification as u16 {
+        return Ok(Packet::Reply);
+    }
+
+    // Validate notification message fields.
+    let state_flags = LE::read_u16(&buf[24..26]);
+    let error_code = LE::read_u32(&buf[30..34]);
+    if state_flags != 4 {
+        return Ok(Packet::Ignore);
+    }
+    // Let the notification parser report the error.
+    if error_code != 0 {
+        return Ok(Packet::Notification);
+    }
+    if rest_length < 8 {
+        return Ok(Packet::Ignore);
+    }
+    let length = LE::read_u32(&buf[38..42]) as usize;
+    if length != rest_length - 4 {
+        return Ok(Packet::Ignore);
+    }
+    Ok(Packet::Notification)
+}
+
+/// Validate the AMS header of a reply to the given request.
+pub(crate) fn check_reply(command_id: u16, action: &'static str, request: &[u8],
+                          invoke_id: u32, reply: &[u8]) -> Result<()> {
+    // The source netid/port must match what we sent.
+    if reply[14..22] != request[6..14] {
+        return Err(Error::Reply(action, "unexpected source address", 0));
+    }
+    // Read the other fields we need.
+    assert!(reply.len() >= AMS_HEADER_SIZE);
+    let mut ptr = &reply[22..];
+    let ret_cmd = ptr.read_u16::<LE>().expect("size");
+    let state_flags = ptr.read_u16::<LE>().expect("size");
+    ptr = &ptr[4..];  // data length
+    let error_code = ptr.read_u32::<LE>().expect("size");
+    let ret_invoke_id = ptr.read_u32::<LE>().expect("size");
+
+    // Command must match.
+    if ret_cmd != command_id {
+        return Err(Error::Reply(action, "unexpected command", ret_cmd.into()));
+    }
+    // State flags must be the request's flags | 1 (response).
+    if state_flags != LE::read_u16(&request[24..26]) | 1 {
+        return Err(Error::Reply(action, "unexpected state flags", state_flags.into()));
+    }
+    // Invoke ID must match what we sent.
+    if ret_invoke_id != invoke_id {
+        return Err(Error::Reply(action, "unexpected invoke ID", ret_invoke_id));
+    }
+    // Check error code in AMS header.
+    if error_code != 0 {
+        return ads_error(action, error_code);
+    }
+    Ok(())
+}
+
+/// Validate a reply to the given request, and distribute the returned data
+/// into the output buffers.
+///
+/// Returns the length of the returned data.
+pub(crate) fn decode_reply(cmd: Command, request: &[u8], invoke_id: u32, reply: &[u8],
+                           data_out: &mut [&mut [u8]]) -> Result<usize> {
+    check_reply(cmd as u16, cmd.action(), request, invoke_id, reply)?;
+
+    let data_len = LE::read_u32(&reply[26..30]);
+    let result = if reply.len() >= AMS_HEADER_SIZE + 4 {
+        LE::read_u32(&reply[AMS_HEADER_SIZE..])
+    } else {
+        0  // this must be because an error code is already set
+    };
+    // Check result field in payload, only relevant if error_code == 0.
+    if result != 0 {
+        return ads_error(cmd.action(), result);
+    }
+
+    // If we don't want return data, we're done.
+    if data_out.is_empty() {
+        return Ok(0);
+    }
+
+    // Check returned length, it needs to fill at least the first data_out
+    // buffer.  This also ensures that we had a result field.
+    if (data_len as usize) < data_out[0].len() + 4 {
+        return Err(Error::Reply(cmd.action(), "got less data than expected", data_len));
+    }
+
+    // The pure user data length, without the result field.
+    let data_len = data_len as usize - 4;
+
+    // Distribute the data into the user output buffers, up to the returned
+    // data length.
+    let mut offset = AMS_HEADER_SIZE + 4;
+    let mut rest_len = data_len;
+    for buf in data_out {
+        let n = buf.len().min(rest_len);
+        buf[..n].copy_from_slice(&reply[offset..][..n]);
+        offset += n;
+        rest_len -= n;
+        if rest_len == 0 {
+            break;
+        }
+    }
+
+    Ok(data_len)
+}
+
+/// Emit a trace event for an AMS/TCP frame sent to or received from the
+/// server.
+///
+/// The event contains the AMS header fields and, depending on the command,
+/// the index group/offset and length of a request, the number of samples in
+/// a notification, or the result code of a reply.  A hex dump of the whole
+/// frame is emitted as a separate event with the target `ads::dump`, so that
+/// it can be enabled independently.
+#[cfg(feature = "tracing")]
+pub(crate) fn trace_frame(direction: &'static str, buf: &[u8]) {
+    use itertools::Itertools;
+
+    if tracing::enabled!(target: "ads::dump", tracing::Level::TRACE) {
+        let dump = buf.iter().map(|b| format!("{:02x}", b)).join(" ");
+        tracing::trace!(target: "ads::dump", direction = direction, data = dump.as_str(),
+                        "frame data");
+    }
+
+    let ams_cmd = LE::read_u16(buf);
+    if ams_cmd != 0 || buf.len() < AMS_HEADER_SIZE {
+        tracing::trace!(target: "ads", direction = direction, ams_cmd = ams_cmd,
+                        length = buf.len(), "AMS/TCP frame");
+        return;
+    }
+
+    let command = LE::read_u16(&buf[22..24]);
+    let state_flags = LE::read_u16(&buf[24..26]);
+    let length = LE::read_u32(&buf[26..30]);
+    let error_code = LE::read_u32(&buf[30..34]);
+    let invoke_id = LE::read_u32(&buf[34..38]);
+    let word = |i: usize| buf.get(AMS_HEADER_SIZE + 4*i..AMS_HEADER_SIZE + 4*i + 4)
+                             .map(LE::read_u32);
+
+    macro_rules! event {
+        ($($field:tt)*) => {
+            tracing::trace!(target: "ads", direction = direction,
+                            command = command_name(command), command_id = command,
+                            state_flags = state_flags, length = length,
+                            error_code = error_code, invoke_id = invoke_id,
+                            $($field)* "ADS frame")
+        };
+    }
+
+    let is_reply = state_flags & 1 != 0;
+    match (is_reply, command, word(0), word(1), word(2)) {
+        (false, 2 | 3 | 6 | 9, Some(index_group), Some(index_offset), Some(data_length)) =>
+            event!(index_group = index_group, index_offset = index_offset,
+                   data_length = data_length,),
+        (false, 8, _, Some(stamps), _) => event!(stamps = stamps,),
+        (true, _, Some(result), _, _) => event!(result = result,),
+        _ => event!(),
+    }
+}
+
+/// Return a description of the ADS command with the given ID.
+#[cfg(feature = "tracing")]
+fn command_name(command_id: u16) -> &'static str {
+    let command = match command_id {
+        1 => Command::DevInfo,
+        2 => Command::Read,
+        3 => Command::Write,
+        4 => Command::ReadState,
+        5 => Command::WriteControl,
+        6 => Command::AddNotification,
+        7 => Command::DeleteNotification,
+        8 => Command::Notification,
+        9 => Command::ReadWrite,
+        _ => return "unknown command",
+    };
+    command.action()
+}
@@ -29,20 +29,34 @@ pub const RW_SYMVAL_BYHANDLE: u32 = 0xF005;
 /// Release a symbol handle.  Index offset is 0.
 pub const RELEASE_SYMHANDLE: u32 = 0xF006;
 
-// undocumented; from AdsDef.h
-pub const SYMTAB: u32 = 0xF000;
-pub const SYMNAME: u32 = 0xF001;
-pub const SYMVAL: u32 = 0xF002;
+/// Read the value of the symbol with the name in the write data.  Index
+/// offset is 0.  Use with a `write_read` transaction.
 pub const GET_SYMVAL_BYNAME: u32 = 0xF004;
+/// Get index group, offset and size (3x u32) of the symbol with the name in
+/// the write data.  Index offset is 0.  Use with a `write_read` transaction.
 pub const GET_SYMINFO_BYNAME: u32 = 0xF007;
+/// Read the symbol version (u8), which changes with each PLC program
+/// download.  Index offset is 0.
 pub const GET_SYMVERSION: u32 = 0xF008;
+/// Get extended symbol info of the symbol with the name in the write data.
+/// Index offset is 0.  Use with a `write_read` transaction.
 pub const GET_SYMINFO_BYNAME_EX: u32 = 0xF009;
-pub const SYM_DOWNLOAD: u32 = 0xF00A;
+/// Upload the symbol info of all symbols.  Index offset is 0.
 pub const SYM_UPLOAD: u32 = 0xF00B;
+/// Read number and total size of the symbol info (2x u32).
 pub const SYM_UPLOAD_INFO: u32 = 0xF00C;
-pub const SYM_DOWNLOAD2: u32 = 0xF00D;
+/// Upload the type info of all data types.  Index offset is 0.
 pub const SYM_DT_UPLOAD: u32 = 0xF00E;
+/// Read number and total size of the symbol and type info, among others
+/// (24 bytes).
 pub const SYM_UPLOAD_INFO2: u32 = 0xF00F;
+
+// undocumented; from AdsDef.h
+pub const SYMTAB: u32 = 0xF000;
+pub const SYMNAME: u32 = 0xF001;
+pub const SYMVAL: u32 = 0xF002;
+pub const SYM_DOWNLOAD: u32 = 0xF00A;
+pub const SYM_DOWNLOAD2: u32 = 0xF00D;
 pub const SYM_NOTE: u32 = 0xF010;
 
 /// Read/write process image of physical inputs (%I fields).
@@ -107,6 +121,13 @@ pub const TARGET_DESC: u32 = 0x2bc;
 pub const LICENSE: u32 = 0x0101_0004;
 pub const LICENSE_MODULES: u32 = 0x0101_0006;
 
+/// Index group of the time services of the system service.
+///
+/// Index offset 1 reads the local time of the target as a 16-byte Windows
+/// `SYSTEMTIME` structure (eight `u16` values from year to milliseconds).
+/// This is what `NT_GetTime` of the Tc2_Utilities library does.
+pub const SYSTEM_TIME: u32 = 400;
+
 // Diverse officially undocumented ports, used with the system service.
 pub const WIN_REGISTRY: u32 = 200;
 pub const EXECUTE: u32 = 500;
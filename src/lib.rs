@@ -32,6 +32,38 @@
 //! let value: u32 = handle.read_value()?;
 //! println!("MY_SYMBOL value is {}", value);
 //! ```
+//!
+//! # Structured data
+//!
+//! PLC structures can be mapped to Rust structs by deriving the `zerocopy`
+//! traits `FromBytes` and `AsBytes` on them.  Fields are laid out in
+//! declaration order; use `#[repr(C, packed)]` and explicit padding arrays to
+//! match the packing of the PLC, the byte order independent integer types
+//! from `zerocopy::byteorder`, and the string types from `ads::strings` for
+//! `STRING(n)` fields:
+//!
+//! ```rust,ignore
+//! use zerocopy::{AsBytes, FromBytes, byteorder::{LE, U16, U32}};
+//!
+//! #[derive(FromBytes, AsBytes, Default)]
+//! #[repr(C, packed)]
+//! struct Motor {
+//!     enabled: u8,
+//!     _pad: [u8; 1],
+//!     speed: U16<LE>,
+//!     name: ads::strings::String<15>,  // STRING(15), 16 bytes
+//!     errors: U32<LE>,
+//! }
+//!
+//! let motor: Motor = handle.read_value()?;
+//! ```
+//!
+//! Reading a value fails if the PLC returns a different amount of data than
+//! the size of the struct.
+//!
+//! Alternatively, the traits in [`structs`] decode and encode the fields one
+//! by one, which allows plain Rust types for them.  With the `derive`
+//! feature, they can be derived with `#[derive(ads::AdsStruct)]`.
 
 #![deny(missing_docs)]
 #![cfg_attr(not(test), deny(clippy::unwrap_used))]
@@ -45,15 +77,29 @@ pub mod ports;
 pub mod index;
 pub mod file;
 pub mod strings;
+pub mod structs;
 pub mod symbol;
+pub mod transport;
+#[cfg(feature = "tokio")]
+pub mod async_client;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod frame;
 #[cfg(test)]
 mod test;
 
-pub use client::{AdsState, Client, Device, Source, Timeouts};
+// The derive macro refers to this crate as `::ads`, also in its own tests.
+#[cfg(test)]
+extern crate self as ads;
+
+pub use client::{AdsState, Client, Device, MemoryArea, OverflowPolicy, ReconnectPolicy,
+                 RetryPolicy, SharedDevice, Source, Timeouts};
 pub use errors::{Error, Result};
 pub use file::File;
 pub use netid::{AmsAddr, AmsNetId, AmsPort};
 pub use symbol::Handle;
+#[cfg(feature = "derive")]
+pub use ads_derive::AdsStruct;
 
 /// The default port for TCP communication.
 pub const PORT: u16 = 0xBF02;
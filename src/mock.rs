@@ -0,0 +1,487 @@
+//! A mock ADS server, to test code using the client without a PLC.
+//!
+//! This is only available with the `mock` feature.  The server listens on a
+//! local TCP port, and answers requests according to expectations that are
+//! set up beforehand:
+//!
+//! ```rust,ignore
+//! let server = ads::mock::MockServer::new()?;
+//! server.expect_read(0x4020, 0).returns(&[1, 2, 3, 4]);
+//! server.expect_write(0x4020, 4).with_data(&[5, 6]).succeeds();
+//!
+//! let client = ads::Client::new(server.addr(), ads::Timeouts::none(), ads::Source::Auto)?;
+//! let device = client.device(ads::AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+//! assert_eq!(device.read_value::<u32>(0x4020, 0)?, 0x04030201);
+//! device.write(0x4020, 4, &[5, 6])?;
+//!
+//! server.verify();
+//! ```
+//!
+//! Requests for device info, device state and notifications (including
+//! deleting them with a sum-up request) are handled without expectations.
+//! Notifications can be sent to the client with
+//! [`MockServer::send_notification`].
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LE};
+use zerocopy::byteorder::{U16, U32};
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::client::{AddNotif, AdsHeader, Command, IndexLength, IndexLengthRW, ReadState,
+                    WriteControl, AMS_HEADER_SIZE, TCP_HEADER_SIZE};
+use crate::errors::ErrContext;
+use crate::notif::{self, FILETIME_UNIX_EPOCH};
+use crate::{index, AdsState, AmsAddr, Error, Result};
+
+/// The ADS error code returned for unexpected requests.
+pub const UNEXPECTED_REQUEST: u32 = 0x701;
+
+/// A request that the server expects.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Request {
+    cmd: u16,
+    index_group: u32,
+    index_offset: u32,
+    /// If given, the request's write data must match.
+    data: Option<Vec<u8>>,
+}
+
+/// The state shared between the `MockServer` and its thread.
+struct State {
+    /// Not yet met expectations, with the reply data or error code
+    expected: Vec<(Request, std::result::Result<Vec<u8>, u32>)>,
+    /// Descriptions of requests that didn't match any expectation
+    unexpected: Vec<String>,
+    device_info: (String, u8, u8, u16),
+    device_state: (AdsState, u16),
+    /// Number of state reads after a control request that still report the
+    /// old state
+    state_delay: usize,
+    /// New state from a control request, and the reads left until it is
+    /// reported
+    pending_state: Option<((AdsState, u16), usize)>,
+    /// Active notifications with handle, index group and offset
+    notifications: Vec<(notif::Handle, u32, u32)>,
+    next_handle: notif::Handle,
+    /// Client and server address of the last request
+    addrs: Option<(AmsAddr, AmsAddr)>,
+    stopped: bool,
+}
+
+/// A mock ADS server.  See the [module docs](self) for an example.
+///
+/// The server handles one client connection at a time; it is stopped when
+/// the `MockServer` is dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    /// The current client connection, for sending replies and notifications
+    writer: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl MockServer {
+    /// Start a new server on a free port of `127.0.0.1`.
+    pub fn new() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").ctx("binding mock server")?;
+        let addr = listener.local_addr().ctx("binding mock server")?;
+        let state = Arc::new(Mutex::new(State {
+            expected: Vec::new(),
+            unexpected: Vec::new(),
+            device_info: ("Mock device".into(), 1, 0, 1),
+            device_state: (AdsState::Run, 0),
+            state_delay: 0,
+            pending_state: None,
+            notifications: Vec::new(),
+            next_handle: 1,
+            addrs: None,
+            stopped: false,
+        }));
+        let writer = Arc::new(Mutex::new(None));
+        let server = Server { state: state.clone(), writer: writer.clone() };
+        thread::spawn(move || server.run(listener));
+        Ok(Self { addr, state, writer })
+    }
+
+    /// Return the address to connect the client to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Expect a read request for the given index group and offset.
+    ///
+    /// If the reply data is longer than requested, it is truncated.
+    pub fn expect_read(&self, index_group: u32, index_offset: u32) -> Expectation<'_> {
+        self.expect(Command::Read, index_group, index_offset)
+    }
+
+    /// Expect a write request for the given index group and offset.
+    pub fn expect_write(&self, index_group: u32, index_offset: u32) -> Expectation<'_> {
+        self.expect(Command::Write, index_group, index_offset)
+    }
+
+    /// Expect a write/read request for the given index group and offset.
+    ///
+    /// If the reply data is longer than requested, it is truncated.
+    pub fn expect_write_read(&self, index_group: u32, index_offset: u32) -> Expectation<'_> {
+        self.expect(Command::ReadWrite, index_group, index_offset)
+    }
+
+    fn expect(&self, cmd: Command, index_group: u32, index_offset: u32) -> Expectation<'_> {
+        Expectation {
+            server: self,
+            request: Request { cmd: cmd as u16, index_group, index_offset, data: None },
+        }
+    }
+
+    /// Set the device info returned by the server.
+    pub fn set_device_info(&self, name: &str, major: u8, minor: u8, version: u16) {
+        self.lock().device_info = (name.into(), major, minor, version);
+    }
+
+    /// Return the current device state, which can be changed by the client.
+    pub fn device_state(&self) -> (AdsState, u16) {
+        self.lock().device_state
+    }
+
+    /// After a control request, report the old state for this many further
+    /// state reads, like a device that takes some time for the transition.
+    ///
+    /// By default, the new state is reported right away.
+    pub fn set_state_delay(&self, reads: usize) {
+        self.lock().state_delay = reads;
+    }
+
+    /// Return the active notifications, with handle, index group and offset.
+    pub fn notifications(&self) -> Vec<(notif::Handle, u32, u32)> {
+        self.lock().notifications.clone()
+    }
+
+    /// Send a notification with a single sample to the connected client.
+    pub fn send_notification(&self, handle: notif::Handle, data: &[u8]) -> Result<()> {
+        let (client, server) = self.lock().addrs.ok_or_else(
+            || Error::Io("sending notification", io::ErrorKind::NotConnected.into()))?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+                                         .map_or(0, |d| (d.as_nanos() / 100) as u64);
+        let mut payload = Vec::with_capacity(28 + data.len());
+        payload.extend_from_slice(&(24 + data.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&(timestamp + FILETIME_UNIX_EPOCH).to_le_bytes());
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&handle.to_le_bytes());
+        payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        payload.extend_from_slice(data);
+        let frame = encode_frame(client, server, Command::Notification as u16, 4, 0, 0, &payload);
+        match &mut *self.writer.lock().expect("not poisoned") {
+            Some(socket) => socket.write_all(&frame).ctx("sending notification"),
+            None => Err(Error::Io("sending notification", io::ErrorKind::NotConnected.into())),
+        }
+    }
+
+    /// Close the connection to the client, as if the server went away.
+    pub fn disconnect(&self) {
+        if let Some(socket) = self.writer.lock().expect("not poisoned").take() {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Panic if not all expectations have been met, or if any unexpected
+    /// requests were received.
+    pub fn verify(&self) {
+        // Don't panic with the lock held, to not poison it for `drop`.
+        let message = {
+            let state = self.lock();
+            if !state.unexpected.is_empty() {
+                format!("mock server received unexpected requests: {:?}", state.unexpected)
+            } else if !state.expected.is_empty() {
+                let requests: Vec<_> = state.expected.iter().map(|(req, _)| req).collect();
+                format!("mock server did not receive expected requests: {:?}", requests)
+            } else {
+                return;
+            }
+        };
+        panic!("{}", message);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("not poisoned")
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.lock().stopped = true;
+        if let Some(socket) = &*self.writer.lock().expect("not poisoned") {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+        // Wake up the listener thread.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// An expected request, registered with the server by specifying the reply
+/// with one of the `returns`, `succeeds` or `fails` methods.
+#[must_use = "the expectation is only registered by specifying the reply"]
+pub struct Expectation<'s> {
+    server: &'s MockServer,
+    request: Request,
+}
+
+impl Expectation<'_> {
+    /// Only match requests with the given write data.
+    pub fn with_data(mut self, data: &[u8]) -> Self {
+        self.request.data = Some(data.to_vec());
+        self
+    }
+
+    /// Reply successfully with the given data.
+    pub fn returns(self, data: &[u8]) {
+        self.server.lock().expected.push((self.request, Ok(data.to_vec())));
+    }
+
+    /// Reply successfully without data.
+    pub fn succeeds(self) {
+        self.returns(&[])
+    }
+
+    /// Reply with the given ADS error code.
+    pub fn fails(self, error_code: u32) {
+        self.server.lock().expected.push((self.request, Err(error_code)));
+    }
+}
+
+/// Encode a complete frame, with the AMS/TCP and AMS headers.
+fn encode_frame(dest: AmsAddr, src: AmsAddr, cmd: u16, state_flags: u16,
+                error_code: u32, invoke_id: u32, data: &[u8]) -> Vec<u8> {
+    let header = AdsHeader {
+        ams_cmd:     0,
+        length:      U32::new((AMS_HEADER_SIZE - TCP_HEADER_SIZE + data.len()) as u32),
+        dest_netid:  dest.netid(),
+        dest_port:   U16::new(dest.port()),
+        src_netid:   src.netid(),
+        src_port:    U16::new(src.port()),
+        command:     U16::new(cmd),
+        state_flags: U16::new(state_flags),
+        data_length: U32::new(data.len() as u32),
+        error_code:  U32::new(error_code),
+        invoke_id:   U32::new(invoke_id),
+    };
+    let mut frame = header.as_bytes().to_vec();
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// The part of the server running in the background thread.
+struct Server {
+    state: Arc<Mutex<State>>,
+    writer: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl Server {
+    fn run(self, listener: TcpListener) {
+        for socket in listener.incoming() {
+            if self.state.lock().expect("not poisoned").stopped {
+                return;
+            }
+            let socket = match socket {
+                Ok(socket) => socket,
+                Err(_) => continue,
+            };
+            if let Ok(writer) = socket.try_clone() {
+                *self.writer.lock().expect("not poisoned") = Some(writer);
+                // The client learns about errors by its connection closing.
+                let _ = self.handle_client(socket);
+                *self.writer.lock().expect("not poisoned") = None;
+            }
+        }
+    }
+
+    fn handle_client(&self, mut socket: TcpStream) -> io::Result<()> {
+        let mut buf = vec![0; AMS_HEADER_SIZE];
+        loop {
+            buf.resize(TCP_HEADER_SIZE, 0);
+            socket.read_exact(&mut buf)?;
+            let length = LE::read_u32(&buf[2..6]) as usize;
+            buf.resize(TCP_HEADER_SIZE + length, 0);
+            socket.read_exact(&mut buf[TCP_HEADER_SIZE..])?;
+            // Ignore router messages, and anything that is not a request.
+            if LE::read_u16(&buf) != 0 || buf.len() < AMS_HEADER_SIZE {
+                continue;
+            }
+            let header = AdsHeader::read_from(&buf[..AMS_HEADER_SIZE]).expect("size");
+            if header.state_flags.get() & 1 != 0 {
+                continue;
+            }
+
+            let client = AmsAddr::new(header.src_netid, header.src_port.get());
+            let server = AmsAddr::new(header.dest_netid, header.dest_port.get());
+            let (reply, error_code) = self.handle_request(header.command.get(),
+                                                          &buf[AMS_HEADER_SIZE..]);
+            self.state.lock().expect("not poisoned").addrs = Some((client, server));
+            // Requests with the "no return" flag don't get a reply.
+            if header.state_flags.get() & 2 != 0 {
+                continue;
+            }
+
+            let frame = encode_frame(client, server, header.command.get(),
+                                     header.state_flags.get() | 1, error_code,
+                                     header.invoke_id.get(), &reply);
+            if let Some(writer) = &mut *self.writer.lock().expect("not poisoned") {
+                writer.write_all(&frame)?;
+            }
+        }
+    }
+
+    /// Return the reply data and the error code for the AMS header.
+    fn handle_request(&self, cmd: u16, data: &[u8]) -> (Vec<u8>, u32) {
+        let mut state = self.state.lock().expect("not poisoned");
+        let mut reply = 0u32.to_le_bytes().to_vec();
+        match cmd {
+            c if c == Command::DevInfo as u16 => {
+                let (name, major, minor, version) = &state.device_info;
+                reply.extend_from_slice(&[*major, *minor]);
+                reply.extend_from_slice(&version.to_le_bytes());
+                let mut name_buf = [0; 16];
+                let len = name.len().min(15);
+                name_buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+                reply.extend_from_slice(&name_buf);
+            }
+            c if c == Command::ReadState as u16 => {
+                match &mut state.pending_state {
+                    Some((_, 0)) => {
+                        state.device_state = state.pending_state.take().expect("some").0;
+                    }
+                    Some((_, reads)) => *reads -= 1,
+                    None => (),
+                }
+                let (ads_state, dev_state) = state.device_state;
+                reply.extend_from_slice(ReadState {
+                    ads_state: U16::new(ads_state as _),
+                    dev_state: U16::new(dev_state),
+                }.as_bytes());
+            }
+            c if c == Command::WriteControl as u16 => {
+                match WriteControl::read_from_prefix(data) {
+                    Some(ctrl) => {
+                        let ads_state = match AdsState::try_from(ctrl.ads_state.get()) {
+                            Ok(ads_state) => ads_state,
+                            Err(_) => return (vec![], 0x70B),
+                        };
+                        let new = (ads_state, ctrl.dev_state.get());
+                        if state.state_delay == 0 {
+                            state.device_state = new;
+                        } else {
+                            state.pending_state = Some((new, state.state_delay));
+                        }
+                    }
+                    None => return (vec![], 0x706),
+                }
+            }
+            c if c == Command::AddNotification as u16 => {
+                match AddNotif::read_from(data) {
+                    Some(req) => {
+                        let handle = state.next_handle;
+                        state.next_handle += 1;
+                        state.notifications.push((handle, req.index_group.get(),
+                                                  req.index_offset.get()));
+                        reply.extend_from_slice(&handle.to_le_bytes());
+                    }
+                    None => return (vec![], 0x706),
+                }
+            }
+            c if c == Command::DeleteNotification as u16 => {
+                if data.len() != 4 {
+                    return (vec![], 0x706);
+                }
+                reply = state.delete_notification(LE::read_u32(data)).to_le_bytes().to_vec();
+            }
+            c if c == Command::Read as u16 => {
+                let req = match IndexLength::read_from(data) {
+                    Some(req) => req,
+                    None => return (vec![], 0x706),
+                };
+                let request = Request { cmd, index_group: req.index_group.get(),
+                                        index_offset: req.index_offset.get(), data: None };
+                return state.reply_to(request, &[], req.length.get() as usize);
+            }
+            c if c == Command::Write as u16 => {
+                let req = match IndexLength::read_from_prefix(data) {
+                    Some(req) => req,
+                    None => return (vec![], 0x706),
+                };
+                let wdata = &data[size_of::<IndexLength>()..];
+                let request = Request { cmd, index_group: req.index_group.get(),
+                                        index_offset: req.index_offset.get(), data: None };
+                return state.reply_to(request, wdata, 0);
+            }
+            c if c == Command::ReadWrite as u16 => {
+                let req = match IndexLengthRW::read_from_prefix(data) {
+                    Some(req) => req,
+                    None => return (vec![], 0x706),
+                };
+                let wdata = &data[size_of::<IndexLengthRW>()..];
+                if req.index_group.get() == index::SUMUP_DELDEVNOTE {
+                    let count = req.index_offset.get() as usize;
+                    if wdata.len() != 4 * count || req.read_length.get() as usize != 4 * count {
+                        return (vec![], 0x706);
+                    }
+                    reply.extend_from_slice(&(4 * count as u32).to_le_bytes());
+                    for handle in wdata.chunks(4) {
+                        let result = state.delete_notification(LE::read_u32(handle));
+                        reply.extend_from_slice(&result.to_le_bytes());
+                    }
+                    return (reply, 0);
+                }
+                let request = Request { cmd, index_group: req.index_group.get(),
+                                        index_offset: req.index_offset.get(), data: None };
+                return state.reply_to(request, wdata, req.read_length.get() as usize);
+            }
+            _ => return (vec![], UNEXPECTED_REQUEST),
+        }
+        (reply, 0)
+    }
+}
+
+impl State {
+    /// Delete a notification, and return the result code.
+    fn delete_notification(&mut self, handle: notif::Handle) -> u32 {
+        let count = self.notifications.len();
+        self.notifications.retain(|n| n.0 != handle);
+        if self.notifications.len() == count { 0x714 } else { 0 }
+    }
+
+    /// Reply to a read, write or write/read request from the expectations.
+    fn reply_to(&mut self, request: Request, wdata: &[u8], max_len: usize) -> (Vec<u8>, u32) {
+        let matches = |exp: &Request| {
+            exp.cmd == request.cmd && exp.index_group == request.index_group &&
+                exp.index_offset == request.index_offset &&
+                exp.data.as_ref().map_or(true, |data| data == wdata)
+        };
+        let mut reply = Vec::new();
+        match self.expected.iter().position(|(exp, _)| matches(exp)) {
+            Some(idx) => match self.expected.remove(idx).1 {
+                Ok(data) => {
+                    reply.extend_from_slice(&0u32.to_le_bytes());
+                    if request.cmd != Command::Write as u16 {
+                        let data = &data[..data.len().min(max_len)];
+                        reply.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                        reply.extend_from_slice(data);
+                    }
+                }
+                Err(code) => reply.extend_from_slice(&code.to_le_bytes()),
+            },
+            None => {
+                self.unexpected.push(format!("{:?}", Request { data: Some(wdata.to_vec()),
+                                                               ..request }));
+                reply.extend_from_slice(&UNEXPECTED_REQUEST.to_le_bytes());
+            }
+        }
+        (reply, 0)
+    }
+}
@@ -10,6 +10,8 @@ use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use itertools::Itertools;
 use zerocopy::{AsBytes, FromBytes};
 
+use crate::ports::Port;
+
 /// Represents an AMS NetID.
 ///
 /// The NetID consists of 6 bytes commonly written like an IPv4 address, i.e.
@@ -59,14 +61,18 @@ impl FromStr for AmsNetId {
 
     /// Parse a NetID from a string (`a.b.c.d.e.f`).
     ///
-    /// Bytes can be missing in the end; missing bytes are substituted by 1.
+    /// The last two bytes can be missing; they are substituted by 1, as in
+    /// the common convention of deriving the NetID from the IP address.
     fn from_str(s: &str) -> Result<AmsNetId, &'static str> {
         let mut arr = [1; 6];
-        for (i, part) in s.split('.').enumerate() {
-            match (arr.get_mut(i), part.parse()) {
-                (Some(loc), Ok(byte)) => *loc = byte,
-                _ => return Err("invalid NetID string"),
-            }
+        let mut n = 0;
+        for part in s.split('.') {
+            let loc = arr.get_mut(n).ok_or("too many bytes in NetID string")?;
+            *loc = part.parse().map_err(|_| "invalid byte in NetID string")?;
+            n += 1;
+        }
+        if n < 4 {
+            return Err("too few bytes in NetID string");
         }
         Ok(AmsNetId(arr))
     }
@@ -78,6 +84,14 @@ impl From<[u8; 6]> for AmsNetId {
     }
 }
 
+impl From<Ipv4Addr> for AmsNetId {
+    /// Create a NetID from an IPv4 address, with the conventional `.1.1`
+    /// suffix.
+    fn from(ip: Ipv4Addr) -> Self {
+        Self::from_ip(ip, 1, 1)
+    }
+}
+
 impl Display for AmsNetId {
     /// Format a NetID in the usual format.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -85,8 +99,27 @@ impl Display for AmsNetId {
     }
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for AmsNetId {
+    /// Serialize a NetID in the usual string format.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AmsNetId {
+    /// Deserialize a NetID from the usual string format.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 /// Combination of an AMS NetID and a port.
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// It is formatted and parsed as `a.b.c.d.e.f:port`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct AmsAddr(AmsNetId, AmsPort);
 
 impl AmsAddr {
@@ -120,10 +153,24 @@ impl AmsAddr {
     }
 }
 
+impl From<(AmsNetId, AmsPort)> for AmsAddr {
+    fn from((netid, port): (AmsNetId, AmsPort)) -> Self {
+        Self(netid, port)
+    }
+}
+
+impl From<(AmsNetId, Port)> for AmsAddr {
+    fn from((netid, port): (AmsNetId, Port)) -> Self {
+        Self(netid, port.into())
+    }
+}
+
 impl FromStr for AmsAddr {
     type Err = &'static str;
 
     /// Parse an AMS address from a string (netid:port).
+    ///
+    /// The NetID is parsed like with [`AmsNetId::from_str`].
     fn from_str(s: &str) -> Result<AmsAddr, &'static str> {
         let (addr, port) = s.split(':').collect_tuple()
                                        .ok_or("invalid AMS addr string")?;
@@ -137,3 +184,26 @@ impl Display for AmsAddr {
         write!(f, "{}:{}", self.0, self.1)
     }
 }
+
+impl fmt::Debug for AmsAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AmsAddr({})", self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AmsAddr {
+    /// Serialize an AMS address in the usual string format.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AmsAddr {
+    /// Deserialize an AMS address from the usual string format.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
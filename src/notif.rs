@@ -1,26 +1,47 @@
 //! Everything to do with ADS notifications.
 
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
 use std::io;
-use std::time::Duration;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use byteorder::{ReadBytesExt, LE};
+use byteorder::{ByteOrder, ReadBytesExt, LE};
+use crossbeam_channel::Receiver;
+use zerocopy::FromBytes;
 
-use crate::client::AMS_HEADER_SIZE;
-use crate::errors::ErrContext;
-use crate::{Error, Result};
+use crate::client::{BufferPool, AMS_HEADER_SIZE};
+use crate::errors::{ads_error, ErrContext};
+use crate::{Device, Error, Result};
 
 /// A handle to the notification; this can be used to delete the notification later.
 pub type Handle = u32;
 
 /// Attributes for creating a notification.
+///
+/// In the request, both durations are sent as 100ns ticks (see
+/// [`to_ads_ticks`]), the maximum delay first.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Attributes {
     /// Length of data the notification is interested in.
     pub length: usize,
     /// When notification messages should be transmitted.
     pub trans_mode: TransmissionMode,
     /// The maximum delay between change and transmission.
+    ///
+    /// This matters for the on-change modes: the server may collect changes
+    /// for up to this time and send them in one message.  Zero means that
+    /// each change is sent right away.
     pub max_delay: Duration,
     /// The cycle time for checking for changes.
+    ///
+    /// For the cyclic modes, this is the time between notifications; for the
+    /// on-change modes, the interval in which the server checks for changes.
+    /// Zero means every server cycle.
     pub cycle_time: Duration,
 }
 
@@ -30,33 +51,362 @@ impl Attributes {
                max_delay: Duration, cycle_time: Duration) -> Self {
         Self { length, trans_mode, max_delay, cycle_time }
     }
+
+    /// Return attributes for a notification on each change, checked in every
+    /// server cycle and sent without delay.
+    pub fn on_change(length: usize) -> Self {
+        Self::new(length, TransmissionMode::ServerOnChange, Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Return attributes for a notification sent cyclically, without delay.
+    pub fn cyclic(length: usize, cycle_time: Duration) -> Self {
+        Self::new(length, TransmissionMode::ServerCycle, Duration::ZERO, cycle_time)
+    }
+
+    /// Return a builder for notification attributes, which validates the
+    /// durations.
+    pub fn builder() -> AttributesBuilder {
+        AttributesBuilder(Self::cyclic(0, Duration::ZERO))
+    }
+
+    /// Return the cycle time in 100ns ticks, as sent in the request.
+    ///
+    /// Returns an error if the cycle time is too long, see [`to_ads_ticks`].
+    pub fn cycle_ticks(&self) -> Result<u32> {
+        to_ads_ticks(self.cycle_time)
+    }
+
+    /// Return the maximum delay in 100ns ticks, as sent in the request.
+    ///
+    /// Returns an error if the delay is too long, see [`to_ads_ticks`].
+    pub fn max_delay_ticks(&self) -> Result<u32> {
+        to_ads_ticks(self.max_delay)
+    }
+}
+
+/// A builder for notification [`Attributes`].
+///
+/// By default, the attributes specify cyclic transmission in every server
+/// cycle, with a data length of 0.
+#[derive(Clone, Debug)]
+pub struct AttributesBuilder(Attributes);
+
+impl AttributesBuilder {
+    /// Set the length of data the notification is interested in.
+    pub fn length(mut self, length: usize) -> Self {
+        self.0.length = length;
+        self
+    }
+
+    /// Set the transmission mode.
+    pub fn trans_mode(mut self, trans_mode: TransmissionMode) -> Self {
+        self.0.trans_mode = trans_mode;
+        self
+    }
+
+    /// Transmit on changes, instead of cyclically.
+    pub fn on_change(self) -> Self {
+        self.trans_mode(TransmissionMode::ServerOnChange)
+    }
+
+    /// Set the cycle time, for transmission or checking for changes.
+    pub fn cycle(mut self, cycle_time: Duration) -> Self {
+        self.0.cycle_time = cycle_time;
+        self
+    }
+
+    /// Set the maximum delay between change and transmission.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.0.max_delay = max_delay;
+        self
+    }
+
+    /// Return the attributes, or an error if a duration or length can't be
+    /// represented in the ADS request.
+    pub fn build(self) -> Result<Attributes> {
+        let _: u32 = self.0.length.try_into()?;
+        self.0.max_delay_ticks()?;
+        self.0.cycle_ticks()?;
+        Ok(self.0)
+    }
+}
+
+/// Convert a duration to the 100ns ticks used by ADS for notification delays
+/// and cycle times.
+///
+/// Returns an error if the duration exceeds the range of a `u32`, i.e. is
+/// longer than about 429 seconds.  Durations below 100ns are rounded down.
+pub fn to_ads_ticks(duration: Duration) -> Result<u32> {
+    Ok((duration.as_nanos() / 100).try_into()?)
 }
 
 /// When notifications should be generated.
 #[repr(u32)]
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TransmissionMode {
     /// No transmission.
     NoTrans = 0,
+    /// Notify each client cycle (legacy, used by TwinCAT 2).
+    ClientCycle = 1,
+    /// Notify when the content changes, checked each client cycle (legacy,
+    /// used by TwinCAT 2).
+    ClientOnChange = 2,
     /// Notify each server cycle.
     ServerCycle = 3,
     /// Notify when the content changes.
     ServerOnChange = 4,
-    // Other constants from the C++ library:
-    // ClientCycle = 1,
-    // ClientOnChange = 2,
-    // ServerCycle2 = 5,
-    // ServerOnChange2 = 6,
-    // Client1Req = 10,
+    /// Notify each server cycle (variant from the C++ library).
+    ServerCycle2 = 5,
+    /// Notify when the content changes (variant from the C++ library).
+    ServerOnChange2 = 6,
+    /// Notify once on client request.
+    Client1Req = 10,
+}
+
+/// A notification handle that deletes the notification automatically on drop.
+///
+/// Note that the guard borrows the `Client`, so it can't outlive it.
+pub struct NotificationGuard<'c> {
+    device: Device<'c>,
+    handle: Handle,
+    // Released after the notification has been deleted.
+    _symbol: Option<crate::symbol::Handle<'c>>,
+}
+
+impl<'c> NotificationGuard<'c> {
+    /// Add a notification for some index group/offset.
+    ///
+    /// See [`Device::add_notification`] for details.
+    pub fn new(device: Device<'c>, index_group: u32, index_offset: u32,
+               attributes: &Attributes) -> Result<Self> {
+        let handle = device.add_notification(index_group, index_offset, attributes)?;
+        Ok(Self { device, handle, _symbol: None })
+    }
+
+    /// Add a notification for the symbol behind a symbol handle, which is
+    /// kept alive as long as the guard.
+    pub(crate) fn with_symbol(device: Device<'c>, symbol: crate::symbol::Handle<'c>,
+                              attributes: &Attributes) -> Result<Self> {
+        let handle = device.add_notification(crate::index::RW_SYMVAL_BYHANDLE,
+                                             symbol.raw(), attributes)?;
+        Ok(Self { device, handle, _symbol: Some(symbol) })
+    }
+
+    /// Return the raw handle, which is used to identify samples.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// Return the raw handle, without deleting the notification.
+    ///
+    /// The notification can be deleted manually using
+    /// [`Device::delete_notification`], otherwise it is deleted when the
+    /// `Client` is dropped.  A symbol handle held by the guard is not
+    /// released.
+    pub fn into_handle(self) -> Handle {
+        let handle = self.handle;
+        std::mem::forget(self);
+        handle
+    }
+}
+
+impl Drop for NotificationGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.device.delete_notification(self.handle);
+    }
+}
+
+/// A receiver for notifications that reports why the connection was lost,
+/// created by [`Client::notification_receiver`](crate::Client::notification_receiver).
+///
+/// When the connection fails, the reader thread quits and the channel is
+/// closed; `recv` then returns the error that caused it.  If the client was
+/// closed, `recv` returns `Ok(None)` instead.  The iterator yields the error
+/// once, and then ends.
+///
+/// With a reconnecting client, the channel stays open across connections.
+pub struct NotificationReceiver {
+    recv: Receiver<Notification>,
+    last_error: Arc<Mutex<Option<Error>>>,
+    done: bool,
+}
+
+impl NotificationReceiver {
+    pub(crate) fn new(recv: Receiver<Notification>,
+                      last_error: Arc<Mutex<Option<Error>>>) -> Self {
+        Self { recv, last_error, done: false }
+    }
+
+    /// Wait for the next notification.
+    ///
+    /// The returned error is a copy of the one that closed the connection.
+    /// For IO errors, it has the same kind and message, but the underlying
+    /// error (the `source()`) is not kept.
+    pub fn recv(&self) -> Result<Option<Notification>> {
+        match self.recv.recv() {
+            Ok(notif) => Ok(Some(notif)),
+            Err(_) => match &*self.last_error.lock().expect("not poisoned") {
+                Some(err) => Err(err.duplicate()),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+impl Iterator for NotificationReceiver {
+    type Item = Result<Notification>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.recv().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
 }
 
 /// A notification message from the ADS server.
+///
+/// The message owns its data, so it is `Send` and can be passed to another
+/// thread for parsing.  Cloning copies the data.
 pub struct Notification {
     data: Vec<u8>,
     nstamps: u32,
+    /// If received by a client, the pool to return the buffer to
+    pool: Option<Arc<BufferPool>>,
+}
+
+impl Drop for Notification {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+impl Clone for Notification {
+    fn clone(&self) -> Self {
+        Self { data: self.data.clone(), nstamps: self.nstamps, pool: self.pool.clone() }
+    }
 }
 
 impl std::fmt::Debug for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.borrowed().fmt(f)
+    }
+}
+
+impl Notification {
+    /// Parse a notification message from an ADS message.
+    pub fn new(data: impl Into<Vec<u8>>) -> Result<Self> {
+        let data = data.into();
+        let nstamps = NotificationRef::new(&data)?.nstamps;
+        Ok(Self { data, nstamps, pool: None })
+    }
+
+    /// Parse a notification message, whose buffer is returned to the pool
+    /// when the notification is dropped.
+    pub(crate) fn new_pooled(data: Vec<u8>, pool: Arc<BufferPool>) -> Result<Self> {
+        let mut notif = Self::new(data)?;
+        notif.pool = Some(pool);
+        Ok(notif)
+    }
+
+    /// Return a borrowed view of this notification.
+    pub fn borrowed(&self) -> NotificationRef<'_> {
+        NotificationRef { data: &self.data, nstamps: self.nstamps }
+    }
+
+    /// Return an iterator over all data samples in this notification.
+    ///
+    /// The structure of the message has already been checked on creation, so
+    /// this will yield all samples.  Should the data nevertheless be
+    /// malformed, iteration stops at that point.  Use
+    /// [`try_samples`](Self::try_samples) to get an error instead.
+    pub fn samples(&self) -> SampleIter<'_> {
+        self.borrowed().samples()
+    }
+
+    /// Return an iterator over all data samples in this notification, which
+    /// yields an `Err` and then stops if the data is malformed.
+    pub fn try_samples(&self) -> TrySampleIter<'_> {
+        self.borrowed().try_samples()
+    }
+
+    /// Return all samples in this notification, grouped by their handle.
+    ///
+    /// See [`NotificationRef::by_handle`] for details.
+    pub fn by_handle(&self) -> HashMap<Handle, Vec<Sample<'_>>> {
+        self.borrowed().by_handle()
+    }
+
+    /// Return an iterator over the samples for a single handle.
+    ///
+    /// See [`NotificationRef::samples_for`] for details.
+    pub fn samples_for(&self, handle: Handle) -> impl Iterator<Item = Sample<'_>> {
+        self.borrowed().samples_for(handle)
+    }
+
+    /// Return the number of stamps (groups of samples with the same
+    /// timestamp) in this notification.
+    pub fn stamp_count(&self) -> u32 {
+        self.nstamps
+    }
+
+    /// Return the total number of samples in this notification.
+    ///
+    /// See [`NotificationRef::sample_count`] for details.
+    pub fn sample_count(&self) -> usize {
+        self.borrowed().sample_count()
+    }
+
+    /// Return an iterator over the stamps in this notification.
+    ///
+    /// See [`NotificationRef::stamps`] for details.
+    pub fn stamps(&self) -> StampIter<'_> {
+        self.borrowed().stamps()
+    }
+
+    /// Return the raw framing of this notification message.
+    ///
+    /// See [`NotificationRef::header`] for details.
+    pub fn header(&self) -> NotificationHeader {
+        self.borrowed().header()
+    }
+
+    /// Consume the notification and return an iterator over owned copies of
+    /// its samples.
+    ///
+    /// Unlike [`Sample`], the [`OwnedSample`]s can be sent across channels,
+    /// which allows flattening a stream of notifications into a stream of
+    /// samples.
+    pub fn into_samples(self) -> impl Iterator<Item = OwnedSample> {
+        self.to_owned_samples().into_iter()
+    }
+
+    /// Return owned copies of all samples, see [`into_samples`](Self::into_samples).
+    pub fn to_owned_samples(&self) -> Vec<OwnedSample> {
+        self.samples().map(|sample| sample.into_owned()).collect()
+    }
+}
+
+/// A notification message from the ADS server that borrows its data from an
+/// external buffer.
+///
+/// This is useful to avoid allocating a new `Vec` for each message, e.g.
+/// when reading many messages into the same scratch buffer.  The samples
+/// yielded by [`samples`](Self::samples) borrow from that buffer, not from
+/// the `NotificationRef`, so they can outlive it, but the buffer can only be
+/// reused once all samples are dropped.
+#[derive(Clone, Copy)]
+pub struct NotificationRef<'a> {
+    data: &'a [u8],
+    nstamps: u32,
+}
+
+impl std::fmt::Debug for NotificationRef<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Notification [")?;
         for sample in self.samples() {
@@ -66,16 +416,29 @@ impl std::fmt::Debug for Notification {
     }
 }
 
-impl Notification {
+impl<'a> NotificationRef<'a> {
     /// Parse a notification message from an ADS message.
-    pub fn new(data: impl Into<Vec<u8>>) -> Result<Self> {
-        // Relevant data starts at byte 42 with the number of stamps.
-        let data = data.into();
+    ///
+    /// If the AMS header carries a nonzero error code (e.g. 0x70A if the
+    /// server ran out of memory for notifications), it is returned as
+    /// `Error::Ads`.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        // Relevant data starts at byte 38 with the data length, followed by
+        // the number of stamps.
         if data.len() < AMS_HEADER_SIZE + 8 {  // header + length + #stamps
-            return Err(Error::Io("parsing notification",
-                                 io::ErrorKind::UnexpectedEof.into()));
+            return Err(Error::Reply("parsing notification", "notification too short",
+                                    data.len() as u32));
+        }
+        let error_code = LE::read_u32(&data[30..34]);
+        if error_code != 0 {
+            return ads_error("parsing notification", error_code);
+        }
+        let mut ptr = &data[AMS_HEADER_SIZE..];
+        let length = ptr.read_u32::<LE>().ctx("parsing notification")?;
+        if length as usize != ptr.len() {
+            return Err(Error::Reply("parsing notification", "notification length mismatch",
+                                    length));
         }
-        let mut ptr = &data[AMS_HEADER_SIZE + 4..];
         let nstamps = ptr.read_u32::<LE>().ctx("parsing notification")?;
         for _ in 0..nstamps {
             let _timestamp = ptr.read_u64::<LE>().ctx("parsing notification")?;
@@ -100,10 +463,122 @@ impl Notification {
         }
     }
 
+    /// Return an owned copy of this notification.
+    pub fn to_notification(&self) -> Notification {
+        Notification { data: self.data.to_vec(), nstamps: self.nstamps, pool: None }
+    }
+
     /// Return an iterator over all data samples in this notification.
-    pub fn samples(&self) -> SampleIter<'_> {
-        SampleIter { data: &self.data[46..], cur_timestamp: 0,
-                     stamps_left: self.nstamps, samples_left: 0 }
+    ///
+    /// See [`Notification::samples`] for details.
+    pub fn samples(&self) -> SampleIter<'a> {
+        SampleIter::new(&self.data[AMS_HEADER_SIZE + 8..], self.nstamps)
+    }
+
+    /// Return an iterator over all data samples in this notification, which
+    /// yields an `Err` and then stops if the data is malformed.
+    pub fn try_samples(&self) -> TrySampleIter<'a> {
+        TrySampleIter(self.samples())
+    }
+
+    /// Return all samples in this notification, grouped by their handle.
+    ///
+    /// The same handle can occur in several stamps; all of its samples are
+    /// kept, in the order of the message (i.e. by timestamp).
+    pub fn by_handle(&self) -> HashMap<Handle, Vec<Sample<'a>>> {
+        let mut map = HashMap::<_, Vec<_>>::new();
+        for sample in self.samples() {
+            map.entry(sample.handle).or_default().push(sample);
+        }
+        map
+    }
+
+    /// Return an iterator over the samples for a single handle.
+    ///
+    /// With cyclic transmission, the server can put several samples for the
+    /// same handle (with different timestamps) into one message.  They are
+    /// yielded in the order of the message, i.e. by timestamp.  If there are
+    /// no samples for the handle, the iterator is empty.
+    pub fn samples_for(&self, handle: Handle) -> impl Iterator<Item = Sample<'a>> {
+        self.samples().filter(move |sample| sample.handle == handle)
+    }
+
+    /// Return the number of stamps (groups of samples with the same
+    /// timestamp) in this notification.
+    pub fn stamp_count(&self) -> u32 {
+        self.nstamps
+    }
+
+    /// Return the total number of samples in this notification.
+    ///
+    /// Only the sample counts of the stamps are read and the sample data is
+    /// skipped, so this is cheaper than counting [`samples`](Self::samples).
+    pub fn sample_count(&self) -> usize {
+        self.stamps().map(|(_, nsamples)| nsamples as usize).sum()
+    }
+
+    /// Return an iterator over the stamps in this notification, yielding the
+    /// timestamp and the number of samples for each stamp.
+    ///
+    /// The sample data is skipped, so this is cheaper than going through
+    /// [`samples`](Self::samples).
+    pub fn stamps(&self) -> StampIter<'a> {
+        StampIter { data: &self.data[AMS_HEADER_SIZE + 8..], stamps_left: self.nstamps }
+    }
+
+    /// Return the raw framing of this notification message.
+    ///
+    /// This is meant for debugging, e.g. to compare with a network capture.
+    pub fn header(&self) -> NotificationHeader {
+        let mut stamp_ranges = Vec::with_capacity(self.nstamps as usize);
+        let mut sample_ranges = Vec::new();
+        // The structure has been validated on creation.
+        let mut pos = AMS_HEADER_SIZE + 8;
+        for _ in 0..self.nstamps {
+            let start = pos;
+            let nsamples = LE::read_u32(&self.data[pos + 8..]);
+            pos += 12;
+            for _ in 0..nsamples {
+                let length = LE::read_u32(&self.data[pos + 4..]) as usize;
+                sample_ranges.push(pos..pos + 8 + length);
+                pos += 8 + length;
+            }
+            stamp_ranges.push(start..pos);
+        }
+        NotificationHeader {
+            length: LE::read_u32(&self.data[AMS_HEADER_SIZE..]),
+            stamps: self.nstamps,
+            stamp_ranges,
+            sample_ranges,
+        }
+    }
+}
+
+/// The raw framing of a notification message, as returned by
+/// [`Notification::header`].
+///
+/// All byte ranges are relative to the start of the message, including the
+/// AMS/TCP and AMS headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotificationHeader {
+    /// The declared length of the notification data.
+    pub length: u32,
+    /// The number of stamps.
+    pub stamps: u32,
+    stamp_ranges: Vec<Range<usize>>,
+    sample_ranges: Vec<Range<usize>>,
+}
+
+impl NotificationHeader {
+    /// Return the byte range of each stamp, including its timestamp, number
+    /// of samples and all samples.
+    pub fn stamp_ranges(&self) -> &[Range<usize>] {
+        &self.stamp_ranges
+    }
+
+    /// Return the byte range of each sample, including its handle and length.
+    pub fn sample_ranges(&self) -> &[Range<usize>] {
+        &self.sample_ranges
     }
 }
 
@@ -112,12 +587,127 @@ impl Notification {
 pub struct Sample<'a> {
     /// The notification handle associated with the data.
     pub handle: Handle,
-    /// Timestamp of generation (nanoseconds since 01/01/1601).
-    pub timestamp: u64, // TODO: better dtype?
+    /// Timestamp of generation (in units of 100ns since 01/01/1601, i.e. a
+    /// Windows FILETIME).  Use [`system_time`](Self::system_time) to convert.
+    pub timestamp: u64,
     /// Data of the handle at the specified time.
     pub data: &'a [u8],
 }
 
+impl<'a> Sample<'a> {
+    /// Return the timestamp of generation as a `SystemTime`.
+    pub fn system_time(&self) -> SystemTime {
+        filetime_to_system_time(self.timestamp)
+    }
+
+    /// Return the timestamp of generation as a `chrono::DateTime<Utc>`.
+    #[cfg(feature = "chrono")]
+    pub fn datetime(&self) -> chrono::DateTime<chrono::Utc> {
+        filetime_to_datetime(self.timestamp)
+    }
+
+    /// Decode the sample data as the given type.
+    ///
+    /// Any type that supports `zerocopy::FromBytes` can be decoded, as with
+    /// [`Device::read_value`].  An error is returned if the data length does
+    /// not match the size of the type.
+    ///
+    /// Note: to be independent of the host's byte order, use the integer types
+    /// defined in `zerocopy::byteorder`.
+    pub fn read_value<T: FromBytes>(&self) -> Result<T> {
+        T::read_from(self.data).ok_or_else(
+            || Error::Reply("decoding sample", "data length does not match type size",
+                            self.data.len() as u32))
+    }
+
+    /// Return the sample data, if it has exactly `len` bytes.
+    ///
+    /// Otherwise, an `Error::Reply` with the actual length is returned.  This
+    /// catches e.g. variables whose type in the PLC has changed.
+    pub fn expect_len(&self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() == len {
+            Ok(self.data)
+        } else {
+            Err(Error::Reply("checking sample length", "unexpected sample length",
+                             self.data.len() as u32))
+        }
+    }
+
+    /// Unpack the sample data into single bits.
+    ///
+    /// The bits of each byte are returned least significant bit first, and
+    /// the bytes in order.  For a little-endian `WORD`, this means that
+    /// element `i` of the result is bit `i` of the value.
+    pub fn to_bits(&self) -> Vec<bool> {
+        unpack_bits(self.data, self.data.len() * 8)
+    }
+
+    /// Return a copy of the sample that owns its data.
+    pub fn into_owned(self) -> OwnedSample {
+        OwnedSample { handle: self.handle, timestamp: self.timestamp, data: self.data.to_vec() }
+    }
+}
+
+/// A single sample from a notification message, which owns its data.
+///
+/// This is returned by [`Notification::into_samples`] and
+/// [`Sample::into_owned`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedSample {
+    /// The notification handle associated with the data.
+    pub handle: Handle,
+    /// Timestamp of generation, see [`Sample::timestamp`].
+    pub timestamp: u64,
+    /// Data of the handle at the specified time.
+    pub data: Vec<u8>,
+}
+
+impl OwnedSample {
+    /// Return a borrowed view of this sample.
+    pub fn borrowed(&self) -> Sample<'_> {
+        Sample { handle: self.handle, timestamp: self.timestamp, data: &self.data }
+    }
+}
+
+/// Unpack the first `count` bits of `data`, least significant bit first
+/// within each byte.
+pub(crate) fn unpack_bits(data: &[u8], count: usize) -> Vec<bool> {
+    (0..count).map(|i| data[i / 8] & (1 << (i % 8)) != 0).collect()
+}
+
+/// Number of 100ns intervals between 01/01/1601 and the UNIX epoch.
+pub(crate) const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Convert a raw ADS timestamp (a Windows FILETIME, in units of 100ns since
+/// 01/01/1601) to a `SystemTime`.
+pub fn filetime_to_system_time(raw: u64) -> SystemTime {
+    fn ticks_to_duration(ticks: u64) -> Duration {
+        Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100)
+    }
+
+    if raw >= FILETIME_UNIX_EPOCH {
+        UNIX_EPOCH + ticks_to_duration(raw - FILETIME_UNIX_EPOCH)
+    } else {
+        // Can't happen on a correctly set up system, but the clock might be
+        // way off.  Clamp instead of panicking if the platform can't go back
+        // that far.
+        UNIX_EPOCH.checked_sub(ticks_to_duration(FILETIME_UNIX_EPOCH - raw))
+                  .unwrap_or(UNIX_EPOCH)
+    }
+}
+
+/// Convert a raw ADS timestamp (a Windows FILETIME, in units of 100ns since
+/// 01/01/1601) to a `chrono::DateTime<Utc>`.
+#[cfg(feature = "chrono")]
+pub fn filetime_to_datetime(raw: u64) -> chrono::DateTime<chrono::Utc> {
+    use chrono::TimeZone;
+
+    // The whole u64 range lies well within chrono's supported range.
+    let secs = (raw / 10_000_000) as i64 - (FILETIME_UNIX_EPOCH / 10_000_000) as i64;
+    let nanos = (raw % 10_000_000) as u32 * 100;
+    chrono::Utc.timestamp_opt(secs, nanos).single().expect("timestamp in range")
+}
+
 /// An iterator over all samples within a notification message.
 pub struct SampleIter<'a> {
     data: &'a [u8],
@@ -126,27 +716,595 @@ pub struct SampleIter<'a> {
     samples_left: u32,
 }
 
+impl<'a> SampleIter<'a> {
+    fn new(data: &'a [u8], nstamps: u32) -> Self {
+        Self { data, cur_timestamp: 0, stamps_left: nstamps, samples_left: 0 }
+    }
+
+    fn next_sample(&mut self) -> Result<Option<Sample<'a>>> {
+        let result = self.next_sample_inner();
+        if result.is_err() {
+            // Don't try to continue reading after garbage.
+            self.data = &[];
+            self.stamps_left = 0;
+            self.samples_left = 0;
+        }
+        result
+    }
+
+    fn next_sample_inner(&mut self) -> Result<Option<Sample<'a>>> {
+        let ctx = "parsing notification";
+        while self.samples_left == 0 {
+            if self.stamps_left == 0 {
+                // Nothing else here, but there shouldn't be data left either.
+                if !self.data.is_empty() {
+                    return Err(Error::Reply(ctx, "unexpected data after samples",
+                                            self.data.len() as u32));
+                }
+                return Ok(None);
+            }
+            // Go to next stamp.
+            self.cur_timestamp = self.data.read_u64::<LE>().ctx(ctx)?;
+            self.samples_left = self.data.read_u32::<LE>().ctx(ctx)?;
+            self.stamps_left -= 1;
+        }
+        // Read more samples from the current stamp.
+        let handle = self.data.read_u32::<LE>().ctx(ctx)?;
+        let length = self.data.read_u32::<LE>().ctx(ctx)? as usize;
+        if length > self.data.len() {
+            return Err(Error::Reply(ctx, "sample length exceeds data", length as u32));
+        }
+        let (data, rest) = self.data.split_at(length);
+        self.data = rest;
+        self.samples_left -= 1;
+        Ok(Some(Sample { handle, data, timestamp: self.cur_timestamp }))
+    }
+}
+
 impl<'a> Iterator for SampleIter<'a> {
     type Item = Sample<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.samples_left > 0 {
-            // Read more samples from the current stamp.
-            let handle = self.data.read_u32::<LE>().expect("size");
-            let length = self.data.read_u32::<LE>().expect("size") as usize;
-            let (data, rest) = self.data.split_at(length);
-            self.data = rest;
-            self.samples_left -= 1;
-            Some(Sample { handle, data, timestamp: self.cur_timestamp })
-        } else if self.stamps_left > 0 {
-            // Go to next stamp.
-            self.cur_timestamp = self.data.read_u64::<LE>().expect("size");
-            self.samples_left = self.data.read_u32::<LE>().expect("size");
-            self.stamps_left -= 1;
-            self.next()
-        } else {
-            // Nothing else here.
-            None
+        self.next_sample().unwrap_or(None)
+    }
+}
+
+/// An iterator over the stamps within a notification message.
+///
+/// Yields the timestamp and the number of samples of each stamp.
+pub struct StampIter<'a> {
+    data: &'a [u8],
+    stamps_left: u32,
+}
+
+impl StampIter<'_> {
+    fn next_stamp(&mut self) -> Option<(u64, u32)> {
+        let timestamp = self.data.read_u64::<LE>().ok()?;
+        let nsamples = self.data.read_u32::<LE>().ok()?;
+        for _ in 0..nsamples {
+            let _handle = self.data.read_u32::<LE>().ok()?;
+            let length = self.data.read_u32::<LE>().ok()? as usize;
+            self.data = self.data.get(length..)?;
+        }
+        Some((timestamp, nsamples))
+    }
+}
+
+impl<'a> Iterator for StampIter<'a> {
+    type Item = (u64, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stamps_left == 0 {
+            return None;
         }
+        let result = self.next_stamp();
+        // Don't try to continue reading after garbage.
+        self.stamps_left = if result.is_some() { self.stamps_left - 1 } else { 0 };
+        result
+    }
+}
+
+/// An iterator over all samples within a notification message, yielding
+/// an error if the message data is malformed.
+pub struct TrySampleIter<'a>(SampleIter<'a>);
+
+impl<'a> Iterator for TrySampleIter<'a> {
+    type Item = Result<Sample<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_sample().transpose()
     }
 }
+
+/// A notification callback, see [`Dispatcher`].
+type Callback = Box<dyn Fn(&Sample) + Send>;
+
+/// Dispatches the samples of incoming notifications to callbacks registered
+/// for their handles.
+///
+/// The dispatcher can be driven from your own loop by calling
+/// [`dispatch`](Self::dispatch) for each notification, or run in a background
+/// thread using [`spawn`](Self::spawn).  Clones of a dispatcher share the
+/// same callbacks, so callbacks can be registered while it is running.
+///
+/// If a callback panics, the panic is caught (and reported by the panic hook),
+/// and dispatching continues.  Callbacks must not register or unregister
+/// other callbacks.
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    callbacks: Arc<Mutex<HashMap<Handle, Callback>>>,
+}
+
+impl Dispatcher {
+    /// Create a new dispatcher without any callbacks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a callback for the given notification handle, replacing any
+    /// previously registered one.
+    pub fn register(&self, handle: Handle, callback: impl Fn(&Sample) + Send + 'static) {
+        self.callbacks.lock().expect("not poisoned").insert(handle, Box::new(callback));
+    }
+
+    /// Remove the callback for the given handle.  Returns true if there was a
+    /// callback registered.
+    pub fn unregister(&self, handle: Handle) -> bool {
+        self.callbacks.lock().expect("not poisoned").remove(&handle).is_some()
+    }
+
+    /// Call the registered callbacks for all samples in the notification.
+    ///
+    /// Samples for handles without a callback are ignored.
+    pub fn dispatch(&self, notification: &Notification) {
+        let callbacks = self.callbacks.lock().expect("not poisoned");
+        for sample in notification.samples() {
+            if let Some(callback) = callbacks.get(&sample.handle) {
+                // The panic hook has already reported the panic.
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(&sample)));
+            }
+        }
+    }
+
+    /// Dispatch all notifications from the channel, until it is disconnected.
+    pub fn run(&self, channel: Receiver<Notification>) {
+        for notification in channel {
+            self.dispatch(&notification);
+        }
+    }
+
+    /// Dispatch all notifications from the channel in a background thread,
+    /// until it is disconnected.
+    pub fn spawn(&self, channel: Receiver<Notification>) -> JoinHandle<()> {
+        let dispatcher = self.clone();
+        thread::spawn(move || dispatcher.run(channel))
+    }
+}
+
+/// Detects missed cycles of a cyclic notification, from the timestamps of
+/// successive samples.
+///
+/// ADS notifications carry no sequence numbers, so samples lost e.g. due to
+/// a buffer overflow can only be detected by comparing the time between two
+/// samples with the expected cycle time.  Differences up to `tolerance` are
+/// attributed to jitter; it should be well below half the cycle time.
+#[derive(Clone, Debug)]
+pub struct NotificationSequencer {
+    handle: Handle,
+    cycle: u64,
+    tolerance: u64,
+    last: Option<u64>,
+}
+
+impl NotificationSequencer {
+    /// Create a new sequencer for the samples of the given notification handle.
+    pub fn new(handle: Handle, cycle_time: Duration, tolerance: Duration) -> Self {
+        Self {
+            handle,
+            // Timestamps are in units of 100ns.
+            cycle: (cycle_time.as_nanos() / 100).clamp(1, u64::MAX as u128) as u64,
+            tolerance: (tolerance.as_nanos() / 100).min(u64::MAX as u128) as u64,
+            last: None,
+        }
+    }
+
+    /// Process all samples for our handle in the notification, and return the
+    /// number of cycles missed since the previous sample.
+    pub fn feed(&mut self, notification: &Notification) -> u64 {
+        let handle = self.handle;
+        notification.samples()
+                    .filter(|sample| sample.handle == handle)
+                    .map(|sample| self.feed_timestamp(sample.timestamp))
+                    .sum()
+    }
+
+    /// Process a single raw sample timestamp, and return the number of cycles
+    /// missed since the previous one.
+    pub fn feed_timestamp(&mut self, timestamp: u64) -> u64 {
+        let missed = match self.last {
+            Some(last) if timestamp > last => {
+                let cycles = (timestamp - last).saturating_add(self.tolerance) / self.cycle;
+                cycles.saturating_sub(1)
+            }
+            _ => 0,
+        };
+        self.last = Some(timestamp);
+        missed
+    }
+
+    /// Forget the previous sample, e.g. after the notification was re-added.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Records the samples of notifications as a time series per handle.
+///
+/// Each sample is stored with its timestamp converted to a `SystemTime` and
+/// a copy of its data.  If a maximum history is set, only the most recent
+/// samples of each handle are kept.
+#[derive(Clone, Debug, Default)]
+pub struct NotificationRecorder {
+    series: HashMap<Handle, VecDeque<(SystemTime, Vec<u8>)>>,
+    max_history: Option<usize>,
+}
+
+impl NotificationRecorder {
+    /// Create a new recorder that keeps all samples.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new recorder that keeps at most `max_history` samples per
+    /// handle, discarding the oldest ones.
+    pub fn with_max_history(max_history: usize) -> Self {
+        Self { series: HashMap::new(), max_history: Some(max_history) }
+    }
+
+    /// Append all samples in the notification to the series of their handles.
+    pub fn push(&mut self, notification: &Notification) {
+        for sample in notification.samples() {
+            let series = self.series.entry(sample.handle).or_default();
+            series.push_back((sample.system_time(), sample.data.to_vec()));
+            if let Some(max_history) = self.max_history {
+                while series.len() > max_history {
+                    series.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Return the number of samples currently recorded for the handle.
+    pub fn len(&self, handle: Handle) -> usize {
+        self.series.get(&handle).map_or(0, |series| series.len())
+    }
+
+    /// Return true if no samples are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.series.values().all(|series| series.is_empty())
+    }
+
+    /// Take the recorded series, oldest sample first, leaving the recorder
+    /// empty.
+    pub fn drain(&mut self) -> HashMap<Handle, Vec<(SystemTime, Vec<u8>)>> {
+        self.series.drain().map(|(handle, series)| (handle, series.into())).collect()
+    }
+}
+
+#[test]
+fn test_filetime() {
+    // 2020-01-01T00:00:00Z
+    assert_eq!(filetime_to_system_time(132_223_104_000_000_000),
+               UNIX_EPOCH + Duration::from_secs(1_577_836_800));
+    assert_eq!(filetime_to_system_time(FILETIME_UNIX_EPOCH + 15),
+               UNIX_EPOCH + Duration::from_nanos(1500));
+    assert_eq!(filetime_to_system_time(FILETIME_UNIX_EPOCH - 10_000_000),
+               UNIX_EPOCH - Duration::from_secs(1));
+    // must not panic
+    let _ = filetime_to_system_time(0);
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_filetime_datetime() {
+    let dt = filetime_to_datetime(132_223_104_000_000_000);
+    assert_eq!(dt, "2020-01-01T00:00:00Z".parse::<chrono::DateTime<chrono::Utc>>().unwrap());
+    let dt = filetime_to_datetime(FILETIME_UNIX_EPOCH + 15);
+    assert_eq!((dt.timestamp(), dt.timestamp_subsec_nanos()), (0, 1500));
+    let dt = filetime_to_datetime(FILETIME_UNIX_EPOCH - 5);
+    assert_eq!((dt.timestamp(), dt.timestamp_subsec_nanos()), (-1, 999_999_500));
+    // must not panic
+    let _ = filetime_to_datetime(0);
+    let _ = filetime_to_datetime(u64::MAX);
+}
+
+#[test]
+fn test_sample_expect_len() {
+    let sample = Sample { handle: 7, timestamp: 0, data: &[1, 2] };
+    assert_eq!(sample.expect_len(2).unwrap(), [1, 2]);
+    assert!(matches!(sample.expect_len(4),
+                     Err(Error::Reply("checking sample length", _, 2))));
+}
+
+#[test]
+fn test_malformed_samples() {
+    // one stamp with two samples of 2 bytes each
+    let data = [0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 2, 0, 0, 0,
+                1, 0, 0, 0, 2, 0, 0, 0, 0xa, 0xb,
+                2, 0, 0, 0, 2, 0, 0, 0, 0xc, 0xd];
+    let samples = SampleIter::new(&data, 1).collect::<Vec<_>>();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[1], Sample { handle: 2, timestamp: 0x9988776655443322, data: &[0xc, 0xd] });
+
+    // too many stamps
+    let mut iter = TrySampleIter(SampleIter::new(&data, 2));
+    assert!(iter.next().unwrap().is_ok());
+    assert!(iter.next().unwrap().is_ok());
+    assert!(matches!(iter.next(), Some(Err(Error::Io(..)))));
+    assert!(iter.next().is_none());
+
+    // too few stamps
+    let mut iter = TrySampleIter(SampleIter::new(&data[..22], 0));
+    assert!(matches!(iter.next(), Some(Err(Error::Reply(_, "unexpected data after samples", 22)))));
+
+    // sample length too large
+    let mut bad_data = data;
+    bad_data[16] = 100;
+    let mut iter = TrySampleIter(SampleIter::new(&bad_data, 1));
+    assert!(matches!(iter.next(), Some(Err(Error::Reply(_, "sample length exceeds data", 100)))));
+    assert!(iter.next().is_none());
+    assert_eq!(SampleIter::new(&bad_data, 1).count(), 0);
+}
+
+#[test]
+fn test_notification_send_clone() {
+    fn assert_send_clone<T: Send + Clone + 'static>(_: &T) {}
+
+    let mut buf = vec![0; AMS_HEADER_SIZE];
+    buf.extend_from_slice(&[25, 0, 0, 0, 1, 0, 0, 0,  // length, stamps
+                            1, 0, 0, 0, 0, 0, 0, 0,  // timestamp
+                            1, 0, 0, 0,  // samples
+                            7, 0, 0, 0, 1, 0, 0, 0, 42]);  // handle 7, 1 byte
+    buf[26] = 29;
+    let notif = Notification::new(buf).unwrap();
+    assert_send_clone(&notif);
+
+    let copy = notif.clone();
+    let samples = std::thread::spawn(move || copy.to_owned_samples()).join().unwrap();
+    assert_eq!(samples, notif.to_owned_samples());
+    assert_eq!(samples.len(), 1);
+    assert_eq!((samples[0].handle, samples[0].timestamp, &samples[0].data[..]), (7, 1, &[42][..]));
+}
+
+#[test]
+fn test_notification_ref() {
+    let mut buf = vec![0; AMS_HEADER_SIZE];
+    buf.extend_from_slice(&[26, 0, 0, 0, 1, 0, 0, 0,
+                            0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 1, 0, 0, 0,
+                            7, 0, 0, 0, 2, 0, 0, 0, 0xa, 0xb]);
+    let sample = {
+        let notif = NotificationRef::new(&buf).unwrap();
+        assert_eq!(notif.to_notification().samples().count(), 1);
+        notif.samples().next().unwrap()
+    };
+    // the sample borrows from the buffer, not the notification
+    assert_eq!(sample, Sample { handle: 7, timestamp: 0x9988776655443322, data: &[0xa, 0xb] });
+
+    let header = NotificationRef::new(&buf).unwrap().header();
+    assert_eq!((header.length, header.stamps), (26, 1));
+    assert_eq!(header.stamp_ranges(), [46..68]);
+    assert_eq!(header.sample_ranges(), [58..68]);
+
+    assert!(matches!(NotificationRef::new(&buf[..buf.len() - 1]),
+                     Err(Error::Reply(_, "notification length mismatch", 26))));
+    assert!(matches!(Notification::new(&buf[..AMS_HEADER_SIZE + 4]),
+                     Err(Error::Reply(_, "notification too short", 42))));
+    assert!(matches!(Notification::new(vec![0; 10]),
+                     Err(Error::Reply(_, "notification too short", 10))));
+
+    // error code in the AMS header
+    let mut bad_buf = buf.clone();
+    bad_buf[30] = 0xA;
+    bad_buf[31] = 0x7;
+    assert!(matches!(Notification::new(bad_buf),
+                     Err(Error::Ads(_, "Out of memory", 0x70A))));
+
+    // length is correct, but the number of stamps is too large
+    buf[AMS_HEADER_SIZE + 4] = 2;
+    assert!(NotificationRef::new(&buf).is_err());
+}
+
+#[test]
+fn test_sample_value() {
+    use zerocopy::byteorder::{I16, U16, U32};
+
+    let sample = Sample { handle: 1, timestamp: 0, data: &[0xfe, 0xff] };
+    assert_eq!(sample.read_value::<I16<LE>>().unwrap().get(), -2);
+    assert_eq!(sample.read_value::<U16<LE>>().unwrap().get(), 0xfffe);
+    assert_eq!(sample.read_value::<[u8; 2]>().unwrap(), [0xfe, 0xff]);
+    assert!(matches!(sample.read_value::<U32<LE>>(),
+                     Err(Error::Reply(_, "data length does not match type size", 2))));
+    assert!(sample.read_value::<u8>().is_err());
+}
+
+#[test]
+fn test_sample_to_bits() {
+    // A WORD with the value 0x8105.
+    let sample = Sample { handle: 1, timestamp: 0, data: &[0x05, 0x81] };
+    let bits = sample.to_bits();
+    assert_eq!(bits.len(), 16);
+    let set: Vec<_> = (0..16).filter(|&i| bits[i]).collect();
+    assert_eq!(set, [0, 2, 8, 15]);
+    assert!(Sample { handle: 1, timestamp: 0, data: &[] }.to_bits().is_empty());
+}
+
+#[test]
+fn test_by_handle() {
+    let mut buf = vec![0; AMS_HEADER_SIZE];
+    buf.extend_from_slice(&[55, 0, 0, 0, 2, 0, 0, 0,
+                            1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0,
+                            7, 0, 0, 0, 1, 0, 0, 0, 0xa,
+                            8, 0, 0, 0, 1, 0, 0, 0, 0xb,
+                            2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,
+                            7, 0, 0, 0, 1, 0, 0, 0, 0xc]);
+    let notif = Notification::new(buf).unwrap();
+    let map = notif.by_handle();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map[&7], [Sample { handle: 7, timestamp: 1, data: &[0xa] },
+                         Sample { handle: 7, timestamp: 2, data: &[0xc] }]);
+    assert_eq!(map[&8], [Sample { handle: 8, timestamp: 1, data: &[0xb] }]);
+    assert_eq!(notif.samples_for(7).collect::<Vec<_>>(), map[&7]);
+    assert_eq!(notif.samples_for(8).collect::<Vec<_>>(), map[&8]);
+    assert_eq!(notif.samples_for(9).count(), 0);
+
+    assert_eq!(notif.stamp_count(), 2);
+    assert_eq!(notif.stamps().collect::<Vec<_>>(), [(1, 2), (2, 1)]);
+    assert_eq!(notif.sample_count(), 3);
+    assert_eq!(notif.sample_count(), notif.samples().count());
+
+    let samples: Vec<_> = notif.into_samples().collect();
+    assert_eq!(samples, [OwnedSample { handle: 7, timestamp: 1, data: vec![0xa] },
+                         OwnedSample { handle: 8, timestamp: 1, data: vec![0xb] },
+                         OwnedSample { handle: 7, timestamp: 2, data: vec![0xc] }]);
+    assert_eq!(samples[1].borrowed(), Sample { handle: 8, timestamp: 1, data: &[0xb] });
+}
+
+#[test]
+fn test_attributes() {
+    use std::convert::TryFrom;
+
+    assert_eq!(to_ads_ticks(Duration::ZERO).unwrap(), 0);
+    assert_eq!(to_ads_ticks(Duration::from_nanos(99)).unwrap(), 0);
+    assert_eq!(to_ads_ticks(Duration::from_millis(1)).unwrap(), 10_000);
+    let max = Duration::from_nanos(u64::from(u32::MAX) * 100);
+    assert_eq!(to_ads_ticks(max).unwrap(), u32::MAX);
+    assert!(matches!(to_ads_ticks(max + Duration::from_nanos(100)), Err(Error::Overflow(_))));
+
+    let attrib = Attributes::builder().length(4).on_change()
+                                      .cycle(Duration::from_millis(10))
+                                      .max_delay(Duration::ZERO).build().unwrap();
+    assert_eq!(attrib.length, 4);
+    assert!(matches!(attrib.trans_mode, TransmissionMode::ServerOnChange));
+    assert_eq!(attrib.cycle_time, Duration::from_millis(10));
+    assert!(Attributes::builder().max_delay(Duration::from_secs(430)).build().is_err());
+    assert!(Attributes::builder().cycle(Duration::from_secs(430)).build().is_err());
+    if let Ok(len) = usize::try_from(u64::from(u32::MAX) + 1) {
+        assert!(Attributes::builder().length(len).build().is_err());
+    }
+
+    assert!(matches!(Attributes::cyclic(2, Duration::from_secs(1)).trans_mode,
+                     TransmissionMode::ServerCycle));
+    assert_eq!(Attributes::on_change(2).cycle_time, Duration::ZERO);
+
+    // sub-millisecond times keep the full resolution
+    let attrib = Attributes::new(2, TransmissionMode::ServerCycle,
+                                 Duration::from_nanos(1250), Duration::from_micros(100));
+    assert_eq!(attrib.cycle_ticks().unwrap(), 1000);
+    assert_eq!(attrib.max_delay_ticks().unwrap(), 12);
+    assert!(Attributes::cyclic(2, Duration::from_secs(430)).cycle_ticks().is_err());
+}
+
+#[test]
+fn test_dispatcher() {
+    let mut buf = vec![0; AMS_HEADER_SIZE];
+    buf.extend_from_slice(&[43, 0, 0, 0, 1, 0, 0, 0,
+                            1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0,
+                            7, 0, 0, 0, 1, 0, 0, 0, 0xa,
+                            8, 0, 0, 0, 1, 0, 0, 0, 0xb,
+                            9, 0, 0, 0, 1, 0, 0, 0, 0xc]);
+    let notif = Notification::new(buf).unwrap();
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let dispatcher = Dispatcher::new();
+    let rx = received.clone();
+    dispatcher.register(7, move |sample| rx.lock().unwrap().push(sample.data.to_vec()));
+    dispatcher.register(8, |_| panic!("callback failed"));
+    let rx = received.clone();
+    dispatcher.register(9, move |sample| rx.lock().unwrap().push(sample.data.to_vec()));
+
+    // pull model: the panic in the second callback doesn't stop dispatching
+    dispatcher.dispatch(&notif);
+    assert_eq!(*received.lock().unwrap(), [[0xa], [0xc]]);
+
+    // push model
+    assert!(dispatcher.unregister(9));
+    assert!(!dispatcher.unregister(9));
+    let (send, recv) = crossbeam_channel::unbounded();
+    let thread = dispatcher.spawn(recv);
+    send.send(notif).unwrap();
+    drop(send);
+    thread.join().unwrap();
+    assert_eq!(*received.lock().unwrap(), [[0xa], [0xc], [0xa]]);
+}
+
+#[test]
+fn test_recorder() {
+    // one stamp with the given time after the epoch, and samples with one
+    // byte each
+    let notif = |timestamp: u64, samples: &[(Handle, u8)]| {
+        let mut buf = vec![0; AMS_HEADER_SIZE];
+        buf.extend_from_slice(&(16 + 9 * samples.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&1_u32.to_le_bytes());
+        buf.extend_from_slice(&(FILETIME_UNIX_EPOCH + timestamp).to_le_bytes());
+        buf.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        for &(handle, value) in samples {
+            buf.extend_from_slice(&handle.to_le_bytes());
+            buf.extend_from_slice(&[1, 0, 0, 0, value]);
+        }
+        Notification::new(buf).unwrap()
+    };
+    let time = |ticks| filetime_to_system_time(FILETIME_UNIX_EPOCH + ticks);
+
+    let mut recorder = NotificationRecorder::new();
+    assert!(recorder.is_empty());
+    recorder.push(&notif(10, &[(7, 0xa), (8, 0xb)]));
+    recorder.push(&notif(20, &[(7, 0xc)]));
+    assert_eq!(recorder.len(7), 2);
+    assert_eq!(recorder.len(9), 0);
+    let series = recorder.drain();
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[&7], [(time(10), vec![0xa]), (time(20), vec![0xc])]);
+    assert_eq!(series[&8], [(time(10), vec![0xb])]);
+    assert!(recorder.is_empty());
+
+    // only the newest samples are kept
+    let mut recorder = NotificationRecorder::with_max_history(2);
+    for i in 0..5 {
+        recorder.push(&notif(i, &[(7, i as u8)]));
+    }
+    assert_eq!(recorder.drain()[&7], [(time(3), vec![3]), (time(4), vec![4])]);
+    let mut recorder = NotificationRecorder::with_max_history(0);
+    recorder.push(&notif(1, &[(7, 1)]));
+    assert_eq!(recorder.len(7), 0);
+}
+
+#[test]
+fn test_sequencer() {
+    // cycle time 1ms = 10000 ticks, tolerance 0.1ms
+    let mut seq = NotificationSequencer::new(7, Duration::from_millis(1),
+                                             Duration::from_micros(100));
+    assert_eq!(seq.feed_timestamp(1_000_000), 0);
+    assert_eq!(seq.feed_timestamp(1_010_000), 0);
+    // jitter within tolerance
+    assert_eq!(seq.feed_timestamp(1_020_500), 0);
+    assert_eq!(seq.feed_timestamp(1_029_600), 0);
+    // two cycles missed, with jitter
+    assert_eq!(seq.feed_timestamp(1_059_300), 2);
+    // timestamps going backwards don't count
+    assert_eq!(seq.feed_timestamp(1_000_000), 0);
+    seq.reset();
+    assert_eq!(seq.feed_timestamp(2_000_000), 0);
+
+    // two stamps with samples for handles 7 and 8
+    let mut buf = vec![0; AMS_HEADER_SIZE];
+    buf.extend_from_slice(&[55, 0, 0, 0, 2, 0, 0, 0,
+                            0x20, 0x4e, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0,
+                            7, 0, 0, 0, 1, 0, 0, 0, 0xa,
+                            8, 0, 0, 0, 1, 0, 0, 0, 0xb,
+                            0x50, 0xc3, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0,
+                            7, 0, 0, 0, 1, 0, 0, 0, 0xc]);
+    let notif = Notification::new(buf).unwrap();
+    seq.reset();
+    // timestamps 20000 and 50000: two cycles missed in between
+    assert_eq!(seq.feed(&notif), 2);
+}
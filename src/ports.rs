@@ -76,3 +76,43 @@ pub const MULTIUSER: AmsPort = 19600;
 pub const DATABASE_SERVER: AmsPort = 21372;
 pub const FIAS_SERVER: AmsPort = 25013;
 pub const BANG_OLUFSEN_SERVER: AmsPort = 25015;
+
+/// The ports of the most commonly used ADS devices.
+///
+/// These can be used instead of the raw port numbers, for example with
+/// `client.device((netid, Port::Tc3Plc1))`.  Note that the PLC runtimes of
+/// TwinCAT 2 and 3 use different ports.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Port {
+    /// The AMS router.
+    Router = AMS_ROUTER,
+    /// The logger.
+    Logger = LOGGER,
+    /// The first PLC runtime of TwinCAT 2.
+    Tc2Plc1 = TC2_PLC_SYSTEM1,
+    /// The second PLC runtime of TwinCAT 2.
+    Tc2Plc2 = TC2_PLC_SYSTEM2,
+    /// The third PLC runtime of TwinCAT 2.
+    Tc2Plc3 = TC2_PLC_SYSTEM3,
+    /// The fourth PLC runtime of TwinCAT 2.
+    Tc2Plc4 = TC2_PLC_SYSTEM4,
+    /// The first PLC runtime of TwinCAT 3.
+    Tc3Plc1 = TC3_PLC_SYSTEM1,
+    /// The second PLC runtime of TwinCAT 3.
+    Tc3Plc2 = TC3_PLC_SYSTEM2,
+    /// The third PLC runtime of TwinCAT 3.
+    Tc3Plc3 = TC3_PLC_SYSTEM3,
+    /// The fourth PLC runtime of TwinCAT 3.
+    Tc3Plc4 = TC3_PLC_SYSTEM4,
+    /// The NC (motion control) runtime.
+    Nc = RING0_NC,
+    /// The system service, e.g. for reading the PLC time or file access.
+    SystemService = SYSTEM_SERVICE,
+}
+
+impl From<Port> for AmsPort {
+    fn from(port: Port) -> Self {
+        port as AmsPort
+    }
+}
@@ -1,5 +1,7 @@
 //! Const-generic string types for representing fixed-length strings.
 
+use zerocopy::byteorder::{LE, U16};
+
 /// Represents a fixed-length byte string.
 ///
 /// This type can be created from a `&str` or `&[u8]` if their byte
@@ -24,6 +26,11 @@ impl<const LEN: usize> String<LEN> {
         self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len())
     }
 
+    /// Return true if the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Get the slice up to the first null byte.
     pub fn as_bytes(&self) -> &[u8] {
         &self.0[..self.len()]
@@ -139,30 +146,43 @@ unsafe impl<const LEN: usize> zerocopy::FromBytes for String<LEN> {
 ///
 /// It can be converted to a Rust `String` if it is properly UTF16
 /// encoded.
+///
+/// The code units are stored in little-endian order, like on the PLC, so
+/// that the memory layout is correct regardless of the host's byte order.
 #[repr(C)]
 #[derive(Clone, Copy)]
-pub struct WString<const LEN: usize>([u16; LEN], u16);  // one extra NULL byte
+pub struct WString<const LEN: usize>([U16<LE>; LEN], U16<LE>);  // one extra NULL unit
 
 impl<const LEN: usize> WString<LEN> {
     /// Create a new empty string.
     pub fn new() -> Self {
-        Self([0; LEN], 0)
+        Self([U16::ZERO; LEN], U16::ZERO)
     }
 
     /// Return the number of code units up to the first null.
     pub fn len(&self) -> usize {
-        self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len())
+        self.0.iter().position(|b| b.get() == 0).unwrap_or(self.0.len())
+    }
+
+    /// Return true if the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     /// Get the slice up to the first null code unit.
-    pub fn as_slice(&self) -> &[u16] {
+    pub fn as_slice(&self) -> &[U16<LE>] {
         &self.0[..self.len()]
     }
 
     /// Get a reference to the full array of code units.
-    pub fn backing_array(&mut self) -> &mut [u16; LEN] {
+    pub fn backing_array(&mut self) -> &mut [U16<LE>; LEN] {
         &mut self.0
     }
+
+    /// Return an iterator over the code units up to the first null.
+    fn units(&self) -> impl Iterator<Item = u16> + '_ {
+        self.as_slice().iter().map(|unit| unit.get())
+    }
 }
 
 impl<const LEN: usize> std::default::Default for WString<LEN> {
@@ -174,7 +194,7 @@ impl<const LEN: usize> std::default::Default for WString<LEN> {
 impl<const LEN: usize> std::fmt::Debug for WString<LEN> {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         let fmted: std::string::String =
-            std::char::decode_utf16(self.0.iter().cloned().take_while(|&b| b != 0))
+            std::char::decode_utf16(self.units())
             .map(|ch| ch.unwrap_or(std::char::REPLACEMENT_CHARACTER))
             .collect();
         std::fmt::Debug::fmt(&fmted, fmt)
@@ -189,13 +209,13 @@ impl<const LEN: usize> std::cmp::PartialEq for WString<LEN> {
 
 impl<const LEN: usize> std::cmp::PartialEq<&[u16]> for WString<LEN> {
     fn eq(&self, other: &&[u16]) -> bool {
-        self.as_slice() == *other
+        self.units().eq(other.iter().cloned())
     }
 }
 
 impl<const LEN: usize> std::cmp::PartialEq<&str> for WString<LEN> {
     fn eq(&self, other: &&str) -> bool {
-        self.as_slice().iter().cloned().eq(other.encode_utf16())
+        self.units().eq(other.encode_utf16())
     }
 }
 
@@ -203,13 +223,21 @@ impl<const LEN: usize> std::cmp::PartialEq<&str> for WString<LEN> {
 
 impl<const LEN: usize> std::convert::From<[u16; LEN]> for WString<LEN> {
     fn from(arr: [u16; LEN]) -> Self {
-        Self(arr, 0)
+        let mut wstr = Self::new();
+        for (unit, &value) in wstr.0.iter_mut().zip(&arr) {
+            unit.set(value);
+        }
+        wstr
     }
 }
 
 impl<const LEN: usize> std::convert::From<WString<LEN>> for [u16; LEN] {
     fn from(wstr: WString<LEN>) -> Self {
-        wstr.0
+        let mut arr = [0; LEN];
+        for (value, unit) in arr.iter_mut().zip(&wstr.0) {
+            *value = unit.get();
+        }
+        arr
     }
 }
 
@@ -222,14 +250,16 @@ impl<const LEN: usize> std::convert::TryFrom<&'_ [u16]> for WString<LEN> {
             return Err(());
         }
         let mut wstr = Self::new();
-        wstr.0[..arr.len()].copy_from_slice(arr);
+        for (unit, &value) in wstr.0.iter_mut().zip(arr) {
+            unit.set(value);
+        }
         Ok(wstr)
     }
 }
 
 impl<const LEN: usize> std::convert::From<WString<LEN>> for std::vec::Vec<u16> {
     fn from(wstr: WString<LEN>) -> Self {
-        wstr.as_slice().to_vec()
+        wstr.units().collect()
     }
 }
 
@@ -243,7 +273,7 @@ impl<const LEN: usize> std::convert::TryFrom<&'_ str> for WString<LEN> {
             if i >= wstr.0.len() {
                 return Err(());
             }
-            wstr.0[i] = unit;
+            wstr.0[i].set(unit);
         }
         Ok(wstr)
     }
@@ -252,7 +282,7 @@ impl<const LEN: usize> std::convert::TryFrom<&'_ str> for WString<LEN> {
 impl<const LEN: usize> std::convert::TryFrom<WString<LEN>> for std::string::String {
     type Error = std::char::DecodeUtf16Error;
     fn try_from(wstr: WString<LEN>) -> std::result::Result<Self, Self::Error> {
-        std::char::decode_utf16(wstr.0.iter().cloned().take_while(|&b| b != 0)).collect()
+        std::char::decode_utf16(wstr.units()).collect()
     }
 }
 
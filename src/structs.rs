@@ -0,0 +1,249 @@
+//! Field-by-field decoding and encoding of PLC structures.
+//!
+//! The zerocopy traits map a Rust struct directly onto the bytes of a PLC
+//! structure, which needs `#[repr(C, packed)]` and byte order independent
+//! field types.  The [`FromAds`] and [`ToAds`] traits instead decode and
+//! encode the fields one after the other, so that plain Rust types such as
+//! `u16`, `f64` and `String` can be used for the fields.
+//!
+//! With the `derive` feature, `#[derive(AdsStruct)]` implements the traits
+//! for a struct with named fields.  The fields are decoded in declaration
+//! order from consecutive bytes, and two attributes adapt the layout to the
+//! PLC structure:
+//!
+//! * `#[ads(pad = N)]` skips N bytes of padding before the field.  On the
+//!   struct itself, it adds N bytes of padding after the last field.
+//! * `#[ads(string = N)]` maps a `String` field to a `STRING(N)`, which
+//!   occupies N + 1 bytes.
+//!
+//! ```rust,ignore
+//! #[derive(ads::AdsStruct)]
+//! struct Motor {
+//!     enabled: bool,
+//!     #[ads(pad = 1)]
+//!     speed: u16,
+//!     #[ads(string = 15)]
+//!     name: String,
+//!     errors: [u32; 2],
+//! }
+//!
+//! let motor: Motor = handle.read_struct()?;
+//! ```
+//!
+//! Nested structs work if they implement the traits as well.  Decoding
+//! fails if the data does not have exactly the size of the struct, and
+//! encoding fails if a string is too long for its field.
+
+use std::convert::TryInto;
+use std::io;
+use std::mem::size_of;
+
+use crate::{Error, Result};
+
+/// A type with a fixed size when encoded for ADS.
+pub trait AdsSize {
+    /// The number of bytes of the encoded value.
+    const ADS_SIZE: usize;
+}
+
+/// A type that can be decoded from ADS data.
+pub trait FromAds: AdsSize + Sized {
+    /// Decode a value from data of exactly `ADS_SIZE` bytes.
+    fn from_ads(data: &[u8]) -> Result<Self>;
+}
+
+/// A type that can be encoded into ADS data.
+pub trait ToAds: AdsSize {
+    /// Encode the value into a buffer of exactly `ADS_SIZE` bytes.
+    fn to_ads(&self, data: &mut [u8]) -> Result<()>;
+}
+
+/// Decodes consecutive fields from the data of a structure.
+///
+/// This is used by the derived [`FromAds`] implementations, and can be used
+/// to implement the trait by hand.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    /// Create a decoder, checking that the data has the given size.
+    pub fn new(data: &'a [u8], size: usize) -> Result<Self> {
+        if data.len() != size {
+            return Err(Error::Reply("decoding data", "data size does not match type size",
+                                    data.len() as u32));
+        }
+        Ok(Self { data })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(Error::Reply("decoding data", "data too short for all fields",
+                                    self.data.len() as u32));
+        }
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+
+    /// Skip `len` bytes of padding.
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len).map(drop)
+    }
+
+    /// Decode the next field.
+    pub fn read<T: FromAds>(&mut self) -> Result<T> {
+        T::from_ads(self.take(T::ADS_SIZE)?)
+    }
+
+    /// Decode the next field as a `STRING(len)`, which occupies `len + 1`
+    /// bytes.
+    ///
+    /// The string ends at the first null byte, and must be UTF-8 encoded.
+    pub fn read_string(&mut self, len: usize) -> Result<String> {
+        let data = self.take(len + 1)?;
+        let data = &data[..data.iter().position(|&b| b == 0).unwrap_or(len)];
+        std::str::from_utf8(data)
+            .map(Into::into)
+            .map_err(|e| Error::Reply("decoding string", "string is not UTF-8 encoded",
+                                      e.valid_up_to() as u32))
+    }
+}
+
+/// Encodes consecutive fields into the data of a structure.
+///
+/// This is used by the derived [`ToAds`] implementations, and can be used
+/// to implement the trait by hand.
+pub struct Encoder<'a> {
+    data: &'a mut [u8],
+}
+
+impl<'a> Encoder<'a> {
+    /// Create an encoder, checking that the buffer has the given size.
+    pub fn new(data: &'a mut [u8], size: usize) -> Result<Self> {
+        if data.len() != size {
+            return Err(Error::Io("encoding data", io::ErrorKind::InvalidInput.into()));
+        }
+        Ok(Self { data })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a mut [u8]> {
+        if len > self.data.len() {
+            return Err(Error::Io("encoding data", io::ErrorKind::InvalidInput.into()));
+        }
+        let (field, rest) = std::mem::take(&mut self.data).split_at_mut(len);
+        self.data = rest;
+        Ok(field)
+    }
+
+    /// Fill `len` bytes of padding with zeros.
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len)?.fill(0);
+        Ok(())
+    }
+
+    /// Encode the next field.
+    pub fn write<T: ToAds>(&mut self, value: &T) -> Result<()> {
+        value.to_ads(self.take(T::ADS_SIZE)?)
+    }
+
+    /// Encode the next field as a `STRING(len)`, which occupies `len + 1`
+    /// bytes.
+    ///
+    /// Returns an error if the string is longer than `len` bytes.
+    pub fn write_string(&mut self, value: &str, len: usize) -> Result<()> {
+        if value.len() > len {
+            return Err(Error::Io("encoding string", io::Error::new(
+                io::ErrorKind::InvalidInput, "string too long for STRING field")));
+        }
+        let data = self.take(len + 1)?;
+        data[..value.len()].copy_from_slice(value.as_bytes());
+        data[value.len()..].fill(0);
+        Ok(())
+    }
+}
+
+macro_rules! impl_number {
+    ($($ty:ty),*) => {
+        $(
+            impl AdsSize for $ty {
+                const ADS_SIZE: usize = size_of::<$ty>();
+            }
+
+            impl FromAds for $ty {
+                fn from_ads(data: &[u8]) -> Result<Self> {
+                    let bytes = Decoder::new(data, Self::ADS_SIZE)?.data;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().expect("size")))
+                }
+            }
+
+            impl ToAds for $ty {
+                fn to_ads(&self, data: &mut [u8]) -> Result<()> {
+                    Encoder::new(data, Self::ADS_SIZE)?.data.copy_from_slice(&self.to_le_bytes());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_number!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+impl AdsSize for bool {
+    const ADS_SIZE: usize = 1;
+}
+
+impl FromAds for bool {
+    fn from_ads(data: &[u8]) -> Result<Self> {
+        u8::from_ads(data).map(|b| b != 0)
+    }
+}
+
+impl ToAds for bool {
+    fn to_ads(&self, data: &mut [u8]) -> Result<()> {
+        u8::from(*self).to_ads(data)
+    }
+}
+
+impl<T: AdsSize, const N: usize> AdsSize for [T; N] {
+    const ADS_SIZE: usize = T::ADS_SIZE * N;
+}
+
+impl<T: FromAds + Default + Copy, const N: usize> FromAds for [T; N] {
+    fn from_ads(data: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder::new(data, Self::ADS_SIZE)?;
+        let mut array = [T::default(); N];
+        for item in &mut array {
+            *item = decoder.read()?;
+        }
+        Ok(array)
+    }
+}
+
+impl<T: ToAds, const N: usize> ToAds for [T; N] {
+    fn to_ads(&self, data: &mut [u8]) -> Result<()> {
+        let mut encoder = Encoder::new(data, Self::ADS_SIZE)?;
+        self.iter().try_for_each(|item| encoder.write(item))
+    }
+}
+
+impl<const LEN: usize> AdsSize for crate::strings::String<LEN> {
+    const ADS_SIZE: usize = LEN + 1;
+}
+
+impl<const LEN: usize> FromAds for crate::strings::String<LEN> {
+    fn from_ads(data: &[u8]) -> Result<Self> {
+        let mut value = Self::new();
+        value.backing_array().copy_from_slice(&Decoder::new(data, Self::ADS_SIZE)?.data[..LEN]);
+        Ok(value)
+    }
+}
+
+impl<const LEN: usize> ToAds for crate::strings::String<LEN> {
+    fn to_ads(&self, data: &mut [u8]) -> Result<()> {
+        let data = Encoder::new(data, Self::ADS_SIZE)?.data;
+        data[..LEN].copy_from_slice(&<[u8; LEN]>::from(*self));
+        data[LEN] = 0;
+        Ok(())
+    }
+}
@@ -1,14 +1,15 @@
 //! Wrappers for symbol operations and symbol handles.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::Read;
 
 use byteorder::{ByteOrder, LE, ReadBytesExt};
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::errors::{Error, ErrContext};
 use crate::index;
+use crate::structs::{FromAds, ToAds};
 use crate::{Device, Result};
 
 /// A handle to a variable within the ADS device.
@@ -21,6 +22,10 @@ pub struct Handle<'c> {
 
 impl<'c> Handle<'c> {
     /// Create a new handle to a single symbol.
+    ///
+    /// The name is the full hierarchical name of the symbol, such as
+    /// `MAIN.fTemperature`.  If it is not found, an ADS error with code 0x710
+    /// is returned.
     pub fn new(device: Device<'c>, symbol: &str) -> Result<Self> {
         let mut handle_bytes = [0; 4];
         device.write_read_exact(index::GET_SYMHANDLE_BYNAME, 0, symbol.as_bytes(),
@@ -61,6 +66,20 @@ impl<'c> Handle<'c> {
     pub fn write_value<T: AsBytes>(&self, value: &T) -> Result<()> {
         self.device.write_value(index::RW_SYMVAL_BYHANDLE, self.handle, value)
     }
+
+    /// Read a structure that is decoded field by field.
+    ///
+    /// See the [`structs`](crate::structs) module for details.
+    pub fn read_struct<T: FromAds>(&self) -> Result<T> {
+        self.device.read_struct(index::RW_SYMVAL_BYHANDLE, self.handle)
+    }
+
+    /// Write a structure that is encoded field by field.
+    ///
+    /// See the [`structs`](crate::structs) module for details.
+    pub fn write_struct<T: ToAds>(&self, value: &T) -> Result<()> {
+        self.device.write_struct(index::RW_SYMVAL_BYHANDLE, self.handle, value)
+    }
 }
 
 impl<'a> Drop for Handle<'a> {
@@ -70,6 +89,86 @@ impl<'a> Drop for Handle<'a> {
     }
 }
 
+/// A cache of symbol handles by name.
+///
+/// Handles are created on first access to a symbol, and reused afterwards.
+/// If the device reports that a handle has become invalid, for example after
+/// an online change, it is created again.  All handles are released when the
+/// cache is dropped or invalidated.
+pub struct SymbolCache<'c> {
+    device: Device<'c>,
+    handles: RefCell<HashMap<String, Handle<'c>>>,
+}
+
+impl<'c> SymbolCache<'c> {
+    /// Create a new, empty cache for symbols of the given device.
+    pub fn new(device: Device<'c>) -> Self {
+        Self { device, handles: RefCell::default() }
+    }
+
+    /// Release all cached handles, e.g. after a download to the PLC.
+    pub fn invalidate(&self) {
+        self.handles.borrow_mut().clear();
+    }
+
+    /// Return the number of cached handles.
+    pub fn len(&self) -> usize {
+        self.handles.borrow().len()
+    }
+
+    /// Return true if no handles are cached.
+    pub fn is_empty(&self) -> bool {
+        self.handles.borrow().is_empty()
+    }
+
+    /// Return the raw handle for the named symbol.
+    pub fn handle(&self, symbol: &str) -> Result<u32> {
+        self.with_handle(symbol, |handle| Ok(handle.raw()))
+    }
+
+    /// Read data from the named symbol (returned data must match size of buffer).
+    pub fn read(&self, symbol: &str, buf: &mut [u8]) -> Result<()> {
+        self.with_handle(symbol, |handle| handle.read(buf))
+    }
+
+    /// Write data to the named symbol.
+    pub fn write(&self, symbol: &str, buf: &[u8]) -> Result<()> {
+        self.with_handle(symbol, |handle| handle.write(buf))
+    }
+
+    /// Read data of given type from the named symbol.
+    ///
+    /// See [`Handle::read_value`] for details.
+    pub fn read_value<T: Default + AsBytes + FromBytes>(&self, symbol: &str) -> Result<T> {
+        self.with_handle(symbol, |handle| handle.read_value())
+    }
+
+    /// Write data of given type to the named symbol.
+    ///
+    /// See [`Handle::read_value`] for details.
+    pub fn write_value<T: AsBytes>(&self, symbol: &str, value: &T) -> Result<()> {
+        self.with_handle(symbol, |handle| handle.write_value(value))
+    }
+
+    fn with_handle<T>(&self, symbol: &str, mut f: impl FnMut(&Handle<'c>) -> Result<T>)
+                      -> Result<T> {
+        let mut handles = self.handles.borrow_mut();
+        if let Some(handle) = handles.get(symbol) {
+            match f(handle) {
+                // The handle is not valid anymore, create a new one.
+                Err(e) if matches!(e.ads_code(), Some(0x710 | 0x711)) => {
+                    handles.remove(symbol);
+                }
+                result => return result,
+            }
+        }
+        let handle = Handle::new(self.device, symbol)?;
+        let result = f(&handle);
+        handles.insert(symbol.into(), handle);
+        result
+    }
+}
+
 /// Get symbol size by name.
 pub fn get_size(device: Device<'_>, symbol: &str) -> Result<usize> {
     let mut buf = [0; 12];
@@ -85,8 +184,22 @@ pub fn get_location(device: Device<'_>, symbol: &str) -> Result<(u32, u32)> {
         u32::from_le_bytes(buf[4..8].try_into().expect("size"))))
 }
 
+/// Get the symbol information for a single symbol by name.
+///
+/// This is much cheaper than uploading all symbols with [`get_symbols`].
+/// If the symbol is not found, an ADS error with code 0x710 is returned.
+pub fn get_symbol(device: Device<'_>, symbol: &str) -> Result<Symbol> {
+    let data = device.write_read_vec(index::GET_SYMINFO_BYNAME_EX, 0, symbol.as_bytes(),
+                                     MAX_ENTRY_SIZE)?;
+    let entry_ptr = next_entry(&mut data.as_slice(), "decoding symbol info")?;
+    decode_symbol_entry(entry_ptr)
+}
+
+/// Maximum size of a single symbol entry returned by the device.
+const MAX_ENTRY_SIZE: usize = 0x10000;
 
 /// Represents a symbol in the PLC memory.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Symbol {
     /// Hierarchical name of the symbol.
     pub name:      String,
@@ -96,6 +209,8 @@ pub struct Symbol {
     pub ix_offset: u32,
     /// Type name of the symbol.
     pub typ:       String,
+    /// Comment given for the symbol in the PLC code.
+    pub comment:   String,
     /// Total size of the symbol, in bytes.
     pub size:      usize,
     /// Base type:
@@ -130,13 +245,126 @@ pub struct Symbol {
     /// - 0x2000 - Static
     /// - 0x4000 - Init on reset
     /// - 0x8000 - Extended flags present
+    ///
+    /// Use [`flags()`](Self::flags) to get them as [`SymbolFlags`].
     pub flags:     u32,
 }
 
+impl Symbol {
+    /// Return the symbol flags in typed form.
+    pub fn flags(&self) -> SymbolFlags {
+        SymbolFlags(self.flags)
+    }
+}
+
+/// The flags of a [`Symbol`].
+///
+/// Flags can be combined with `|` and tested with
+/// [`contains`](Self::contains).  Unknown bits are kept, and available with
+/// [`bits`](Self::bits).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SymbolFlags(u32);
+
+impl SymbolFlags {
+    /// The symbol is persistent.
+    pub const PERSISTENT: Self = Self(0x01);
+    /// The symbol is a bit value.
+    pub const BIT_VALUE: Self = Self(0x02);
+    /// The symbol is a `REFERENCE TO`.
+    pub const REFERENCE_TO: Self = Self(0x04);
+    /// The symbol info contains a type GUID.
+    pub const TYPE_GUID: Self = Self(0x08);
+    /// The symbol is a TcCOM interface pointer.
+    pub const TCOM_INTERFACE_PTR: Self = Self(0x10);
+    /// The symbol is read only.
+    pub const READ_ONLY: Self = Self(0x20);
+    /// The symbol is accessed by interface methods.
+    pub const ITF_METHOD_ACCESS: Self = Self(0x40);
+    /// The symbol is dereferenced by methods.
+    pub const METHOD_DEREF: Self = Self(0x80);
+    /// The symbol info contains attributes.
+    pub const ATTRIBUTES: Self = Self(0x1000);
+    /// The symbol is static.
+    pub const STATIC: Self = Self(0x2000);
+    /// The symbol is initialized on reset.
+    pub const INIT_ON_RESET: Self = Self(0x4000);
+    /// The symbol info contains extended flags.
+    pub const EXTENDED_FLAGS: Self = Self(0x8000);
+
+    /// The bits that contain the context (see [`context`](Self::context)).
+    const CONTEXT_MASK: u32 = 0x0F00;
+
+    /// Create flags from the raw value, keeping unknown bits.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Return the raw value.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Return true if all flags in `other` are set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Return the context (task) number stored in the flags.
+    pub fn context(self) -> u8 {
+        ((self.0 & Self::CONTEXT_MASK) >> 8) as u8
+    }
+}
+
+impl std::ops::BitOr for SymbolFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl std::fmt::Debug for SymbolFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const NAMES: &[(SymbolFlags, &str)] = &[
+            (SymbolFlags::PERSISTENT, "PERSISTENT"),
+            (SymbolFlags::BIT_VALUE, "BIT_VALUE"),
+            (SymbolFlags::REFERENCE_TO, "REFERENCE_TO"),
+            (SymbolFlags::TYPE_GUID, "TYPE_GUID"),
+            (SymbolFlags::TCOM_INTERFACE_PTR, "TCOM_INTERFACE_PTR"),
+            (SymbolFlags::READ_ONLY, "READ_ONLY"),
+            (SymbolFlags::ITF_METHOD_ACCESS, "ITF_METHOD_ACCESS"),
+            (SymbolFlags::METHOD_DEREF, "METHOD_DEREF"),
+            (SymbolFlags::ATTRIBUTES, "ATTRIBUTES"),
+            (SymbolFlags::STATIC, "STATIC"),
+            (SymbolFlags::INIT_ON_RESET, "INIT_ON_RESET"),
+            (SymbolFlags::EXTENDED_FLAGS, "EXTENDED_FLAGS"),
+        ];
+        let mut set = f.debug_set();
+        let mut rest = self.0 & !Self::CONTEXT_MASK;
+        for &(flag, name) in NAMES {
+            if self.contains(flag) {
+                set.entry(&format_args!("{}", name));
+                rest &= !flag.0;
+            }
+        }
+        if self.context() != 0 {
+            set.entry(&format_args!("CONTEXT({})", self.context()));
+        }
+        if rest != 0 {
+            set.entry(&format_args!("{:#x}", rest));
+        }
+        set.finish()
+    }
+}
+
 /// Represents a type in the PLC's type inventory.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Type {
     /// Name of the type.
     pub name:      String,
+    /// Name of the underlying type, if any (e.g. the element type of arrays).
+    pub typ:       String,
+    /// Comment given for the type in the PLC code.
+    pub comment:   String,
     /// Total size of the type, in bytes.
     pub size:      usize,
     /// If the type is an array, (lower, upper) index bounds for all dimensions.
@@ -172,12 +400,36 @@ pub struct Type {
     pub flags:     u32,
 }
 
+impl Type {
+    /// Return the dimensions, if the type is an array.
+    pub fn array_dims(&self) -> Vec<ArrayDim> {
+        array_dims(&self.array)
+    }
+
+    /// Return the byte offset of an array element within the array.
+    ///
+    /// `index` must contain one index per dimension, and the indices must be
+    /// within the bounds.  Otherwise, or if the type is not an array, `None` is
+    /// returned.  As in the PLC, the last index varies fastest:
+    ///
+    /// ```rust,ignore
+    /// // ARRAY [1..3, 0..9] OF INT
+    /// assert_eq!(typ.element_offset(&[2, 5]), Some(30));
+    /// ```
+    pub fn element_offset(&self, index: &[i32]) -> Option<usize> {
+        element_offset(&self.array, self.size, index)
+    }
+}
+
 /// Represents a field of a structure type.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Field {
     /// Name of the field.
     pub name:      String,
     /// Type name of the field.
     pub typ:       String,
+    /// Comment given for the field in the PLC code.
+    pub comment:   String,
     /// Offset of the field in the structure.  If `None`, the field is defined
     /// in some other memory block and not inline to the structure.
     pub offset:    Option<u32>,
@@ -191,16 +443,71 @@ pub struct Field {
     pub flags:     u32,
 }
 
+impl Field {
+    /// Return the dimensions, if the field is an array.
+    pub fn array_dims(&self) -> Vec<ArrayDim> {
+        array_dims(&self.array)
+    }
+
+    /// Return the byte offset of an array element within the field (see
+    /// [`Type::element_offset`]).
+    pub fn element_offset(&self, index: &[i32]) -> Option<usize> {
+        element_offset(&self.array, self.size, index)
+    }
+}
+
+/// One dimension of an array type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArrayDim {
+    /// The lowest index of the dimension.
+    pub lower_bound: i32,
+    /// The number of elements in the dimension.
+    pub elements: u32,
+}
+
+fn array_dims(array: &[(i32, i32)]) -> Vec<ArrayDim> {
+    array.iter().map(|&(lower, upper)| ArrayDim {
+        lower_bound: lower,
+        elements: upper.wrapping_sub(lower).wrapping_add(1) as u32,
+    }).collect()
+}
+
+fn element_offset(array: &[(i32, i32)], size: usize, index: &[i32]) -> Option<usize> {
+    if array.is_empty() || index.len() != array.len() {
+        return None;
+    }
+    let dims = array_dims(array);
+    let total = dims.iter().try_fold(1usize, |n, dim| n.checked_mul(dim.elements as usize))?;
+    if total == 0 {
+        return None;
+    }
+    let mut flat = 0;
+    for (dim, &ix) in dims.iter().zip(index) {
+        let pos = ix.checked_sub(dim.lower_bound).filter(|&pos| pos >= 0)? as u32;
+        if pos >= dim.elements {
+            return None;
+        }
+        flat = flat * dim.elements as usize + pos as usize;
+    }
+    Some(flat * (size / total))
+}
+
 /// A mapping from type name to type.
 pub type TypeMap = HashMap<String, Type>;
 
-/// Get and decode symbol and type information from the PLC.
-pub fn get_symbol_info(device: Device<'_>) -> Result<(Vec<Symbol>, TypeMap)> {
-    // Query the sizes of symbol and type info.
+/// Query the sizes of symbol and type info.
+fn get_upload_info(device: Device<'_>) -> Result<(usize, usize)> {
     let mut read_data = [0; 64];
     device.read_exact(index::SYM_UPLOAD_INFO2, 0, &mut read_data)?;
     let symbol_len = LE::read_u32(&read_data[4..]) as usize;
     let types_len  = LE::read_u32(&read_data[12..]) as usize;
+    Ok((symbol_len, types_len))
+}
+
+/// Get and decode symbol and type information from the PLC.
+pub fn get_symbol_info(device: Device<'_>) -> Result<(Vec<Symbol>, TypeMap)> {
+    let (symbol_len, types_len) = get_upload_info(device)?;
 
     // Query the type info.
     let mut type_data = vec![0; types_len];
@@ -213,6 +520,44 @@ pub fn get_symbol_info(device: Device<'_>) -> Result<(Vec<Symbol>, TypeMap)> {
     decode_symbol_info(symbol_data, type_data)
 }
 
+/// Get and decode only the type information from the PLC.
+pub fn get_types(device: Device<'_>) -> Result<TypeMap> {
+    let (_, types_len) = get_upload_info(device)?;
+    let mut type_data = vec![0; types_len];
+    device.read_exact(index::SYM_DT_UPLOAD, 0, &mut type_data)?;
+    decode_types(&type_data)
+}
+
+/// Get and decode only the symbol information from the PLC.
+pub fn get_symbols(device: Device<'_>) -> Result<Vec<Symbol>> {
+    let (symbol_len, _) = get_upload_info(device)?;
+    let mut symbol_data = vec![0; symbol_len];
+    device.read_exact(index::SYM_UPLOAD, 0, &mut symbol_data)?;
+    decode_symbols(&symbol_data)
+}
+
+/// Split off the next entry, which is prefixed by its total length, from the
+/// data.
+fn next_entry<'a>(ptr: &mut &'a [u8], ctx: &'static str) -> Result<&'a [u8]> {
+    let entry_size = ptr.read_u32::<LE>().ctx(ctx)? as usize;
+    if entry_size < 4 || entry_size - 4 > ptr.len() {
+        return Err(Error::Reply(ctx, "invalid entry length", entry_size as u32));
+    }
+    let (entry, rest) = ptr.split_at(entry_size - 4);
+    *ptr = rest;
+    Ok(entry)
+}
+
+/// Read a null-terminated string of given length (excluding the null).
+fn read_string(ptr: &mut &[u8], len: usize, ctx: &'static str) -> Result<String> {
+    if ptr.len() <= len {
+        return Err(Error::Reply(ctx, "string length exceeds entry", len as u32));
+    }
+    let string = String::from_utf8_lossy(&ptr[..len]).into_owned();
+    *ptr = &ptr[len + 1..];
+    Ok(string)
+}
+
 /// Decode symbol and type information from the PLC.
 ///
 /// The data must come from the `SYM_UPLOAD` and `SYM_DT_UPLOAD` queries,
@@ -220,115 +565,111 @@ pub fn get_symbol_info(device: Device<'_>) -> Result<(Vec<Symbol>, TypeMap)> {
 ///
 /// Returns a list of symbols, and a map of type names to types.
 pub fn decode_symbol_info(symbol_data: Vec<u8>, type_data: Vec<u8>) -> Result<(Vec<Symbol>, TypeMap)> {
-    // Decode the type info.
-    let mut buf = [0; 1024];
-    let mut data_ptr = type_data.as_slice();
-    let mut type_map = HashMap::new();
-
-    fn decode_type_info(mut ptr: &[u8], parent: Option<&mut Type>) -> Result<Option<Type>> {
-        let ctx = "decoding type info";
+    let type_map = decode_types(&type_data)?;
+    let symbols = decode_symbols(&symbol_data)?;
+    Ok((symbols, type_map))
+}
 
-        let mut buf = [0; 1024];
-        let version = ptr.read_u32::<LE>().ctx(ctx)?;
-        if version != 1 {
-            return Err(Error::Reply(ctx, "unknown type info version", version));
-        }
-        let _subitem_index = ptr.read_u16::<LE>().ctx(ctx)?;
-        let _plc_interface_id = ptr.read_u16::<LE>().ctx(ctx)?;
-        let _reserved = ptr.read_u32::<LE>().ctx(ctx)?;
-        let size = ptr.read_u32::<LE>().ctx(ctx)? as usize;
-        let offset = ptr.read_u32::<LE>().ctx(ctx)?;
-        let base_type = ptr.read_u32::<LE>().ctx(ctx)?;
-        let flags = ptr.read_u32::<LE>().ctx(ctx)?;
-        let len_name = ptr.read_u16::<LE>().ctx(ctx)? as usize;
-        let len_type = ptr.read_u16::<LE>().ctx(ctx)? as usize;
-        let len_comment = ptr.read_u16::<LE>().ctx(ctx)? as usize;
-        let array_dim = ptr.read_u16::<LE>().ctx(ctx)?;
-        let sub_items = ptr.read_u16::<LE>().ctx(ctx)?;
-        ptr.read_exact(&mut buf[..len_name + 1]).ctx(ctx)?;
-        let name = String::from_utf8_lossy(&buf[..len_name]).into_owned();
-        ptr.read_exact(&mut buf[..len_type + 1]).ctx(ctx)?;
-        let typ = String::from_utf8_lossy(&buf[..len_type]).into_owned();
-        ptr = &ptr[len_comment + 1..];
-
-        let mut array = vec![];
-        for _ in 0..array_dim {
-            let lower = ptr.read_i32::<LE>().ctx(ctx)?;
-            let total = ptr.read_i32::<LE>().ctx(ctx)?;
-            array.push((lower, lower + total - 1));
-        }
+fn decode_types(mut data_ptr: &[u8]) -> Result<TypeMap> {
+    let mut type_map = HashMap::new();
+    while !data_ptr.is_empty() {
+        let entry_ptr = next_entry(&mut data_ptr, "decoding type info")?;
+        let typ = decode_type_info(entry_ptr, None)?.expect("base type");
+        type_map.insert(typ.name.clone(), typ);
+    }
+    Ok(type_map)
+}
 
-        if let Some(parent) = parent {
-            assert_eq!(sub_items, 0);
-            // Offset -1 marks that the field is placed somewhere else in memory
-            // (e.g. AT %Mxx).
-            let offset = if offset == 0xFFFF_FFFF { None } else { Some(offset) };
-            parent.fields.push(Field { name, typ, offset, size, array, base_type, flags });
-            Ok(None)
-        } else {
-            assert_eq!(offset, 0);
-            let mut typinfo = Type { name, size, array, base_type, flags, fields: Vec::new() };
-
-            for _ in 0..sub_items {
-                let sub_size = ptr.read_u32::<LE>().ctx(ctx)? as usize;
-                let (sub_ptr, rest) = ptr.split_at(sub_size - 4);
-                decode_type_info(sub_ptr, Some(&mut typinfo))?;
-                ptr = rest;
-            }
+fn decode_type_info(mut ptr: &[u8], parent: Option<&mut Type>) -> Result<Option<Type>> {
+    let ctx = "decoding type info";
 
-            // following fields (variable length), which we jump over:
-            // - type GUID if flags has Type GUID
-            // - copy mask of *size* bytes
-            // - # of methods and method entries if flags has Method infos
-            // - # of attributes and attributes if flags has Attributes
-            // - # of enum infos and enum infos if flags has Enum infos
-            // - refactor infos if flags has Refactor infos
-            // - splevels if flags has SP levels
+    let version = ptr.read_u32::<LE>().ctx(ctx)?;
+    if version != 1 {
+        return Err(Error::Reply(ctx, "unknown type info version", version));
+    }
+    let _subitem_index = ptr.read_u16::<LE>().ctx(ctx)?;
+    let _plc_interface_id = ptr.read_u16::<LE>().ctx(ctx)?;
+    let _reserved = ptr.read_u32::<LE>().ctx(ctx)?;
+    let size = ptr.read_u32::<LE>().ctx(ctx)? as usize;
+    let offset = ptr.read_u32::<LE>().ctx(ctx)?;
+    let base_type = ptr.read_u32::<LE>().ctx(ctx)?;
+    let flags = ptr.read_u32::<LE>().ctx(ctx)?;
+    let len_name = ptr.read_u16::<LE>().ctx(ctx)? as usize;
+    let len_type = ptr.read_u16::<LE>().ctx(ctx)? as usize;
+    let len_comment = ptr.read_u16::<LE>().ctx(ctx)? as usize;
+    let array_dim = ptr.read_u16::<LE>().ctx(ctx)?;
+    let sub_items = ptr.read_u16::<LE>().ctx(ctx)?;
+    let name = read_string(&mut ptr, len_name, ctx)?;
+    let typ = read_string(&mut ptr, len_type, ctx)?;
+    let comment = read_string(&mut ptr, len_comment, ctx)?;
+
+    let mut array = vec![];
+    for _ in 0..array_dim {
+        let lower = ptr.read_i32::<LE>().ctx(ctx)?;
+        let total = ptr.read_i32::<LE>().ctx(ctx)?;
+        array.push((lower, lower.wrapping_add(total).wrapping_sub(1)));
+    }
 
-            Ok(Some(typinfo))
+    if let Some(parent) = parent {
+        // Offset -1 marks that the field is placed somewhere else in memory
+        // (e.g. AT %Mxx).
+        let offset = if offset == 0xFFFF_FFFF { None } else { Some(offset) };
+        // Sub-items of the field are not needed, since the field's type is
+        // contained in the type map as well.
+        parent.fields.push(Field { name, typ, comment, offset, size, array, base_type, flags });
+        Ok(None)
+    } else {
+        let mut typinfo = Type { name, typ, comment, size, array, base_type, flags,
+                                 fields: Vec::new() };
+
+        for _ in 0..sub_items {
+            let sub_ptr = next_entry(&mut ptr, ctx)?;
+            decode_type_info(sub_ptr, Some(&mut typinfo))?;
         }
-    }
 
-    while !data_ptr.is_empty() {
-        let entry_size = data_ptr.read_u32::<LE>()
-            .ctx("decoding type info")? as usize;
-        let (entry_ptr, rest) = data_ptr.split_at(entry_size - 4);
-        let typ = decode_type_info(entry_ptr, None)?.expect("base type");
-        type_map.insert(typ.name.clone(), typ);
-        data_ptr = rest;
+        // following fields (variable length), which we jump over:
+        // - type GUID if flags has Type GUID
+        // - copy mask of *size* bytes
+        // - # of methods and method entries if flags has Method infos
+        // - # of attributes and attributes if flags has Attributes
+        // - # of enum infos and enum infos if flags has Enum infos
+        // - refactor infos if flags has Refactor infos
+        // - splevels if flags has SP levels
+
+        Ok(Some(typinfo))
     }
+}
 
-    // Decode the symbol info.
+fn decode_symbols(mut data_ptr: &[u8]) -> Result<Vec<Symbol>> {
     let mut symbols = Vec::new();
-    let mut data_ptr = symbol_data.as_slice();
-    let ctx = "decoding symbol info";
     while !data_ptr.is_empty() {
-        let entry_size = data_ptr.read_u32::<LE>().ctx(ctx)? as usize;
-        let (mut entry_ptr, rest) = data_ptr.split_at(entry_size - 4);
-        let ix_group = entry_ptr.read_u32::<LE>().ctx(ctx)?;
-        let ix_offset = entry_ptr.read_u32::<LE>().ctx(ctx)?;
-        let size = entry_ptr.read_u32::<LE>().ctx(ctx)? as usize;
-        let base_type = entry_ptr.read_u32::<LE>().ctx(ctx)?;
-        let flags = entry_ptr.read_u16::<LE>().ctx(ctx)? as u32;
-        let _legacy_array_dim = entry_ptr.read_u16::<LE>().ctx(ctx)?;
-        let len_name = entry_ptr.read_u16::<LE>().ctx(ctx)? as usize;
-        let len_type = entry_ptr.read_u16::<LE>().ctx(ctx)? as usize;
-        let _len_comment = entry_ptr.read_u16::<LE>().ctx(ctx)? as usize;
-        entry_ptr.read_exact(&mut buf[..len_name + 1]).ctx(ctx)?;
-        let name = String::from_utf8_lossy(&buf[..len_name]).into_owned();
-        entry_ptr.read_exact(&mut buf[..len_type + 1]).ctx(ctx)?;
-        let typ = String::from_utf8_lossy(&buf[..len_type]).into_owned();
-        // following fields (variable length), which we jump over:
-        // - comment with \0
-        // - type GUID if flags has Type GUID
-        // - # of attributes and attribute entries if flags has Attributes
-        // - flags2 if flags has Extended flags
-        // - if flags2 has Old names
-
-        symbols.push(Symbol { name, ix_group, ix_offset, typ, size, base_type, flags });
-
-        data_ptr = rest;
+        // The entry length is used to find the next entry, so that additional
+        // fields in newer versions are simply ignored.
+        let entry_ptr = next_entry(&mut data_ptr, "decoding symbol info")?;
+        symbols.push(decode_symbol_entry(entry_ptr)?);
     }
+    Ok(symbols)
+}
 
-    Ok((symbols, type_map))
+fn decode_symbol_entry(mut entry_ptr: &[u8]) -> Result<Symbol> {
+    let ctx = "decoding symbol info";
+    let ix_group = entry_ptr.read_u32::<LE>().ctx(ctx)?;
+    let ix_offset = entry_ptr.read_u32::<LE>().ctx(ctx)?;
+    let size = entry_ptr.read_u32::<LE>().ctx(ctx)? as usize;
+    let base_type = entry_ptr.read_u32::<LE>().ctx(ctx)?;
+    let flags = entry_ptr.read_u16::<LE>().ctx(ctx)? as u32;
+    let _legacy_array_dim = entry_ptr.read_u16::<LE>().ctx(ctx)?;
+    let len_name = entry_ptr.read_u16::<LE>().ctx(ctx)? as usize;
+    let len_type = entry_ptr.read_u16::<LE>().ctx(ctx)? as usize;
+    let len_comment = entry_ptr.read_u16::<LE>().ctx(ctx)? as usize;
+    let name = read_string(&mut entry_ptr, len_name, ctx)?;
+    let typ = read_string(&mut entry_ptr, len_type, ctx)?;
+    let comment = read_string(&mut entry_ptr, len_comment, ctx)?;
+    // following fields (variable length), which we jump over:
+    // - type GUID if flags has Type GUID
+    // - # of attributes and attribute entries if flags has Attributes
+    // - flags2 if flags has Extended flags
+    // - if flags2 has Old names
+
+    Ok(Symbol { name, ix_group, ix_offset, typ, comment, size, base_type, flags })
 }
@@ -1,6 +1,6 @@
 // Code used in the crate test suite.
 
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryInto;
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::net::{TcpListener, TcpStream};
@@ -19,8 +19,14 @@ use crate::client::{AddNotif, AdsHeader, IndexLength, IndexLengthRW};
 use crate::{file, index};
 
 // Test modules.
+#[cfg(feature = "tokio")]
+mod test_async;
 mod test_client;
+#[cfg(feature = "mock")]
+mod test_mock;
 mod test_netid;
+mod test_structs;
+mod test_symbol;
 mod test_udp;
 
 // Since Cargo tests run multi-threaded, start one server per thread and
@@ -35,10 +41,12 @@ thread_local! {
         thread::spawn(move || {
             let mut server = Server {
                 opts: opts_server,
-                state: (crate::AdsState::Run, 0),
+                state: (crate::AdsState::Run as u16, 0),
                 data: vec![0; 1024],
                 file_ptr: None,
+                file_big: false,
                 notif: None,
+                sym_handle: 77,
             };
             for client in socket.incoming().flatten() {
                 // We only need to handle one client concurrently.
@@ -55,9 +63,14 @@ thread_local! {
 pub struct ServerOpts {
     pub timeout: Option<Duration>,
     pub no_reply: bool,
+    pub delay_reply: Option<Duration>,
     pub garbage_header: bool,
     pub bad_notif: bool,
     pub ignore_invokeid: bool,
+    // Close the connection once, after replying to this many requests.
+    pub disconnect_after: Option<usize>,
+    // Declare this packet length for the reply, then close the connection.
+    pub bad_length: Option<u32>,
 }
 
 pub fn config_test_server(opts: ServerOpts) -> u16 {
@@ -73,17 +86,25 @@ struct Server {
     data: Vec<u8>,
     // If the test file is opened for writing, and the read/write position.
     file_ptr: Option<(bool, usize)>,
+    // If the big test file is open, which has 3 MB of patterned data instead
+    // of 888 zero bytes.
+    file_big: bool,
     // If a notification has been added, the (offset, size) to send.
     notif: Option<(usize, usize)>,
-    // The simulated device state.
-    state: (crate::AdsState, u16),
+    // The simulated device state, with the raw ADS state.
+    state: (u16, u16),
+    // The handle given out for the symbol.  A reset toggles it, to simulate
+    // that handles become invalid.
+    sym_handle: u32,
 }
 
 impl Server {
     fn handle_client(&mut self, mut socket: TcpStream) {
+        // Replies are written in several parts, which shouldn't be delayed.
+        socket.set_nodelay(true).unwrap();
         let opts = self.opts.clone();
         loop {
-            let opts = opts.lock().unwrap();
+            let mut opts = opts.lock().unwrap();
             let mut header = AdsHeader::new_zeroed();
             if let Err(e) = socket.read_exact(header.as_bytes_mut()) {
                 if e.kind() == std::io::ErrorKind::UnexpectedEof {
@@ -99,8 +120,18 @@ impl Server {
             if opts.no_reply {
                 return;
             }
+            match opts.disconnect_after {
+                Some(0) => {
+                    opts.disconnect_after = None;
+                    return;
+                }
+                Some(n) => opts.disconnect_after = Some(n - 1),
+                None => (),
+            }
 
             let (reply_data, error) = match header.command.get() {
+                // Simulate a router without the target port.
+                _ if header.dest_port.get() == 9999 => (vec![], 0x6),
                 1 => self.do_devinfo(&data),
                 2 => self.do_read(&data),
                 3 => self.do_write(&data),
@@ -112,6 +143,12 @@ impl Server {
                 _ => (vec![], 0x701),
             };
 
+            // Requests with the "no return" flag don't get a reply.
+            if header.state_flags.get() & 2 != 0 {
+                assert_eq!(header.state_flags.get(), 6);
+                continue;
+            }
+
             // Generate a notification if they are enabled.
             if let Some((off, len)) = &self.notif {
                 self.send_notification(*off, *len, &header, opts.bad_notif, &mut socket);
@@ -134,6 +171,16 @@ impl Server {
                 reply_header.invoke_id = header.invoke_id;
             }
             println!("<<< {:?}", reply_header);
+            if let Some(delay) = opts.delay_reply {
+                thread::sleep(delay);
+            }
+
+            if let Some(length) = opts.bad_length {
+                reply_header.length.set(length);
+                socket.write_all(reply_header.as_bytes()).unwrap();
+                socket.write_all(&reply_data).unwrap();
+                return;
+            }
 
             socket.write_all(reply_header.as_bytes()).unwrap();
             socket.write_all(&reply_data).unwrap();
@@ -186,26 +233,27 @@ impl Server {
             return (vec![], 0x706);
         }
         let mut out = 0u32.to_le_bytes().to_vec();
-        out.write_u16::<LE>(self.state.0 as u16).unwrap();
+        out.write_u16::<LE>(self.state.0).unwrap();
         out.write_u16::<LE>(self.state.1).unwrap();
         (out, 0)
     }
 
     fn do_write_control(&mut self, mut data: &[u8]) -> (Vec<u8>, u32) {
-        if data.len() != 8 {
+        if data.len() < 8 || data.len() != 8 + LE::read_u32(&data[4..]) as usize {
             return (vec![], 0x706);
         }
         let adsstate = data.read_u16::<LE>().unwrap();
         let devstate = data.read_u16::<LE>().unwrap();
         let mut out = vec![];
-        match crate::AdsState::try_from(adsstate) {
-            Err(_) | Ok(crate::AdsState::Invalid) => {
-                out.write_u32::<LE>(0x70B).unwrap();
-            }
-            Ok(adsstate) => {
-                self.state = (adsstate, devstate);
-                out.write_u32::<LE>(0).unwrap();
+        // Unknown states are accepted, like from a newer device.
+        if adsstate == crate::AdsState::Invalid as u16 {
+            out.write_u32::<LE>(0x70B).unwrap();
+        } else {
+            if adsstate == crate::AdsState::Reset as u16 {
+                self.sym_handle ^= 1;
             }
+            self.state = (adsstate, devstate);
+            out.write_u32::<LE>(0).unwrap();
         }
         (out, 0)
     }
@@ -220,9 +268,32 @@ impl Server {
         let len = request.length.get() as usize;
         let mut out = Vec::new();
         out.write_u32::<LE>(0).unwrap();
+        // The symbol version changes together with the symbol handle.
+        if grp == index::GET_SYMVERSION && len == 1 {
+            out.write_u32::<LE>(1).unwrap();
+            out.write_u8(self.sym_handle as u8).unwrap();
+            return (out, 0);
+        }
+        // The time service returns a SYSTEMTIME, here 2022-10-25 02:57:46.500.
+        if grp == index::SYSTEM_TIME && off == 1 && len == 16 {
+            out.write_u32::<LE>(16).unwrap();
+            for &field in &[2022, 10, 2, 25, 2, 57, 46, 500] {
+                out.write_u16::<LE>(field).unwrap();
+            }
+            return (out, 0);
+        }
+        // Simulate bit access.
+        if grp == index::PLC_RW_MX && len == 1 {
+            if off / 8 >= self.data.len() {
+                return (vec![], 0x703);
+            }
+            out.write_u32::<LE>(1).unwrap();
+            out.write_u8((self.data[off / 8] >> (off % 8)) & 1).unwrap();
+            return (out, 0);
+        }
         // Simulate symbol access.
         if grp == index::RW_SYMVAL_BYHANDLE {
-            if off != 77 {
+            if off != self.sym_handle as usize {
                 return (vec![], 0x710);
             }
             off = 1020; // symbol lives at the end of self.data
@@ -247,12 +318,26 @@ impl Server {
         let len = request.length.get() as usize;
 
         if grp == index::RW_SYMVAL_BYHANDLE {
-            if off != 77 {
+            if off != self.sym_handle as usize {
                 return (vec![], 0x710);
             }
             off = 1020;
+        } else if grp == index::PLC_RW_MX {
+            if off / 8 >= self.data.len() {
+                return (vec![], 0x703);
+            }
+            if data.len() != size_of::<IndexLength>() + 1 || len != 1 {
+                return (vec![], 0x706);
+            }
+            let mask = 1 << (off % 8);
+            if data[12] != 0 {
+                self.data[off / 8] |= mask;
+            } else {
+                self.data[off / 8] &= !mask;
+            }
+            return (0u32.to_le_bytes().into(), 0);
         } else if grp == index::RELEASE_SYMHANDLE {
-            if off != 77 {
+            if off != self.sym_handle as usize {
                 return (vec![], 0x710);
             }
             return (0u32.to_le_bytes().into(), 0);
@@ -279,6 +364,13 @@ impl Server {
         let wlen = request.write_length.get() as usize;
         let mut out = 0u32.to_le_bytes().to_vec();
 
+        // Enforce the limits of sum-up requests: 500 single requests and
+        // 64 kB of data (plus the reply's result length).
+        if (index::SUMUP_READ..=index::SUMUP_DELDEVNOTE).contains(&request.index_group.get()) &&
+            (off > 500 || rlen > 0x10000 + 8 || wlen > 0x10000) {
+            return (vec![], 0x705);
+        }
+
         // Simulate file and symbol access.
         match request.index_group.get() {
             index::SUMUP_READ_EX => {
@@ -350,9 +442,11 @@ impl Server {
                 }
             }
             index::FILE_OPEN => {
-                if &data[16..] != b"/etc/passwd" {
-                    return (vec![], 0x70C);
-                }
+                let big = match &data[16..] {
+                    b"/etc/passwd" => false,
+                    b"/var/log/big.log" => true,
+                    _ => return (vec![], 0x70C),
+                };
                 if self.file_ptr.is_some() {
                     return (vec![], 0x708);
                 }
@@ -360,6 +454,7 @@ impl Server {
                 out.write_u32::<LE>(4).unwrap();
                 out.write_u32::<LE>(42).unwrap();
                 self.file_ptr = Some((write, 0));
+                self.file_big = big;
             }
             index::FILE_CLOSE => {
                 if !data[16..].is_empty() {
@@ -382,10 +477,16 @@ impl Server {
             index::FILE_READ => {
                 if let Some((false, ptr)) = &mut self.file_ptr {
                     let cur = *ptr;
-                    *ptr = (*ptr + rlen).min(888);
-                    let amount = *ptr - cur;
-                    out.write_u32::<LE>(amount as u32).unwrap();
-                    out.resize(out.len() + amount, 0);
+                    if self.file_big {
+                        // Like a real server, limit the size of one read.
+                        *ptr = (*ptr + rlen.min(0x10000)).min(3 << 20);
+                        out.write_u32::<LE>((*ptr - cur) as u32).unwrap();
+                        out.extend((cur..*ptr).map(|pos| (pos % 251) as u8));
+                    } else {
+                        *ptr = (*ptr + rlen).min(888);
+                        out.write_u32::<LE>((*ptr - cur) as u32).unwrap();
+                        out.resize(out.len() + *ptr - cur, 0);
+                    }
                 } else {
                     return (vec![], 0x704);
                 }
@@ -400,12 +501,48 @@ impl Server {
                 }
                 out.write_u32::<LE>(0).unwrap();
             }
+            index::FILE_BROWSE => {
+                let entry: (&[u8], u32, u64) = match (off, &data[16..]) {
+                    (1, b"/dir\\*.*") => (b"log.txt", 0x20, 0x1_0000_0010),
+                    (2, b"") => (b"sub", file::DIRECTORY, 0),
+                    _ => return (vec![], 0x70C),
+                };
+                let mut find = vec![0; 324];
+                LE::write_u32(&mut find[0..], off + 1);
+                LE::write_u32(&mut find[4..], entry.1);
+                LE::write_u64(&mut find[8..], 1);
+                LE::write_u64(&mut find[16..], 2);
+                LE::write_u64(&mut find[24..], 0x01D0_0000_0000_0000);
+                LE::write_u32(&mut find[32..], (entry.2 >> 32) as u32);
+                LE::write_u32(&mut find[36..], entry.2 as u32);
+                find[48..][..entry.0.len()].copy_from_slice(entry.0);
+                out.write_u32::<LE>(find.len() as u32).unwrap();
+                out.extend(find);
+            }
             index::GET_SYMHANDLE_BYNAME => {
                 if &data[16..] != b"SYMBOL" {
                     return (vec![], 0x710);
                 }
                 out.write_u32::<LE>(4).unwrap();
-                out.write_u32::<LE>(77).unwrap();
+                out.write_u32::<LE>(self.sym_handle).unwrap();
+            }
+            index::GET_SYMINFO_BYNAME_EX => {
+                if &data[16..] != b"SYMBOL" {
+                    return (vec![], 0x710);
+                }
+                let mut entry = vec![];
+                entry.write_u32::<LE>(index::PLC_RW_M).unwrap();
+                entry.write_u32::<LE>(1020).unwrap();
+                entry.write_u32::<LE>(4).unwrap(); // size
+                entry.write_u32::<LE>(19).unwrap(); // UDINT
+                entry.write_u32::<LE>(8).unwrap(); // flags
+                entry.write_u16::<LE>(6).unwrap();
+                entry.write_u16::<LE>(5).unwrap();
+                entry.write_u16::<LE>(7).unwrap();
+                entry.extend(b"SYMBOL\0UDINT\0comment\0");
+                out.write_u32::<LE>(entry.len() as u32 + 4).unwrap();
+                out.write_u32::<LE>(entry.len() as u32 + 4).unwrap();
+                out.extend(entry);
             }
             _ => return (vec![], 0x702),
         }
@@ -417,10 +554,15 @@ impl Server {
             return (vec![], 0x706);
         }
         let request = AddNotif::read_from(data).unwrap();
-        let off = request.index_offset.get() as usize;
+        let mut off = request.index_offset.get() as usize;
         let len = request.length.get() as usize;
 
-        if request.index_group.get() != index::PLC_RW_M {
+        if request.index_group.get() == index::RW_SYMVAL_BYHANDLE {
+            if off != self.sym_handle as usize {
+                return (vec![], 0x710);
+            }
+            off = 1020;
+        } else if request.index_group.get() != index::PLC_RW_M {
             return (vec![], 0x702);
         }
         if off + len > self.data.len() {
@@ -0,0 +1,129 @@
+//! Test for the async client.
+
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use crate::async_client::{AsyncClient, AsyncDevice};
+use crate::test::{config_test_server, ServerOpts};
+use crate::{AmsAddr, AmsNetId, Error, Source, Timeouts};
+
+fn run_test<Fut: Future<Output = ()>>(opts: ServerOpts, f: impl FnOnce(AsyncClient) -> Fut) {
+    let timeouts = if let Some(tmo) = opts.timeout {
+        Timeouts::new(tmo)
+    } else {
+        Timeouts::none()
+    };
+    let port = config_test_server(opts);
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        let client = AsyncClient::connect(("127.0.0.1", port), timeouts, Source::Auto)
+            .await.unwrap();
+        f(client).await;
+    });
+}
+
+fn device(client: &AsyncClient) -> AsyncDevice<'_> {
+    client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851))
+}
+
+#[test]
+fn test_async_devinfo() {
+    run_test(ServerOpts::default(), |client| async move {
+        let info = device(&client).get_info().await.unwrap();
+        assert_eq!(info.version, 4024);
+        assert_eq!(info.name, "Nice device");
+    })
+}
+
+#[test]
+fn test_async_timeout() {
+    run_test(ServerOpts { timeout: Some(Duration::from_millis(1)),
+                          no_reply: true,
+                          .. Default::default() }, |client| async move {
+        match device(&client).get_info().await.unwrap_err() {
+            Error::Io(_, ioe) if ioe.kind() == io::ErrorKind::TimedOut => (),
+            err => panic!("unexpected error from timeout: {}", err)
+        }
+    })
+}
+
+#[test]
+fn test_async_readwrite() {
+    run_test(ServerOpts::default(), |client| async move {
+        let device = device(&client);
+        device.write(0x4020, 7, &[1, 6, 8, 9]).await.unwrap();
+        let mut buf = [0; 4];
+        device.read_exact(0x4020, 7, &mut buf).await.unwrap();
+        assert_eq!(buf, [1, 6, 8, 9]);
+        assert_eq!(device.read_value::<u8>(0x4020, 8).await.unwrap(), 6);
+
+        // unknown index group
+        assert!(matches!(device.read(0x1234, 0, &mut buf).await.unwrap_err(),
+                         Error::Request { code: 0x702, index_group: 0x1234, length: 4, .. }));
+
+        // the connection is still usable after an error
+        device.write_value(0x4020, 7, &0u32).await.unwrap();
+        assert_eq!(device.read_value::<u32>(0x4020, 7).await.unwrap(), 0);
+    })
+}
+
+#[test]
+fn test_async_overlapping() {
+    run_test(ServerOpts::default(), |client| async move {
+        let device = device(&client);
+        device.write(0x4020, 12, &[1, 2, 3, 4, 5, 6, 7, 8]).await.unwrap();
+
+        // Both requests are sent before any reply is received; each must get
+        // its own reply.
+        let (first, second) = tokio::join!(device.read_value::<[u8; 4]>(0x4020, 12),
+                                           device.read_value::<[u8; 4]>(0x4020, 16));
+        assert_eq!(first.unwrap(), [1, 2, 3, 4]);
+        assert_eq!(second.unwrap(), [5, 6, 7, 8]);
+    })
+}
+
+#[test]
+fn test_async_notification() {
+    use crate::notif::*;
+    run_test(ServerOpts::default(), |client| async move {
+        let device = device(&client);
+        let mut stream = client.get_notification_stream().unwrap();
+        assert!(client.get_notification_stream().is_none());
+
+        let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                     Duration::from_secs(1), Duration::from_secs(1));
+        device.write(0x4020, 0, &[4, 4, 1, 1]).await.unwrap();
+        let handle = device.add_notification(0x4020, 0, &attrib).await.unwrap();
+        device.write(0x4020, 0, &[8, 8, 1, 1]).await.unwrap();
+        device.delete_notification(handle).await.unwrap();
+
+        let first = stream.next().await.unwrap().unwrap();
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.samples().next().unwrap().data, &[4, 4, 1, 1]);
+        assert_eq!(second.samples().next().unwrap().data, &[8, 8, 1, 1]);
+    })
+}
+
+#[test]
+fn test_async_notification_overflow() {
+    use crate::notif::*;
+    run_test(ServerOpts::default(), |client| async move {
+        let device = device(&client);
+        client.notification_buffer(Some(1));
+        let mut stream = client.get_notification_stream().unwrap();
+
+        // The server sends a notification after each request.
+        let handle = device.add_notification(0x4020, 0, &Attributes::on_change(4)).await.unwrap();
+        device.write(0x4020, 0, &[1, 2, 3, 4]).await.unwrap();
+        device.write(0x4020, 0, &[5, 6, 7, 8]).await.unwrap();
+        device.delete_notification(handle).await.unwrap();
+
+        // Only the first notification was kept; the others are reported as
+        // lost before it.
+        assert!(matches!(stream.next().await.unwrap(),
+                         Err(Error::Reply(_, "notifications were lost", 2))));
+        let notif = stream.next().await.unwrap().unwrap();
+        assert_eq!(notif.samples().next().unwrap().data, &[0, 0, 0, 0]);
+    })
+}
@@ -2,12 +2,12 @@
 
 use std::convert::TryFrom;
 use std::io::{self, Read, Write};
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use crate::test::{config_test_server, ServerOpts};
-use crate::{AmsAddr, AmsNetId, Client, Device, Error, Source, Timeouts};
+use crate::{AmsAddr, AmsNetId, Client, Device, Error, SharedDevice, Source, Timeouts};
 
-fn run_test(opts: ServerOpts, f: impl Fn(Device)) {
+pub(super) fn run_test(opts: ServerOpts, f: impl Fn(Device)) {
     let timeouts = if let Some(tmo) = opts.timeout {
         Timeouts::new(tmo)
     } else {
@@ -18,6 +18,117 @@ fn run_test(opts: ServerOpts, f: impl Fn(Device)) {
     f(client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851)));
 }
 
+#[test]
+fn test_connect_checked() {
+    let port = config_test_server(ServerOpts::default());
+    let netid = AmsNetId::new(1, 2, 3, 4, 5, 6);
+    let client = Client::connect_checked(("127.0.0.1", port), Timeouts::none(), Source::Auto,
+                                         AmsAddr::new(netid, 851)).unwrap();
+    drop(client);
+
+    let err = match Client::connect_checked(("127.0.0.1", port), Timeouts::none(),
+                                            Source::Auto, AmsAddr::new(netid, 9999)) {
+        Ok(_) => panic!("connect_checked should fail"),
+        Err(err) => err,
+    };
+    assert!(matches!(err, Error::Ads("checking target", _, 6)));
+    assert!(err.to_string().contains("Target port not found"));
+}
+
+#[test]
+fn test_connection_info() {
+    let port = config_test_server(ServerOpts::default());
+    let source = AmsAddr::new(AmsNetId::new(10, 1, 2, 3, 1, 1), 40000);
+    let client = Client::new(("127.0.0.1", port), Timeouts::none(),
+                             Source::Addr(source)).unwrap();
+    let info = client.connection_info();
+    assert_eq!(info.peer_addr, ([127, 0, 0, 1], port).into());
+    assert_eq!(info.local_addr.ip(), std::net::Ipv4Addr::LOCALHOST);
+    assert_eq!(info.source, source);
+    assert!(!info.source_requested);
+    assert_eq!(info.reconnects, 0);
+}
+
+#[test]
+fn test_auto_source_port() {
+    // Capture the source port from the first AMS header on two connections.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        (0..2).map(|_| {
+            let mut socket = listener.accept().unwrap().0;
+            let mut header = [0; 38];
+            socket.read_exact(&mut header).unwrap();
+            u16::from_le_bytes([header[20], header[21]])
+        }).collect::<Vec<_>>()
+    });
+
+    let timeouts = Timeouts::new(Duration::from_secs(1));
+    let client1 = Client::new(("127.0.0.1", port), timeouts, Source::Auto).unwrap();
+    let client2 = Client::new(("127.0.0.1", port), timeouts, Source::Auto).unwrap();
+    assert_ne!(client1.source().port(), client2.source().port());
+    let addr = AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851);
+    // The server closes the connection without replying.
+    assert!(client1.device(addr).get_state().is_err());
+    assert!(client2.device(addr).get_state().is_err());
+    assert_eq!(server.join().unwrap(), [client1.source().port(), client2.source().port()]);
+
+    let port = config_test_server(ServerOpts::default());
+    let client = Client::new(("127.0.0.1", port), Timeouts::none(),
+                             Source::AutoPort(50000)).unwrap();
+    assert_eq!(client.source(), AmsAddr::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 50000));
+    client.device(addr).get_state().unwrap();
+}
+
+#[test]
+fn test_threads() {
+    let port = config_test_server(ServerOpts::default());
+    let client = Client::new(("127.0.0.1", port), Timeouts::none(), Source::Auto).unwrap();
+    let client = std::sync::Arc::new(client);
+    let addr = AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851);
+
+    let threads = (0..4u32).map(|i| {
+        let client = client.clone();
+        std::thread::spawn(move || {
+            let device = client.device(addr);
+            for j in 0..50u32 {
+                device.write_value(0x4020, 4 * i, &(1000 * i + j)).unwrap();
+                assert_eq!(device.read_value::<u32>(0x4020, 4 * i).unwrap(), 1000 * i + j);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let device = client.device(addr);
+    for i in 0..4 {
+        assert_eq!(device.read_value::<u32>(0x4020, 4 * i).unwrap(), 1000 * i + 49);
+    }
+}
+
+#[test]
+fn test_shared_device() {
+    let port = config_test_server(ServerOpts::default());
+    let client = Client::new(("127.0.0.1", port), Timeouts::none(), Source::Auto).unwrap();
+    let device = SharedDevice::new(std::sync::Arc::new(client),
+                                   AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+
+    let threads = (0..4u32).map(|i| {
+        let device = device.clone();
+        std::thread::spawn(move || {
+            for j in 0..50u32 {
+                device.device().write_value(0x4020, 4 * i, &(1000 * i + j)).unwrap();
+                assert_eq!(device.device().read_value::<u32>(0x4020, 4 * i).unwrap(),
+                           1000 * i + j);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(device.device().read_value::<u32>(0x4020, 12).unwrap(), 3049);
+}
+
 #[test]
 fn test_garbage_packet() {
     run_test(ServerOpts { garbage_header: true, .. Default::default() }, |device| {
@@ -26,6 +137,23 @@ fn test_garbage_packet() {
     })
 }
 
+#[test]
+fn test_bad_packet_length() {
+    // The connection is closed in the middle of the packet.
+    run_test(ServerOpts { bad_length: Some(1000), .. Default::default() }, |device| {
+        match device.get_info().unwrap_err() {
+            Error::Io("reading rest of packet", ioe) =>
+                assert_eq!(ioe.kind(), io::ErrorKind::UnexpectedEof),
+            err => panic!("unexpected error: {}", err),
+        }
+    });
+    // A huge length is rejected before allocating a buffer for it.
+    run_test(ServerOpts { bad_length: Some(u32::MAX), .. Default::default() }, |device| {
+        assert!(matches!(device.get_info().unwrap_err(),
+                         Error::Reply(_, "packet length exceeds maximum", u32::MAX)));
+    });
+}
+
 #[test]
 fn test_timeout() {
     run_test(ServerOpts { timeout: Some(Duration::from_millis(1)),
@@ -39,11 +167,75 @@ fn test_timeout() {
     })
 }
 
+#[test]
+fn test_delayed_reply() {
+    run_test(ServerOpts { timeout: Some(Duration::from_millis(50)),
+                          delay_reply: Some(Duration::from_millis(100)),
+                          .. Default::default() }, |device| {
+        assert_eq!(device.client.read_timeout(), Some(Duration::from_millis(50)));
+        match device.get_info().unwrap_err() {
+            Error::Io(_, ioe) if ioe.kind() == io::ErrorKind::TimedOut => (),
+            err => panic!("unexpected error from timeout: {}", err)
+        }
+
+        // The late reply to the first request is discarded.
+        device.client.set_read_timeout(None);
+        assert_eq!(device.get_info().unwrap().version, 4024);
+    })
+}
+
 #[test]
 fn test_wrong_invokeid() {
-    run_test(ServerOpts { ignore_invokeid: true, .. Default::default() }, |device| {
+    let opts = ServerOpts { ignore_invokeid: true, timeout: Some(Duration::from_millis(100)),
+                            .. Default::default() };
+    run_test(opts, |device| {
+        // The reply is discarded, so the request times out.
         assert!(matches!(device.get_info().unwrap_err(),
-                         Error::Reply(_, "unexpected invoke ID", 0)));
+                         Error::Io(_, e) if e.kind() == io::ErrorKind::TimedOut));
+        assert_eq!(device.client.connection_info().unknown_replies, 1);
+    })
+}
+
+#[test]
+fn test_raw_command() {
+    run_test(ServerOpts::default(), |device| {
+        // ReadDeviceInfo: result, major, minor, version, name
+        let reply = device.raw_command(1, &[]).unwrap();
+        assert_eq!(reply.len(), 24);
+        assert_eq!(&reply[..8], &[0, 0, 0, 0, 7, 1, 0xb8, 0xf]);
+        assert_eq!(&reply[8..19], b"Nice device");
+
+        // unknown command
+        assert!(matches!(device.raw_command(99, &[]),
+                         Err(Error::Ads("raw command", _, 0x701))));
+    })
+}
+
+#[test]
+fn test_plc_time() {
+    run_test(ServerOpts::default(), |device| {
+        assert!(matches!(device.read_plc_time(), Err(Error::Io("reading PLC time", _))));
+    });
+    let port = config_test_server(ServerOpts::default());
+    let client = Client::new(("127.0.0.1", port), Timeouts::none(), Source::Auto).unwrap();
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6),
+                                            crate::ports::SYSTEM_SERVICE));
+    assert_eq!(device.read_plc_time().unwrap(),
+               UNIX_EPOCH + Duration::from_millis(1_666_666_666_500));
+}
+
+#[test]
+fn test_write_no_reply() {
+    run_test(ServerOpts::default(), |device| {
+        // The server checks the flags, and doesn't reply.
+        device.write_no_reply(0x4020, 7, &[1, 2, 3]).unwrap();
+        let mut data = [0; 3];
+        device.read_exact(0x4020, 7, &mut data).unwrap();
+        assert_eq!(data, [1, 2, 3]);
+
+        // Errors are not detected.
+        device.write_no_reply(0x5555, 0, &[1]).unwrap();
+        assert!(device.get_state().is_ok());
     })
 }
 
@@ -51,17 +243,82 @@ fn test_wrong_invokeid() {
 fn test_devinfo() {
     run_test(ServerOpts::default(), |device| {
         let info = device.get_info().unwrap();
+        assert_eq!((info.major, info.minor), (7, 1));
         assert_eq!(info.version, 4024);
         assert_eq!(info.name, "Nice device");
     })
 }
 
+#[test]
+fn test_addresses() {
+    run_test(ServerOpts::default(), |device| {
+        assert_eq!(device.addr(), AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+        // auto-generated from the local IP
+        let source = device.client.source();
+        assert_eq!(source.netid(), AmsNetId::new(127, 0, 0, 1, 1, 1));
+        assert!(source.port() >= 49152);
+        // the local NetID is mapped to the source NetID
+        let local = device.client.device(AmsAddr::new(AmsNetId::local(), 10000));
+        assert_eq!(local.addr(), AmsAddr::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 10000));
+        // the address can be given as a tuple
+        let plc = device.client.device((AmsNetId::local(), crate::ports::Port::Tc3Plc2));
+        assert_eq!(plc.addr(), AmsAddr::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 852));
+        let plc = device.client.device((AmsNetId::local(), 852));
+        assert_eq!(plc.addr(), AmsAddr::new(AmsNetId::new(127, 0, 0, 1, 1, 1), 852));
+    })
+}
+
+#[test]
+fn test_ip_target() {
+    let (socket_addr, target) = crate::client::ip_target([192, 168, 1, 50].into(), 851);
+    assert_eq!(socket_addr, "192.168.1.50:48898".parse().unwrap());
+    assert_eq!(target, AmsAddr::new(AmsNetId::new(192, 168, 1, 50, 1, 1), 851));
+}
+
+#[test]
+fn test_explicit_source() {
+    let port = config_test_server(ServerOpts::default());
+    let source = AmsAddr::new(AmsNetId::new(10, 1, 2, 3, 1, 1), 32000);
+    let client = Client::new(("127.0.0.1", port), Timeouts::new(Duration::from_secs(1)),
+                             Source::Addr(source)).unwrap();
+    assert_eq!(client.source(), source);
+    // The server sends replies to the source address of the request; the
+    // client only accepts replies sent to its own address.
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+    assert_eq!(device.get_info().unwrap().name, "Nice device");
+}
+
+#[test]
+fn test_multiple_ports() {
+    use crate::notif::Attributes;
+    run_test(ServerOpts::default(), |device| {
+        let netid = device.addr().netid();
+        let other = device.client.device(AmsAddr::new(netid, 852));
+        assert_eq!(other.addr().port(), 852);
+        device.write(0x4020, 0, &[1]).unwrap();
+        assert_eq!(other.read_value::<u8>(0x4020, 0).unwrap(), 1);
+
+        let handle = other.add_notification(0x4020, 0, &Attributes::on_change(1)).unwrap();
+        other.delete_notification(handle).unwrap();
+    })
+}
+
 #[test]
 fn test_state() {
     run_test(ServerOpts::default(), |device| {
         device.write_control(crate::AdsState::Config, 42).unwrap();
         assert_eq!(device.get_state().unwrap(), (crate::AdsState::Config, 42));
         assert!(device.write_control(crate::AdsState::Invalid, 42).is_err());
+
+        device.write_control_data(crate::AdsState::Run, 1, b"data").unwrap();
+        assert_eq!(device.get_state().unwrap(), (crate::AdsState::Run, 1));
+        assert_eq!(device.get_state_raw().unwrap(), (5, 1));
+
+        // unknown states are passed through by get_state_raw
+        device.raw_command(5, &[100, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(device.get_state_raw().unwrap(), (100, 0));
+        assert!(matches!(device.get_state(), Err(Error::Reply("read state", _, 100))));
+        device.write_control(crate::AdsState::Run, 0).unwrap();
     })
 }
 
@@ -75,15 +332,149 @@ fn test_readwrite() {
         assert_eq!(data, buf);
 
         assert!(matches!(device.read_exact(0x4021, 0, &mut buf),
-                         Err(Error::Ads(_, _, 0x702))));
+                         Err(Error::Request { code: 0x702, index_group: 0x4021, .. })));
         assert!(matches!(device.read_exact(0x4020, 98765, &mut buf),
-                         Err(Error::Ads(_, _, 0x703))));
+                         Err(Error::Request { code: 0x703, index_offset: 98765, length: 4, .. })));
 
         device.write_value(0x4020, 7, &0xdeadbeef_u32).unwrap();
         assert!(device.read_value::<u32>(0x4020, 7).unwrap() == 0xdeadbeef);
     })
 }
 
+#[test]
+fn test_value_types() {
+    use zerocopy::byteorder::{I16, I32, I64, U16, U64, LE};
+    run_test(ServerOpts::default(), |device| {
+        device.write_value(0x4020, 0, &-5_i8).unwrap();
+        assert_eq!(device.read_value::<i8>(0x4020, 0).unwrap(), -5);
+        assert_eq!(device.read_value::<u8>(0x4020, 0).unwrap(), 251);
+
+        device.write_value(0x4020, 0, &I16::<LE>::new(-2)).unwrap();
+        assert_eq!(device.read_value::<I16<LE>>(0x4020, 0).unwrap().get(), -2);
+        assert_eq!(device.read_value::<U16<LE>>(0x4020, 0).unwrap().get(), 0xfffe);
+
+        device.write_value(0x4020, 0, &I32::<LE>::new(i32::MIN)).unwrap();
+        assert_eq!(device.read_value::<I32<LE>>(0x4020, 0).unwrap().get(), i32::MIN);
+
+        device.write_value(0x4020, 0, &I64::<LE>::new(-1)).unwrap();
+        assert_eq!(device.read_value::<U64<LE>>(0x4020, 0).unwrap().get(), u64::MAX);
+
+        device.write_value(0x4020, 0, &1.5_f32).unwrap();
+        assert_eq!(device.read_value::<f32>(0x4020, 0).unwrap(), 1.5);
+        device.write_value(0x4020, 0, &-0.25_f64).unwrap();
+        assert_eq!(device.read_value::<f64>(0x4020, 0).unwrap(), -0.25);
+
+        device.write_value(0x4020, 0, &[1_u8, 2, 3]).unwrap();
+        assert_eq!(device.read_value::<[u8; 3]>(0x4020, 0).unwrap(), [1, 2, 3]);
+
+        // reading beyond the end of the device memory
+        assert!(device.read_value::<[u8; 16]>(0x4020, 1020).is_err());
+    })
+}
+
+#[test]
+fn test_struct_access() {
+    use std::convert::TryInto;
+    use zerocopy::byteorder::{LE, U16};
+    use zerocopy::{AsBytes, FromBytes};
+
+    #[derive(FromBytes, AsBytes, Default)]
+    #[repr(C, packed)]
+    struct Inner {
+        flag: u8,
+        _pad: [u8; 1],
+        count: U16<LE>,
+    }
+
+    #[derive(FromBytes, AsBytes, Default)]
+    #[repr(C, packed)]
+    struct Outer {
+        inner: Inner,
+        name: crate::strings::String<7>,
+        value: f32,
+    }
+
+    run_test(ServerOpts::default(), |device| {
+        let mut data = vec![1, 0xff, 0x34, 0x12];
+        data.extend(b"motor\0\0\0");
+        data.extend(2.5_f32.to_le_bytes());
+        device.write(0x4020, 0, &data).unwrap();
+
+        let value: Outer = device.read_value(0x4020, 0).unwrap();
+        assert_eq!(value.inner.flag, 1);
+        assert_eq!(value.inner.count.get(), 0x1234);
+        let (name, val) = (value.name, value.value);
+        assert!(name == "motor");
+        assert_eq!(val, 2.5);
+
+        let value = Outer { name: "pump".try_into().unwrap(), .. value };
+        device.write_value(0x4020, 0, &value).unwrap();
+        let mut buf = [0; 16];
+        device.read_exact(0x4020, 0, &mut buf).unwrap();
+        assert_eq!(&buf[4..12], b"pump\0\0\0\0");
+        assert_eq!(buf[1], 0xff);
+    })
+}
+
+#[test]
+fn test_array_access() {
+    use zerocopy::byteorder::{I16, LE};
+    run_test(ServerOpts::default(), |device| {
+        let values = [1.5_f32, -2.0, 0.25];
+        device.write_array(0x4020, 0, &values).unwrap();
+        assert_eq!(device.read_array::<f32>(0x4020, 0, 3).unwrap(), values);
+        assert_eq!(device.read_array::<u8>(0x4020, 0, 4).unwrap(), 1.5_f32.to_le_bytes());
+
+        let values = [I16::<LE>::new(-1), I16::new(300)];
+        device.write_array(0x4020, 0, &values).unwrap();
+        let read = device.read_array::<I16<LE>>(0x4020, 0, 2).unwrap();
+        assert_eq!(read.iter().map(|v| v.get()).collect::<Vec<_>>(), [-1, 300]);
+
+        assert!(device.read_array::<f32>(0x4020, 0, 0).unwrap().is_empty());
+        // beyond the end of the device memory
+        assert!(device.read_array::<u32>(0x4020, 1016, 4).is_err());
+    })
+}
+
+#[test]
+fn test_time_types() {
+    run_test(ServerOpts::default(), |device| {
+        // TIME#1s500ms
+        device.write(0x4020, 0, &1500_u32.to_le_bytes()).unwrap();
+        assert_eq!(device.read_time(0x4020, 0).unwrap(), Duration::from_millis(1500));
+        device.write_time(0x4020, 0, Duration::from_micros(2_500_900)).unwrap();
+        assert_eq!(device.read_time(0x4020, 0).unwrap(), Duration::from_millis(2500));
+        assert!(device.write_time(0x4020, 0, Duration::from_secs(50 * 86400)).is_err());
+
+        // LTIME#1ms1ns
+        device.write(0x4020, 0, &1_000_001_u64.to_le_bytes()).unwrap();
+        assert_eq!(device.read_ltime(0x4020, 0).unwrap(), Duration::from_nanos(1_000_001));
+        device.write_ltime(0x4020, 0, Duration::from_secs(3)).unwrap();
+        assert_eq!(device.read_ltime(0x4020, 0).unwrap(), Duration::from_secs(3));
+
+        // DT#2020-01-01-12:00:00
+        let time = UNIX_EPOCH + Duration::from_secs(1_577_880_000);
+        device.write(0x4020, 0, &1_577_880_000_u32.to_le_bytes()).unwrap();
+        assert_eq!(device.read_date(0x4020, 0).unwrap(), time);
+        device.write_date(0x4020, 0, time + Duration::from_millis(999)).unwrap();
+        assert_eq!(device.read_date(0x4020, 0).unwrap(), time);
+        assert!(device.write_date(0x4020, 0, UNIX_EPOCH - Duration::from_secs(1)).is_err());
+    })
+}
+
+#[test]
+fn test_read_bits() {
+    run_test(ServerOpts::default(), |device| {
+        device.write(0x4020, 0, &0x8105_u16.to_le_bytes()).unwrap();
+        let bits = device.read_bits(0x4020, 0, 16).unwrap();
+        let set: Vec<_> = (0..16).filter(|&i| bits[i]).collect();
+        assert_eq!(set, [0, 2, 8, 15]);
+        // partial bytes are cut off
+        assert_eq!(device.read_bits(0x4020, 0, 3).unwrap(), [true, false, true]);
+        assert!(device.read_bits(0x4020, 0, 0).unwrap().is_empty());
+    })
+}
+
 #[test]
 fn test_multi_requests() {
     use crate::index::*;
@@ -125,6 +516,109 @@ fn test_multi_requests() {
     });
 }
 
+#[test]
+fn test_multi_split() {
+    use crate::client::*;
+    run_test(ServerOpts::default(), |device| {
+        let data = (0..=255).collect::<Vec<u8>>();
+        device.write(0x4020, 0, &data).unwrap();
+
+        // more single requests than allowed in one sum-up request
+        let mut bufs = vec![[0; 1]; MAX_SUM_REQUESTS + 100];
+        let mut reqs = bufs.iter_mut().enumerate()
+                           .map(|(i, buf)| ReadRequest::new(0x4020, (i % 256) as u32, buf))
+                           .collect::<Vec<_>>();
+        assert_eq!(ReadRequest::sum_size(&reqs), (16 + 12*600, 8 + 9*600));
+        device.read_multi(&mut reqs).unwrap();
+        for (i, req) in reqs.iter().enumerate() {
+            assert_eq!(req.data().unwrap(), [i as u8]);
+        }
+
+        // more data than allowed in one sum-up request
+        let mut bufs = vec![[0; 1000]; 70];
+        let mut reqs = bufs.iter_mut().map(|buf| ReadRequest::new(0x4020, 0, buf))
+                           .collect::<Vec<_>>();
+        assert!(ReadRequest::sum_size(&reqs).1 > MAX_SUM_DATA_SIZE);
+        device.read_multi(&mut reqs).unwrap();
+        assert!(reqs.iter().all(|req| req.data().unwrap()[..256] == data[..]));
+
+        let wbufs = vec![[0x55; 1000]; 70];
+        let mut reqs = wbufs.iter().enumerate()
+                            .map(|(i, buf)| WriteRequest::new(0x4020, i as u32 % 2, buf))
+                            .collect::<Vec<_>>();
+        assert!(WriteRequest::sum_size(&reqs).0 > MAX_SUM_DATA_SIZE);
+        device.write_multi(&mut reqs).unwrap();
+        assert!(reqs.iter().all(|req| req.ensure().is_ok()));
+        assert_eq!(device.read_value::<u32>(0x4020, 0).unwrap(), 0x55555555);
+    })
+}
+
+#[test]
+fn test_multi_read_partial_failure() {
+    use crate::client::ReadRequest;
+    run_test(ServerOpts::default(), |device| {
+        device.write(0x4020, 0, b"abcdefgh").unwrap();
+        let mut buf1 = [0; 2];
+        let mut buf2 = [0; 4];
+        let mut buf3 = [0; 6];
+        let mut buf4 = [0; 4];
+        let mut reqs = vec![
+            ReadRequest::new(0x4020, 0, &mut buf1),
+            ReadRequest::new(0x4021, 0, &mut buf2),
+            ReadRequest::new(0x4020, 2, &mut buf3),
+            ReadRequest::new(0x4020, 1022, &mut buf4),
+        ];
+        device.read_multi(&mut reqs).unwrap();
+        assert_eq!(reqs[0].data().unwrap(), b"ab");
+        assert!(matches!(reqs[1].data(), Err(Error::Ads(_, _, 0x702))));
+        assert_eq!(reqs[2].data().unwrap(), b"cdefgh");
+        assert!(matches!(reqs[3].data(), Err(Error::Ads(_, _, 0x703))));
+    })
+}
+
+#[test]
+fn test_multi_write_partial_failure() {
+    use crate::client::WriteRequest;
+    run_test(ServerOpts::default(), |device| {
+        device.write(0x4020, 0, &[0; 6]).unwrap();
+        let mut reqs = vec![
+            WriteRequest::new(0x4020, 0, b"ab"),
+            WriteRequest::new(0x4020, 1023, b"cd"),
+            WriteRequest::new(0x4020, 4, b"ef"),
+        ];
+        device.write_multi(&mut reqs).unwrap();
+        assert!(reqs[0].ensure().is_ok());
+        assert!(matches!(reqs[1].ensure(), Err(Error::Ads(_, _, 0x703))));
+        assert!(reqs[2].ensure().is_ok());
+
+        // the failed write didn't affect the others
+        let mut buf = [0; 6];
+        device.read_exact(0x4020, 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"ab\0\0ef");
+    })
+}
+
+#[test]
+fn test_multi_write_read_mixed_sizes() {
+    use crate::client::WriteReadRequest;
+    use crate::index::GET_SYMHANDLE_BYNAME;
+    run_test(ServerOpts::default(), |device| {
+        let mut buf1 = [0xff; 8];
+        let mut buf2 = [0xff; 4];
+        let mut buf3 = [0xff; 4];
+        let mut reqs = vec![
+            // returns only 4 out of 8 requested bytes
+            WriteReadRequest::new(GET_SYMHANDLE_BYNAME, 0, b"SYMBOL", &mut buf1),
+            WriteReadRequest::new(GET_SYMHANDLE_BYNAME, 0, b"NOSYMBOL", &mut buf2),
+            WriteReadRequest::new(GET_SYMHANDLE_BYNAME, 0, b"SYMBOL", &mut buf3),
+        ];
+        device.write_read_multi(&mut reqs).unwrap();
+        assert_eq!(reqs[0].data().unwrap(), 77u32.to_le_bytes());
+        assert!(matches!(reqs[1].data(), Err(Error::Ads(_, _, 0x710))));
+        assert_eq!(reqs[2].data().unwrap(), 77u32.to_le_bytes());
+    })
+}
+
 #[test]
 fn test_fileaccess() {
     use crate::file::*;
@@ -135,7 +629,8 @@ fn test_fileaccess() {
         assert!(file.read(&mut [0; 4]).is_err());
         file.write_all(b"asdf").unwrap();
         file.flush().unwrap();
-        drop(file);
+        assert_eq!(file.handle(), 42);
+        file.close().unwrap();
 
         let mut file = File::open(device, "/etc/passwd", READ).unwrap();
         assert!(file.write(&[0; 4]).is_err());
@@ -149,11 +644,80 @@ fn test_fileaccess() {
     })
 }
 
+#[test]
+fn test_file_streaming() {
+    use crate::file::*;
+    run_test(ServerOpts::default(), |device| {
+        let mut file = File::open(device, "/var/log/big.log", READ).unwrap();
+        // copy in 4 KB chunks
+        let mut reader = io::BufReader::with_capacity(4096, &mut file);
+        let mut copy = vec![];
+        assert_eq!(io::copy(&mut reader, &mut copy).unwrap(), 3 << 20);
+        assert!(copy.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+        // at EOF, reads return nothing
+        assert_eq!(file.read(&mut [0; 10]).unwrap(), 0);
+        file.close().unwrap();
+
+        // a large buffer is filled in multiple requests
+        let mut file = File::open(device, "/var/log/big.log", READ).unwrap();
+        let mut buf = vec![0; 1 << 20];
+        assert_eq!(file.read(&mut buf).unwrap(), MAX_CHUNK_SIZE);
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], (MAX_CHUNK_SIZE % 251) as u8);
+    })
+}
+
+#[test]
+fn test_short_write_read() {
+    use crate::file::*;
+    run_test(ServerOpts::default(), |device| {
+        let file = File::open(device, "/etc/passwd", READ).unwrap();
+        let mut buf = [0xff; 1000];
+        let n = device.write_read(crate::index::FILE_READ, file.handle(), &[], &mut buf).unwrap();
+        // the file has only 888 bytes
+        assert_eq!(n, 888);
+        assert!(buf[..n].iter().all(|&b| b == 0));
+        assert!(buf[n..].iter().all(|&b| b == 0xff));
+
+        // now at end of file
+        let data = device.write_read_vec(crate::index::FILE_READ, file.handle(), &[], 1000).unwrap();
+        assert!(data.is_empty());
+        file.close().unwrap();
+        let file = File::open(device, "/etc/passwd", READ).unwrap();
+        let data = device.write_read_vec(crate::index::FILE_READ, file.handle(), &[], 1000).unwrap();
+        assert_eq!(data.len(), 888);
+
+        let data = device.read_vec(0x4020, 7, 5).unwrap();
+        assert_eq!(data.len(), 5);
+    })
+}
+
+#[test]
+fn test_read_dir() {
+    use crate::file::*;
+    run_test(ServerOpts::default(), |device| {
+        let entries = read_dir(device, "/dir").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, b"log.txt");
+        assert_eq!(entries[0].size, 0x1_0000_0010);
+        assert!(!entries[0].is_directory());
+        assert_eq!((entries[0].created, entries[0].accessed), (1, 2));
+        assert_eq!(entries[0].modified, 0x01D0_0000_0000_0000);
+        assert!(entries[0].modified_time() > std::time::UNIX_EPOCH);
+        assert_eq!(entries[1].name, b"sub");
+        assert!(entries[1].is_directory());
+
+        assert_eq!(listdir(device, "/dir").unwrap()[1], (b"sub".to_vec(), DIRECTORY, 0));
+        assert!(read_dir(device, "/nothing").unwrap().is_empty());
+    })
+}
+
 #[test]
 fn test_symbolaccess() {
     use crate::symbol::*;
     run_test(ServerOpts::default(), |device| {
-        assert!(Handle::new(device, "blub").is_err());
+        assert!(matches!(Handle::new(device, "blub"),
+                         Err(Error::Request { message: "Symbol not found", code: 0x710, .. })));
         let handle = Handle::new(device, "SYMBOL").unwrap();
         assert!(handle.write(&[1, 2, 3, 4, 5]).is_err());
         assert!(handle.read(&mut [0; 5]).is_err());
@@ -170,6 +734,93 @@ fn test_symbolaccess() {
     })
 }
 
+#[test]
+fn test_symbol_info() {
+    run_test(ServerOpts::default(), |device| {
+        let symbol = device.symbol_info("SYMBOL").unwrap();
+        assert_eq!(symbol.name, "SYMBOL");
+        assert_eq!((symbol.ix_group, symbol.ix_offset), (0x4020, 1020));
+        assert_eq!(symbol.size, 4);
+        assert_eq!(symbol.base_type, 19);
+        assert_eq!(symbol.typ, "UDINT");
+        assert_eq!(symbol.comment, "comment");
+
+        assert!(matches!(device.symbol_info("blub"),
+                         Err(Error::Request { message: "Symbol not found", code: 0x710, .. })));
+    })
+}
+
+#[test]
+fn test_marker() {
+    use crate::MemoryArea;
+
+    assert_eq!((MemoryArea::Marker.byte_group(), MemoryArea::Marker.bit_group()),
+               (0x4020, 0x4021));
+    assert_eq!((MemoryArea::Input.byte_group(), MemoryArea::Input.bit_group()),
+               (0xF020, 0xF021));
+    assert_eq!((MemoryArea::Output.byte_group(), MemoryArea::Output.bit_group()),
+               (0xF030, 0xF031));
+
+    run_test(ServerOpts::default(), |device| {
+        device.write_marker(100, &[0, 0]).unwrap();
+        // %MX100.3 and %MX101.7
+        device.write_marker_bit(803, true).unwrap();
+        device.write_marker_bit(815, true).unwrap();
+        assert_eq!(device.read_marker(100, 2).unwrap(), [0x08, 0x80]);
+        assert!(device.read_marker_bit(803).unwrap());
+        assert!(!device.read_marker_bit(802).unwrap());
+
+        device.write_marker_bit(803, false).unwrap();
+        assert_eq!(device.read_marker(100, 2).unwrap(), [0, 0x80]);
+        assert_eq!(device.read_area(MemoryArea::Marker, 101, 1).unwrap(), [0x80]);
+    })
+}
+
+#[test]
+fn test_write_named() {
+    run_test(ServerOpts::default(), |device| {
+        let results = device.write_named(&[("SYMBOL", vec![1, 2, 3, 4]),
+                                           ("blub", vec![5, 6]),
+                                           ("SYMBOL", vec![5, 6, 7, 8])]).unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(Error::Ads(_, _, 0x710))));
+        assert!(results[2].is_ok());
+        let handle = crate::symbol::Handle::new(device, "SYMBOL").unwrap();
+        assert_eq!(handle.read_value::<[u8; 4]>().unwrap(), [5, 6, 7, 8]);
+
+        assert!(device.write_named::<&[u8]>(&[]).unwrap().is_empty());
+    })
+}
+
+#[test]
+fn test_symbol_cache() {
+    use crate::symbol::*;
+    run_test(ServerOpts::default(), |device| {
+        let cache = SymbolCache::new(device);
+        assert!(cache.read("blub", &mut [0; 4]).is_err());
+        assert!(cache.is_empty());
+
+        cache.write_value("SYMBOL", &0x12345678_u32).unwrap();
+        assert_eq!(cache.read_value::<u32>("SYMBOL").unwrap(), 0x12345678);
+        assert_eq!(cache.handle("SYMBOL").unwrap(), 77);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(device.symbol_version().unwrap(), 77);
+
+        // After a reset, the old handle is invalid, and a new one is created.
+        device.write_control(crate::AdsState::Reset, 0).unwrap();
+        assert_eq!(device.symbol_version().unwrap(), 76);
+        assert_eq!(cache.read_value::<u32>("SYMBOL").unwrap(), 0x12345678);
+        assert_eq!(cache.handle("SYMBOL").unwrap(), 76);
+
+        cache.invalidate();
+        assert!(cache.is_empty());
+        device.write_control(crate::AdsState::Reset, 0).unwrap();
+        device.write_control(crate::AdsState::Run, 0).unwrap();
+        assert_eq!(cache.handle("SYMBOL").unwrap(), 77);
+    })
+}
+
 #[test]
 fn test_notification() {
     use crate::notif::*;
@@ -200,6 +851,214 @@ fn test_notification() {
     })
 }
 
+#[test]
+fn test_reconnect() {
+    use crate::client::ReconnectPolicy;
+    use crate::notif::*;
+    use std::sync::{Arc, Mutex};
+
+    let port = config_test_server(ServerOpts { disconnect_after: Some(1), .. Default::default() });
+    let policy = ReconnectPolicy { max_attempts: 3, backoff: Duration::from_millis(10) };
+    let client = Client::connect_with(("127.0.0.1", port), Timeouts::none(),
+                                      Source::Auto, policy).unwrap();
+    let reissued = Arc::new(Mutex::new(Vec::new()));
+    let reissued_cb = reissued.clone();
+    client.on_reconnect(move |handles| reissued_cb.lock().unwrap().extend_from_slice(handles));
+
+    let addr = AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851);
+    let device = client.device(addr);
+    let handle = device.add_notification(0x4020, 0, &Attributes::on_change(4)).unwrap();
+
+    // The server closes the connection on this request, and the client
+    // transparently reconnects.
+    assert_eq!(device.get_info().unwrap().version, 4024);
+    assert_eq!(*reissued.lock().unwrap(), [(addr, handle, 132)]);
+    assert_eq!(client.connection_info().reconnects, 1);
+    device.delete_notification(132).unwrap();
+}
+
+#[test]
+fn test_reconnect_disabled() {
+    use crate::client::ReconnectPolicy;
+
+    let port = config_test_server(ServerOpts { disconnect_after: Some(1), .. Default::default() });
+    let policy = ReconnectPolicy { max_attempts: 0, backoff: Duration::from_millis(10) };
+    let client = Client::connect_with(("127.0.0.1", port), Timeouts::none(),
+                                      Source::Auto, policy).unwrap();
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+
+    device.get_state().unwrap();
+    assert!(device.get_info().is_err());
+    assert_eq!(client.connection_info().reconnects, 0);
+}
+
+#[test]
+fn test_unmatched_while_adding() {
+    use crate::notif::Attributes;
+    run_test(ServerOpts::default(), |device| {
+        // The server sends a sample for the new handle before the reply:
+        // it is not unmatched.
+        device.add_notification(0x4020, 0, &Attributes::on_change(4)).unwrap();
+        assert_eq!(device.client.stats().unmatched, 0);
+
+        // Now it sends a sample from another device with the same handle,
+        // which stays unknown since the add fails.
+        let other = device.client.device(AmsAddr::new(device.addr().netid(), 852));
+        assert!(other.add_notification(0x4021, 0, &Attributes::on_change(4)).is_err());
+        assert_eq!(device.client.stats().unmatched, 1);
+    })
+}
+
+#[test]
+fn test_keepalive() {
+    run_test(ServerOpts::default(), |device| {
+        device.client.enable_keepalive(Duration::from_millis(20));
+        device.get_state().unwrap();
+        // Replies to the keepalive requests must not be taken as replies to
+        // the following requests.
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(device.get_info().unwrap().version, 4024);
+        std::thread::sleep(Duration::from_millis(100));
+        device.get_state().unwrap();
+    })
+}
+
+#[test]
+fn test_keepalive_dead() {
+    // A server that accepts the connection, but never replies.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || listener.accept().unwrap());
+
+    let client = Client::new(("127.0.0.1", port), Timeouts::new(Duration::from_millis(50)),
+                             Source::Auto).unwrap();
+    let _socket = server.join().unwrap();
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+    assert!(matches!(device.get_state().unwrap_err(),
+                     Error::Io(_, e) if e.kind() == io::ErrorKind::TimedOut));
+
+    client.enable_keepalive(Duration::from_millis(10));
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(matches!(device.get_state().unwrap_err(), Error::Io("sending request", _)));
+}
+
+#[test]
+fn test_no_reconnect() {
+    run_test(ServerOpts { disconnect_after: Some(0), .. Default::default() }, |device| {
+        assert!(matches!(device.get_info().unwrap_err(),
+                         Error::Io(_, e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    })
+}
+
+#[test]
+fn test_interleaved_notification() {
+    use crate::notif::*;
+    use std::time::Duration;
+    // The server sends a notification well before each reply, while the
+    // request is still pending.
+    run_test(ServerOpts { delay_reply: Some(Duration::from_millis(100)), ..Default::default() },
+             |device| {
+        let chan = device.client.get_notification_channel();
+
+        let attrib = Attributes::new(2, TransmissionMode::ServerOnChange,
+                                     Duration::from_secs(1), Duration::from_secs(1));
+        device.write(0x4020, 0, &[3, 3]).unwrap();
+        let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+        device.write(0x4020, 8, &[5, 6, 7, 8]).unwrap();
+        assert_eq!(device.read_value::<[u8; 4]>(0x4020, 8).unwrap(), [5, 6, 7, 8]);
+        device.delete_notification(handle).unwrap();
+
+        // add_notification, write, read
+        for _ in 0..3 {
+            let notif = chan.try_recv().unwrap();
+            assert_eq!(notif.samples().next().unwrap().data, &[3, 3]);
+        }
+    })
+}
+
+#[test]
+fn test_notification_guard() {
+    use crate::notif::*;
+    use std::time::Duration;
+    run_test(ServerOpts::default(), |device| {
+        let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                     Duration::from_secs(1), Duration::from_secs(1));
+        let guard = NotificationGuard::new(device, 0x4020, 0, &attrib).unwrap();
+        let handle = guard.handle();
+        drop(guard);
+        // already deleted by the guard
+        assert!(device.delete_notification(handle).is_err());
+
+        assert!(NotificationGuard::new(device, 0x6789, 0, &attrib).is_err());
+
+        let handle = NotificationGuard::new(device, 0x4020, 0, &attrib).unwrap().into_handle();
+        device.delete_notification(handle).unwrap();
+    })
+}
+
+#[test]
+fn test_notify_symbol() {
+    use crate::notif::*;
+    use std::time::Duration;
+    run_test(ServerOpts::default(), |device| {
+        let chan = device.client.get_notification_channel();
+        device.write(0x4020, 1020, &[1, 2, 3, 4]).unwrap();
+        let guard = device.notify_symbol("SYMBOL", TransmissionMode::ServerOnChange,
+                                         Duration::ZERO, Duration::ZERO).unwrap();
+        let notif = chan.recv_timeout(Duration::from_secs(1)).unwrap();
+        let sample = notif.samples().next().unwrap();
+        assert_eq!(sample.handle, guard.handle());
+        assert_eq!(sample.data, &[1, 2, 3, 4]);
+        let handle = guard.handle();
+        drop(guard);
+        assert!(device.delete_notification(handle).is_err());
+
+        assert!(matches!(device.notify_symbol("NOSYMBOL", TransmissionMode::ServerOnChange,
+                                              Duration::ZERO, Duration::ZERO),
+                         Err(Error::Request { code: 0x710, .. })));
+    })
+}
+
+#[test]
+fn test_watch() {
+    use crate::notif::TransmissionMode;
+    run_test(ServerOpts::default(), |device| {
+        device.write(0x4020, 1020, &[1, 0, 0, 0]).unwrap();
+        let (guard, values) = device.watch::<[u8; 4]>("SYMBOL", TransmissionMode::ServerOnChange)
+                                    .unwrap();
+        // The server sends the first notification right after adding it,
+        // possibly before the watch has been set up.
+        assert_eq!(values.recv_timeout(Duration::from_secs(1)).unwrap(), [1, 0, 0, 0]);
+        drop(guard);
+        // The channel is disconnected.
+        assert!(values.iter().all(|value| value == [1, 0, 0, 0]));
+    })
+}
+
+#[test]
+fn test_watch_reconnect() {
+    use crate::client::ReconnectPolicy;
+    use crate::notif::TransmissionMode;
+
+    // Write the value, get the symbol handle and info, and add the
+    // notification, then disconnect on the next request.
+    let port = config_test_server(ServerOpts { disconnect_after: Some(4), .. Default::default() });
+    let policy = ReconnectPolicy { max_attempts: 3, backoff: Duration::from_millis(10) };
+    let client = Client::connect_with(("127.0.0.1", port), Timeouts::none(),
+                                      Source::Auto, policy).unwrap();
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+    device.write(0x4020, 1020, &[1, 0, 0, 0]).unwrap();
+    let (_guard, values) = device.watch::<[u8; 4]>("SYMBOL", TransmissionMode::ServerOnChange)
+                                 .unwrap();
+    assert_eq!(values.recv_timeout(Duration::from_secs(1)).unwrap(), [1, 0, 0, 0]);
+
+    // The samples sent after reissuing the notification, and after the
+    // request, reach the same channel.
+    device.get_info().unwrap();
+    assert_eq!(client.connection_info().reconnects, 1);
+    assert_eq!(values.try_iter().count(), 2);
+}
+
 #[test]
 fn test_multi_notification() {
     use crate::notif::*;
@@ -226,6 +1085,353 @@ fn test_multi_notification() {
     });
 }
 
+#[test]
+fn test_delete_notification_list() {
+    use crate::notif::*;
+    run_test(ServerOpts::default(), |device| {
+        let attrib = Attributes::on_change(4);
+        let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+        let results = device.delete_notification_list(&[handle, 42]).unwrap();
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(Error::Ads(_, _, 0x714))));
+        // already deleted
+        let results = device.delete_notification_list(&[handle]).unwrap();
+        assert!(results[0].is_err());
+        assert!(device.delete_notification_list(&[]).unwrap().is_empty());
+    })
+}
+
+#[test]
+fn test_multi_notification_order() {
+    use crate::notif::*;
+    use crate::client::*;
+    run_test(ServerOpts::default(), |device| {
+        // The test server supports only one active notification.
+        let attrib = Attributes::on_change(4);
+        let mut reqs = [
+            AddNotifRequest::new(0x4021, 0, &attrib),
+            AddNotifRequest::new(0x4020, 0, &attrib),
+            AddNotifRequest::new(0x4020, 1022, &attrib),
+        ];
+        device.add_notification_multi(&mut reqs).unwrap();
+        assert!(matches!(reqs[0].handle(), Err(Error::Ads(_, _, 0x702))));
+        let handle = reqs[1].handle().unwrap();
+        assert!(matches!(reqs[2].handle(), Err(Error::Ads(_, _, 0x703))));
+
+        let mut reqs = [DelNotifRequest::new(handle + 1), DelNotifRequest::new(handle)];
+        device.delete_notification_multi(&mut reqs).unwrap();
+        assert!(matches!(reqs[0].ensure(), Err(Error::Ads(_, _, 0x714))));
+        assert!(reqs[1].ensure().is_ok());
+        assert!(device.delete_notification(handle).is_err());
+    })
+}
+
+#[test]
+fn test_notification_buffer() {
+    use crate::notif::*;
+    use crate::OverflowPolicy;
+    use std::time::Duration;
+    run_test(ServerOpts::default(), |device| {
+        let attrib = Attributes::new(1, TransmissionMode::ServerOnChange,
+                                     Duration::from_secs(1), Duration::from_secs(1));
+        // Each request generates a notification with the current value.
+        let run = |policy| {
+            device.client.notification_buffer(Some(2), policy);
+            let chan = device.client.get_notification_channel();
+            device.write(0x4020, 0, &[0]).unwrap();
+            let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+            for i in 1..=4 {
+                device.write(0x4020, 0, &[i]).unwrap();
+            }
+            device.delete_notification(handle).unwrap();
+            chan.try_iter().map(|n| n.samples().next().unwrap().data[0]).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(OverflowPolicy::DropOldest), [3, 4]);
+        assert_eq!(device.client.lost_notifications(), 3);
+        assert_eq!(run(OverflowPolicy::DropNewest), [0, 1]);
+        assert_eq!(device.client.lost_notifications(), 6);
+    })
+}
+
+#[test]
+fn test_buffer_pool() {
+    use crate::client::BufferPool;
+    let pool = BufferPool::new(2);
+    let bufs = [pool.take(), pool.take(), pool.take(), vec![0; 0x20000]];
+    let ptrs = bufs.iter().map(|b| b.as_ptr()).collect::<Vec<_>>();
+    for mut buf in bufs {
+        buf.push(1);
+        pool.put(buf);
+    }
+    // Only two buffers are kept, they are cleared, and the large one is never kept.
+    let (a, b) = (pool.take(), pool.take());
+    assert!(a.is_empty() && b.is_empty());
+    assert_eq!([b.as_ptr(), a.as_ptr()], ptrs[..2]);
+    assert!(pool.take().capacity() < 0x20000);
+
+    pool.put(a);
+    pool.set_capacity(0);
+    pool.put(b);
+    assert_eq!(pool.take().capacity(), crate::client::DEFAULT_BUFFER_SIZE);
+}
+
+#[test]
+fn test_parse_frame() {
+    use crate::client::{AmsFrame, AmsHeader};
+    // Reply to a read of 4 bytes, captured from a PLC.
+    let frame = b"\0\0\x2c\0\0\0\x0a\0\0\x05\x01\x01\x21\x80\x05\x20\x74\x05\x01\x01\
+                  \x53\x03\x02\0\x05\0\x0c\0\0\0\0\0\0\0\x2a\0\0\0\
+                  \0\0\0\0\x04\0\0\0\xa4\xe0\xfb\x44";
+    let parsed = AmsFrame::parse(frame).unwrap();
+    assert_eq!(parsed, AmsFrame {
+        header: AmsHeader {
+            target: "10.0.0.5.1.1:32801".parse().unwrap(),
+            source: "5.32.116.5.1.1:851".parse().unwrap(),
+            command: 2,
+            state_flags: 5,
+            data_length: 12,
+            error_code: 0,
+            invoke_id: 42,
+        },
+        payload: frame[38..].to_vec(),
+    });
+
+    assert!(matches!(AmsFrame::parse(&frame[..30]), Err(Error::Reply(_, "frame too short", 30))));
+    assert!(matches!(AmsFrame::parse(&frame[..49]), Err(Error::Reply(_, _, 12))));
+    let mut router_msg = frame.to_vec();
+    router_msg[0] = 1;
+    assert!(matches!(AmsFrame::parse(&router_msg), Err(Error::Reply(_, _, 1))));
+}
+
+#[test]
+fn test_recv_frame() {
+    use crate::notif::*;
+    run_test(ServerOpts::default(), |device| {
+        let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                     Duration::from_secs(1), Duration::from_secs(1));
+        let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+
+        // Switch to raw mode.
+        device.client.set_read_timeout(Some(Duration::from_millis(10)));
+        assert!(matches!(device.client.recv_frame(),
+                         Err(Error::Io(_, e)) if e.kind() == io::ErrorKind::TimedOut));
+
+        // High-level requests don't get their reply anymore.
+        assert!(device.write(0x4020, 0, &[1, 2, 3, 4]).is_err());
+
+        // Instead, the notification and the reply arrive as raw frames.
+        device.client.set_read_timeout(Some(Duration::from_secs(1)));
+        let frame = device.client.recv_frame().unwrap();
+        assert_eq!(frame.header.source, device.addr());
+        assert_eq!(frame.header.target, device.client.source());
+        assert_eq!(frame.header.command, crate::client::Command::Notification as u16);
+        assert_eq!(frame.header.state_flags, 4);
+        assert_eq!(frame.header.data_length as usize, frame.payload.len());
+        let notif = Notification::new(
+            [&[0; crate::client::AMS_HEADER_SIZE][..], &frame.payload].concat()).unwrap();
+        let sample = notif.samples().next().unwrap();
+        assert_eq!((sample.handle, sample.data), (handle, &[1, 2, 3, 4][..]));
+
+        let frame = device.client.recv_frame().unwrap();
+        assert_eq!(frame.header.command, crate::client::Command::Write as u16);
+        assert_eq!(frame.header.state_flags, 5);
+        assert_eq!(frame.payload, [0, 0, 0, 0]);
+
+        // Deleting the notification on drop won't get a reply either.
+        device.client.set_read_timeout(Some(Duration::from_millis(10)));
+    })
+}
+
+#[test]
+fn test_notification_receiver_closed() {
+    let port = config_test_server(ServerOpts::default());
+    let client = Client::new(("127.0.0.1", port), Timeouts::none(), Source::Auto).unwrap();
+    let recv = client.notification_receiver();
+    drop(client);
+    // Closing the client is not an error.
+    assert!(matches!(recv.recv(), Ok(None)));
+}
+
+/// One direction of an in-memory pipe: the buffered data, and if it's closed.
+#[derive(Default)]
+struct Pipe {
+    data: std::sync::Mutex<(std::collections::VecDeque<u8>, bool)>,
+    cond: std::sync::Condvar,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.data.lock().unwrap().1 = true;
+        self.cond.notify_all();
+    }
+}
+
+/// One end of an in-memory duplex pipe.
+struct PipeEnd {
+    rx: std::sync::Arc<Pipe>,
+    tx: std::sync::Arc<Pipe>,
+}
+
+fn duplex_pipe() -> (PipeEnd, PipeEnd) {
+    let (a, b) = (std::sync::Arc::new(Pipe::default()), std::sync::Arc::new(Pipe::default()));
+    (PipeEnd { rx: a.clone(), tx: b.clone() }, PipeEnd { rx: b, tx: a })
+}
+
+impl Read for PipeEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut data = self.rx.data.lock().unwrap();
+        while data.0.is_empty() && !data.1 {
+            data = self.rx.cond.wait(data).unwrap();
+        }
+        let n = buf.len().min(data.0.len());
+        for (slot, byte) in buf.iter_mut().zip(data.0.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for PipeEnd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.tx.data.lock().unwrap();
+        if data.1 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        data.0.extend(buf);
+        self.tx.cond.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl crate::transport::Transport for PipeEnd {
+    fn try_clone(&self) -> io::Result<Box<dyn crate::transport::Transport>> {
+        Ok(Box::new(PipeEnd { rx: self.rx.clone(), tx: self.tx.clone() }))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.rx.close();
+        self.tx.close();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_with_transport() {
+    use crate::transport::Transport;
+    // Connect the test server to one end of the pipe.
+    let port = config_test_server(ServerOpts::default());
+    let (client_end, mut server_end) = duplex_pipe();
+    let mut socket = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut server_end_2 = server_end.try_clone().unwrap();
+    let mut socket_2 = socket.try_clone().unwrap();
+    std::thread::spawn(move || {
+        let _ = io::copy(&mut server_end, &mut socket);
+        let _ = socket.shutdown(std::net::Shutdown::Both);
+    });
+    std::thread::spawn(move || {
+        let _ = io::copy(&mut socket_2, &mut server_end_2);
+    });
+
+    let remote = "127.0.0.1:48898".parse().unwrap();
+    let client = Client::with_transport(client_end, remote, Timeouts::new(Duration::from_secs(1)),
+                                        Source::Auto).unwrap();
+    assert_eq!(client.connection_info().peer_addr, remote);
+    assert_eq!(client.source().netid(), AmsNetId::new(127, 0, 0, 1, 1, 1));
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+    device.write(0x4020, 7, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(device.read_value::<u32>(0x4020, 7).unwrap(), 0x04030201);
+}
+
+/// A pipe end whose writes block until the pipe is closed.
+struct StallingEnd(PipeEnd);
+
+impl Read for StallingEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for StallingEnd {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        let mut data = self.0.tx.data.lock().unwrap();
+        while !data.1 {
+            data = self.0.tx.cond.wait(data).unwrap();
+        }
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl crate::transport::Transport for StallingEnd {
+    fn try_clone(&self) -> io::Result<Box<dyn crate::transport::Transport>> {
+        Ok(Box::new(StallingEnd(PipeEnd { rx: self.0.rx.clone(), tx: self.0.tx.clone() })))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        crate::transport::Transport::shutdown(&self.0)
+    }
+}
+
+#[test]
+fn test_keepalive_stuck_write() {
+    let (client_end, _server_end) = duplex_pipe();
+    let remote = "127.0.0.1:48898".parse().unwrap();
+    let client = Client::with_transport(StallingEnd(client_end), remote, Timeouts::none(),
+                                        Source::Auto).unwrap();
+    client.enable_keepalive(Duration::from_millis(20));
+    // The keepalive thread closes the connection, which ends the write.
+    let device = client.device(AmsAddr::new(AmsNetId::new(1, 2, 3, 4, 5, 6), 851));
+    assert!(device.get_state().is_err());
+}
+
+#[test]
+fn test_recv_frame_inconsistent() {
+    let (client_end, mut server_end) = duplex_pipe();
+    let remote = "127.0.0.1:48898".parse().unwrap();
+    let client = Client::with_transport(client_end, remote, Timeouts::none(),
+                                        Source::Auto).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(10)));
+    assert!(client.recv_frame().is_err());
+
+    // An AMS header that declares 5 bytes of data without any following is
+    // dropped, and the next frame is received.
+    let mut frame = vec![0, 0, 32, 0, 0, 0];
+    frame.extend_from_slice(&[0; 16]);
+    frame.extend_from_slice(&[1, 0, 5, 0, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    server_end.write_all(&frame).unwrap();
+    frame[24] = 0;
+    frame[26] = 0;
+    server_end.write_all(&frame).unwrap();
+
+    client.set_read_timeout(Some(Duration::from_secs(1)));
+    let frame = client.recv_frame().unwrap();
+    assert_eq!((frame.header.command, frame.header.state_flags), (1, 0));
+}
+
+#[test]
+fn test_read_by_name_partial() {
+    run_test(ServerOpts::default(), |device| {
+        // The symbol is located at offset 1020, with 4 bytes.
+        device.write(0x4020, 1020, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(device.read_by_name_partial("SYMBOL", 0, 4).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(device.read_by_name_partial("SYMBOL", 2, 2).unwrap(), [3, 4]);
+        assert_eq!(device.read_by_name_partial("SYMBOL", 4, 0).unwrap(), []);
+
+        for (offset, len) in [(3, 2), (5, 0), (usize::MAX, 2)] {
+            assert!(matches!(device.read_by_name_partial("SYMBOL", offset, len),
+                             Err(Error::Io(_, e)) if e.kind() == io::ErrorKind::InvalidInput));
+        }
+    })
+}
+
 #[test]
 fn test_bad_notification() {
     use crate::notif::*;
@@ -240,6 +1446,7 @@ fn test_bad_notification() {
 
         // No notification should have come through.
         assert!(chan.try_recv().is_err());
+        assert_eq!(device.client.lost_notifications(), 2);
 
         // Notification is automatically deleted at end of scope.
     })
@@ -255,6 +1462,7 @@ fn test_string_type() {
         assert!(bstr == &b"abc"[..]);
         assert!(bstr == "abc");
         assert!(bstr.len() == 3);
+        assert!(!bstr.is_empty() && String5::new().is_empty());
         assert!(bstr.backing_array().len() == 5);
 
         assert!(String5::try_from("abcdef").is_err());
@@ -276,6 +1484,8 @@ fn test_string_type() {
 
 #[test]
 fn test_wstring_type() {
+    use byteorder::{ByteOrder, LE};
+    use zerocopy::{AsBytes, FromBytes};
     type WString5 = crate::strings::WString<5>;
 
     run_test(ServerOpts::default(), |device| {
@@ -284,6 +1494,7 @@ fn test_wstring_type() {
         assert!(wstr == &[b'a' as u16, b'b' as u16, b'c' as u16][..]);
         assert!(wstr == "abc");
         assert!(wstr.len() == 3);
+        assert!(!wstr.is_empty() && WString5::new().is_empty());
         assert!(wstr.backing_array().len() == 5);
 
         assert!(WString5::try_from(&[1, 2, 3, 4, 5, 6][..]).is_err());
@@ -291,6 +1502,15 @@ fn test_wstring_type() {
 
         assert!(format!("{:?}", wstr) == "\"abc\"");
 
+        // The memory layout is little-endian, independent of the host.
+        let mut bytes = [0; 12];
+        LE::write_u16(&mut bytes[0..], 0x263A);
+        LE::write_u16(&mut bytes[2..], b'x' as u16);
+        let smiley = WString5::read_from(&bytes[..]).unwrap();
+        assert!(smiley == "\u{263A}x");
+        assert!(smiley.as_bytes() == bytes);
+        assert!(WString5::try_from("\u{263A}x").unwrap().as_bytes() == bytes);
+
         let wstr2 = WString5::try_from(&[b'a' as u16, b'b' as u16, b'c' as u16][..]).unwrap();
         assert!(<[u16; 5]>::from(wstr2) == [b'a' as u16, b'b' as u16, b'c' as u16, 0, 0]);
         assert!(wstr == wstr2);
@@ -303,3 +1523,30 @@ fn test_wstring_type() {
         assert!(<Vec<u16>>::try_from(ret).unwrap() == [b'a' as u16, b'b' as u16, b'c' as u16]);
     })
 }
+
+#[test]
+fn test_string_access() {
+    run_test(ServerOpts::default(), |device| {
+        device.write_string(0x4020, 0, 5, "abc").unwrap();
+        let mut buf = [0xff; 6];
+        device.read_exact(0x4020, 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"abc\0\0\0");
+        assert_eq!(device.read_string(0x4020, 0, 5).unwrap(), "abc");
+        assert_eq!(device.read_string(0x4020, 0, 2).unwrap(), "ab");
+
+        device.write_string(0x4020, 0, 5, "abcde").unwrap();
+        assert_eq!(device.read_string(0x4020, 0, 5).unwrap(), "abcde");
+        assert!(matches!(device.write_string(0x4020, 0, 5, "abcdef"),
+                         Err(Error::Io(_, e)) if e.kind() == io::ErrorKind::InvalidInput));
+        // nothing was written
+        assert_eq!(device.read_string(0x4020, 0, 5).unwrap(), "abcde");
+
+        device.write_wstring(0x4020, 0, 4, "äb€").unwrap();
+        let mut buf = [0xff; 10];
+        device.read_exact(0x4020, 0, &mut buf).unwrap();
+        assert_eq!(buf, [0xe4, 0, b'b', 0, 0xac, 0x20, 0, 0, 0, 0]);
+        assert_eq!(device.read_wstring(0x4020, 0, 4).unwrap(), "äb€");
+        assert_eq!(device.read_wstring(0x4020, 0, 1).unwrap(), "ä");
+        assert!(device.write_wstring(0x4020, 0, 2, "äb€").is_err());
+    })
+}
@@ -0,0 +1,339 @@
+use std::time::Duration;
+
+use crate::mock::{MockServer, UNEXPECTED_REQUEST};
+use crate::notif::{Attributes, TransmissionMode};
+use crate::{AdsState, AmsAddr, Client, Error, RetryPolicy, Source, Timeouts};
+
+fn connect(server: &MockServer) -> Client {
+    Client::new(server.addr(), Timeouts::new(Duration::from_secs(1)), Source::Auto).unwrap()
+}
+
+#[test]
+fn test_mock_expectations() {
+    let server = MockServer::new().unwrap();
+    server.expect_read(0x4020, 0).returns(&[1, 2, 3, 4, 5]);
+    server.expect_write(0x4020, 4).with_data(&[5, 6]).succeeds();
+    server.expect_write_read(0xF003, 0).with_data(b"X").returns(&[9, 0, 0, 0]);
+    server.expect_read(0x4020, 8).fails(0x710);
+
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    assert_eq!(device.read_value::<u32>(0x4020, 0).unwrap(), 0x04030201);
+    device.write(0x4020, 4, &[5, 6]).unwrap();
+    assert_eq!(device.write_read_vec(0xF003, 0, b"X", 4).unwrap(), [9, 0, 0, 0]);
+    assert_eq!(device.read_value::<u32>(0x4020, 8).unwrap_err().ads_code(), Some(0x710));
+    server.verify();
+}
+
+#[test]
+#[should_panic(expected = "unexpected requests")]
+fn test_mock_unexpected() {
+    let server = MockServer::new().unwrap();
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    assert!(matches!(device.read_value::<u32>(0x4020, 0),
+                     Err(Error::Request { code: UNEXPECTED_REQUEST, .. })));
+    server.verify();
+}
+
+#[test]
+#[should_panic(expected = "did not receive expected requests")]
+fn test_mock_unmet() {
+    let server = MockServer::new().unwrap();
+    server.expect_write(0x4020, 0).succeeds();
+    server.verify();
+}
+
+#[test]
+fn test_mock_builtin() {
+    let server = MockServer::new().unwrap();
+    server.set_device_info("PLC", 3, 1, 4024);
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+
+    let info = device.get_info().unwrap();
+    assert_eq!((info.name.as_str(), info.major, info.minor, info.version),
+               ("PLC", 3, 1, 4024));
+    assert_eq!(device.get_state().unwrap(), (AdsState::Run, 0));
+    device.write_control(AdsState::Stop, 2).unwrap();
+    assert_eq!(server.device_state(), (AdsState::Stop, 2));
+
+    let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                 Duration::from_secs(1), Duration::from_secs(1));
+    let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+    assert_eq!(server.notifications(), [(handle, 0x4020, 0)]);
+
+    server.send_notification(handle, &[1, 2, 3, 4]).unwrap();
+    let notif = client.get_notification_channel()
+                      .recv_timeout(Duration::from_secs(1)).unwrap();
+    let sample = notif.samples().next().unwrap();
+    assert_eq!((sample.handle, sample.data), (handle, &[1, 2, 3, 4][..]));
+
+    device.delete_notification(handle).unwrap();
+    assert!(server.notifications().is_empty());
+    server.verify();
+}
+
+#[test]
+fn test_mock_shutdown() {
+    let server = MockServer::new().unwrap();
+    let client = connect(&server);
+    let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                 Duration::from_secs(1), Duration::from_secs(1));
+    for port in [851, 852] {
+        let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), port));
+        for offset in 0..3 {
+            device.add_notification(0x4020, offset, &attrib).unwrap();
+        }
+    }
+    assert_eq!(server.notifications().len(), 6);
+    client.shutdown().unwrap();
+    assert!(server.notifications().is_empty());
+    server.verify();
+}
+
+#[test]
+fn test_mock_watch() {
+    use zerocopy::byteorder::{LE, U32};
+
+    let server = MockServer::new().unwrap();
+    // symbol entry, prefixed by its length
+    let mut entry = 44u32.to_le_bytes().to_vec();
+    for value in [0x4020, 0, 4, 19, 8] {
+        entry.extend_from_slice(&u32::to_le_bytes(value));
+    }
+    for len in [6, 5, 0] {
+        entry.extend_from_slice(&u16::to_le_bytes(len));
+    }
+    entry.extend_from_slice(b"MAIN.x\0UDINT\0\0");
+    server.expect_write_read(0xF009, 0).with_data(b"MAIN.x").returns(&entry);
+    server.expect_write_read(0xF003, 0).with_data(b"MAIN.x").returns(&[9, 0, 0, 0]);
+    server.expect_write(0xF006, 0).with_data(&[9, 0, 0, 0]).succeeds();
+
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    let (guard, values) = device.watch::<U32<LE>>("MAIN.x", TransmissionMode::ServerOnChange)
+                                .unwrap();
+    assert_eq!(server.notifications(), [(guard.handle(), 0xF005, 9)]);
+
+    server.send_notification(guard.handle(), &[1, 0, 0, 0]).unwrap();
+    server.send_notification(guard.handle(), &[2, 0, 0, 0]).unwrap();
+    assert_eq!(values.recv_timeout(Duration::from_secs(1)).unwrap().get(), 1);
+    assert_eq!(values.recv_timeout(Duration::from_secs(1)).unwrap().get(), 2);
+
+    drop(guard);
+    assert!(values.recv().is_err());
+    assert!(server.notifications().is_empty());
+    server.verify();
+}
+
+#[test]
+fn test_mock_read_by_name() {
+    let server = MockServer::new().unwrap();
+    // symbol entry for a REAL, prefixed by its length
+    let mut entry = 43u32.to_le_bytes().to_vec();
+    for value in [0x4020, 4, 4, 4, 8] {
+        entry.extend_from_slice(&u32::to_le_bytes(value));
+    }
+    for len in [6, 4, 0] {
+        entry.extend_from_slice(&u16::to_le_bytes(len));
+    }
+    entry.extend_from_slice(b"MAIN.r\0REAL\0\0");
+    server.expect_write_read(0xF009, 0).with_data(b"MAIN.r").returns(&entry);
+    server.expect_write_read(0xF003, 0).with_data(b"MAIN.r").returns(&[7, 0, 0, 0]);
+    server.expect_read(0xF005, 7).returns(&1.5f32.to_le_bytes());
+    server.expect_write(0xF005, 7).with_data(&2.5f32.to_le_bytes()).succeeds();
+    server.expect_read(0xF005, 7).returns(&2.5f32.to_le_bytes());
+
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    assert_eq!(device.read_by_name::<f32>("MAIN.r").unwrap(), 1.5);
+    // The handle is cached.
+    device.write_by_name("MAIN.r", &2.5f32).unwrap();
+    assert_eq!(device.read_by_name::<f32>("MAIN.r").unwrap(), 2.5);
+    // The size must match.
+    assert!(matches!(device.read_by_name::<f64>("MAIN.r"),
+                     Err(Error::Reply(_, "type size does not match symbol size", 4))));
+    server.verify();
+
+    // The handle is released when the client is dropped.
+    server.expect_write(0xF006, 0).with_data(&[7, 0, 0, 0]).succeeds();
+    drop(client);
+    server.verify();
+}
+
+#[test]
+fn test_mock_set_state() {
+    let server = MockServer::new().unwrap();
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+
+    device.write_control(AdsState::Run, 3).unwrap();
+    server.set_state_delay(1);
+    assert_eq!(device.set_state(AdsState::Stop).unwrap(), AdsState::Stop);
+    // the device state is kept
+    assert_eq!(server.device_state(), (AdsState::Stop, 3));
+
+    server.set_state_delay(1000);
+    assert!(matches!(device.set_state_timeout(AdsState::Run, Duration::from_millis(100)),
+                     Err(Error::Reply(_, "state did not change in time", 6))));
+    server.verify();
+}
+
+#[test]
+fn test_mock_app_name() {
+    let server = MockServer::new().unwrap();
+    let name = b"TwinCAT_SystemInfoVarList._AppInfo.AppName";
+    let mut reply = [0; 64];
+    reply[..8].copy_from_slice(b"Port_851");
+    server.expect_write_read(0xF004, 0).with_data(name).returns(&reply);
+    server.expect_write_read(0xF004, 0).with_data(name).fails(0x710);
+
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    assert_eq!(device.app_name().unwrap(), "Port_851");
+    assert!(matches!(device.app_name(), Err(Error::Ads("reading application name", _, 0x710))));
+    server.verify();
+}
+
+#[test]
+fn test_mock_disconnect() {
+    let server = MockServer::new().unwrap();
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    let mut recv = client.notification_receiver();
+    let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                 Duration::from_secs(1), Duration::from_secs(1));
+    let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+    server.send_notification(handle, &[1, 2, 3, 4]).unwrap();
+    assert!(client.last_error().is_none());
+    assert!(client.is_connected());
+
+    server.disconnect();
+    // Notifications received before are still delivered.
+    assert!(matches!(recv.next(), Some(Ok(_))));
+    assert!(matches!(recv.next(), Some(Err(Error::Io(_, e)))
+                     if e.kind() == std::io::ErrorKind::UnexpectedEof));
+    assert!(recv.next().is_none());
+    assert!(matches!(client.last_error(), Some(Error::Io("reading AMS packet header", _))));
+    assert!(!client.is_connected());
+}
+
+#[test]
+fn test_mock_notification_stats() {
+    use crate::client::NotificationStats;
+    use crate::OverflowPolicy;
+
+    let server = MockServer::new().unwrap();
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    let attrib = Attributes::new(4, TransmissionMode::ServerOnChange,
+                                 Duration::from_secs(1), Duration::from_secs(1));
+    let handle = device.add_notification(0x4020, 0, &attrib).unwrap();
+
+    client.notification_buffer(Some(1), OverflowPolicy::DropNewest);
+    server.send_notification(handle, &[1, 0, 0, 0]).unwrap();
+    server.send_notification(handle + 1, &[2, 0, 0, 0]).unwrap();
+    // A request makes sure that the notifications have been processed.
+    device.get_state().unwrap();
+    assert_eq!(client.stats(), NotificationStats { delivered: 1, unmatched: 1, overflow: 1 });
+
+    // Samples arriving after deleting the handle are unmatched.
+    let chan = client.get_notification_channel();
+    assert!(chan.try_recv().is_ok());
+    device.delete_notification(handle).unwrap();
+    server.send_notification(handle, &[3, 0, 0, 0]).unwrap();
+    assert!(chan.recv_timeout(Duration::from_secs(1)).is_ok());
+    device.get_state().unwrap();
+    assert_eq!(client.stats(), NotificationStats { delivered: 2, unmatched: 2, overflow: 1 });
+}
+
+#[test]
+fn test_mock_list_routes() {
+    use crate::client::RouteInfo;
+
+    // Route table entry as returned by the router.
+    fn entry(netid: [u8; 6], flags: u32, host: &[u8], name: &[u8]) -> Vec<u8> {
+        let mut entry = netid.to_vec();
+        entry.extend_from_slice(&[0; 2]);
+        for value in [flags, 5000, 0xFFFF, 0, 0, 0, host.len() as u32, name.len() as u32, 0] {
+            entry.extend_from_slice(&value.to_le_bytes());
+        }
+        entry.extend_from_slice(host);
+        entry.extend_from_slice(name);
+        entry
+    }
+
+    let server = MockServer::new().unwrap();
+    server.expect_read(803, 0).returns(&entry([10, 0, 0, 5, 1, 1], 0, b"10.0.0.5\0", b"PLC\0"));
+    server.expect_read(803, 1).returns(&entry([5, 1, 2, 3, 1, 1], 0x81, b"host\0", b"dev\0"));
+    server.expect_read(803, 2).fails(0x716);
+    server.expect_read(803, 0).returns(&entry([5, 1, 2, 3, 1, 1], 0, b"host\0", b"dev\0")[..50]);
+    server.expect_read(803, 0).fails(0x723);
+
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 10000));
+    assert_eq!(device.list_routes().unwrap(), [
+        RouteInfo { name: "PLC".into(), netid: [10, 0, 0, 5, 1, 1].into(),
+                    address: "10.0.0.5".into(), flags: 0 },
+        RouteInfo { name: "dev".into(), netid: [5, 1, 2, 3, 1, 1].into(),
+                    address: "host".into(), flags: 0x81 },
+    ]);
+    assert!(matches!(device.list_routes(), Err(Error::Reply(_, "invalid route entry", 50))));
+    assert!(matches!(device.list_routes(), Err(Error::Ads("listing routes", _, 0x723))));
+    server.verify();
+
+    let plc = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    assert!(matches!(plc.list_routes(), Err(Error::Io("listing routes", _))));
+}
+
+#[test]
+fn test_mock_retry() {
+    let server = MockServer::new().unwrap();
+    let client = connect(&server);
+    let policy = RetryPolicy { retry_on: &[0x70A, 0x1], max_attempts: 3,
+                               delay: Duration::from_millis(1) };
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851))
+                       .with_retry(policy);
+
+    // transient errors are retried
+    server.expect_read(0x4020, 0).fails(0x70A);
+    server.expect_read(0x4020, 0).fails(0x1);
+    server.expect_read(0x4020, 0).returns(&[1, 0]);
+    assert_eq!(device.read_value::<u16>(0x4020, 0).unwrap(), 1);
+    server.verify();
+
+    // up to the maximum number of attempts
+    for _ in 0..3 {
+        server.expect_read(0x4020, 0).fails(0x70A);
+    }
+    assert_eq!(device.read_value::<u16>(0x4020, 0).unwrap_err().ads_code(), Some(0x70A));
+    server.verify();
+
+    // other errors are not retried
+    server.expect_write(0x4020, 0).fails(0x710);
+    assert_eq!(device.write(0x4020, 0, &[1]).unwrap_err().ads_code(), Some(0x710));
+    server.verify();
+}
+
+#[test]
+fn test_mock_request_context() {
+    let server = MockServer::new().unwrap();
+    server.expect_write(0x4020, 12).with_data(&[1, 2, 3]).fails(0x704);
+    server.expect_read(0x4021, 4).fails(0x702);
+    server.expect_write_read(0xF003, 0).with_data(b"X").fails(0x710);
+
+    let client = connect(&server);
+    let device = client.device(AmsAddr::new([1, 2, 3, 4, 5, 6].into(), 851));
+    let err = device.write(0x4020, 12, &[1, 2, 3]).unwrap_err();
+    assert!(matches!(err, Error::Request { action: "write data", code: 0x704, index_group: 0x4020,
+                                           index_offset: 12, length: 3, .. }));
+    assert_eq!(err.to_string(), "write data: Reading/writing not permitted (0x704) at index \
+                                 group 0x4020, offset 0xc, 3 bytes");
+    assert!(matches!(device.read_value::<u16>(0x4021, 4),
+                     Err(Error::Request { code: 0x702, index_group: 0x4021, index_offset: 4,
+                                          length: 2, .. })));
+    assert!(matches!(device.write_read_vec(0xF003, 0, b"X", 4),
+                     Err(Error::Request { code: 0x710, index_group: 0xF003, length: 1, .. })));
+    server.verify();
+}
@@ -12,6 +12,7 @@ fn test_netid() {
     assert_eq!(netid.to_string(), "5.123.8.9.1.1");
 
     assert_eq!(netid, AmsNetId::from_ip(Ipv4Addr::new(5, 123, 8, 9), 1, 1));
+    assert_eq!(netid, AmsNetId::from(Ipv4Addr::new(5, 123, 8, 9)));
 
     assert_eq!(netid, AmsNetId::from_slice(&[5, 123, 8, 9, 1, 1]).unwrap());
     assert_eq!(netid, AmsNetId::from([5, 123, 8, 9, 1, 1]));
@@ -19,7 +20,12 @@ fn test_netid() {
 
     assert_eq!(netid, AmsNetId::from_str("5.123.8.9.1.1").unwrap());
     assert_eq!(netid, AmsNetId::from_str("5.123.8.9").unwrap());
-    assert!(AmsNetId::from_str("256.123.8.9.1.1").is_err());
+    assert_eq!(AmsNetId::from_str("5.123.8.9.2").unwrap(), AmsNetId::new(5, 123, 8, 9, 2, 1));
+    assert_eq!(AmsNetId::from_str(&netid.to_string()).unwrap(), netid);
+    assert_eq!(AmsNetId::from_str("256.123.8.9.1.1"), Err("invalid byte in NetID string"));
+    assert_eq!(AmsNetId::from_str("1.2.3"), Err("too few bytes in NetID string"));
+    assert_eq!(AmsNetId::from_str("1.2.3.4.5.6.7"), Err("too many bytes in NetID string"));
+    assert!(AmsNetId::from_str("1.2..3.4").is_err());
     assert!(AmsNetId::from_str("blah").is_err());
 }
 
@@ -30,6 +36,7 @@ fn test_addr() {
     assert_eq!(addr.netid(), netid);
     assert_eq!(addr.port(), 851);
     assert_eq!(addr.to_string(), "5.123.8.9.1.1:851");
+    assert_eq!(format!("{:?}", addr), "AmsAddr(5.123.8.9.1.1:851)");
 
     let mut buf = vec![];
     addr.write_to(&mut buf).unwrap();
@@ -44,4 +51,35 @@ fn test_addr() {
     assert!(AmsAddr::from_str(":88851").is_err());
     assert!(AmsAddr::from_str("blah").is_err());
     assert!(AmsAddr::from_str("").is_err());
+    assert_eq!(AmsAddr::from_str("5.123.8.9.1.1:"), Err("invalid port number"));
+    assert_eq!(AmsAddr::from_str("5.123.8.9.1.1:-1"), Err("invalid port number"));
+    assert!(AmsAddr::from_str("5.123.8.9.1.1:851:1").is_err());
+
+    for s in ["5.23.91.12.1.1:851", "0.0.0.0.0.0:0", "255.255.255.255.255.255:65535"] {
+        assert_eq!(AmsAddr::from_str(s).unwrap().to_string(), s);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let addr = AmsAddr::new(AmsNetId::new(192, 168, 0, 1, 1, 1), 851);
+    assert_eq!(serde_json::to_string(&addr.netid()).unwrap(), "\"192.168.0.1.1.1\"");
+    assert_eq!(serde_json::to_string(&addr).unwrap(), "\"192.168.0.1.1.1:851\"");
+    assert_eq!(serde_json::from_str::<AmsNetId>("\"192.168.0.1.1.1\"").unwrap(), addr.netid());
+    assert_eq!(serde_json::from_str::<AmsAddr>("\"192.168.0.1.1.1:851\"").unwrap(), addr);
+    assert!(serde_json::from_str::<AmsNetId>("\"192.168.0.256\"").is_err());
+    assert!(serde_json::from_str::<AmsNetId>("[192, 168, 0, 1, 1, 1]").is_err());
+}
+
+#[test]
+fn test_addr_from_port() {
+    use crate::ports::{self, Port};
+
+    let netid = AmsNetId::new(5, 123, 8, 9, 1, 1);
+    assert_eq!(AmsAddr::from((netid, 851)), AmsAddr::new(netid, 851));
+    assert_eq!(AmsAddr::from((netid, Port::Tc3Plc1)), AmsAddr::new(netid, 851));
+    assert_eq!(u16::from(Port::Tc2Plc1), 801);
+    assert_eq!(u16::from(Port::Tc3Plc4), ports::TC3_PLC_SYSTEM4);
+    assert_eq!(u16::from(Port::SystemService), 10000);
 }
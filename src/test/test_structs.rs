@@ -0,0 +1,94 @@
+// Tests for the field-by-field structure traits and their derive macro.
+
+use std::convert::TryInto;
+
+use ads_derive::AdsStruct;
+
+use crate::structs::{AdsSize, FromAds, ToAds};
+use crate::test::test_client::run_test;
+use crate::test::ServerOpts;
+use crate::Error;
+
+#[derive(AdsStruct, Debug, PartialEq)]
+#[ads(pad = 2)]
+struct Inner {
+    flag: bool,
+    #[ads(pad = 1)]
+    count: u16,
+    values: [i16; 2],
+}
+
+#[derive(AdsStruct, Debug, PartialEq)]
+pub struct Outer {
+    inner: Inner,
+    #[ads(string = 7)]
+    pub name: String,
+    #[ads(pad = 3)]
+    pub(crate) value: f32,
+    fixed: crate::strings::String<3>,
+}
+
+fn outer() -> Outer {
+    Outer {
+        inner: Inner { flag: true, count: 0x1234, values: [-1, 300] },
+        name: "motor".into(),
+        value: 2.5,
+        fixed: "ab".try_into().unwrap(),
+    }
+}
+
+fn outer_bytes() -> Vec<u8> {
+    let mut data = vec![1, 0, 0x34, 0x12, 0xff, 0xff, 0x2c, 0x01, 0, 0];
+    data.extend(b"motor\0\0\0");
+    data.extend([0; 3]);
+    data.extend(2.5_f32.to_le_bytes());
+    data.extend(b"ab\0\0");
+    data
+}
+
+#[test]
+fn test_derive_layout() {
+    assert_eq!(Inner::ADS_SIZE, 10);
+    assert_eq!(Outer::ADS_SIZE, 10 + 8 + 3 + 4 + 4);
+
+    let data = outer_bytes();
+    assert_eq!(Outer::from_ads(&data).unwrap(), outer());
+
+    let mut buf = vec![0xaa; Outer::ADS_SIZE];
+    outer().to_ads(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn test_derive_errors() {
+    let data = outer_bytes();
+    // the total length is validated
+    assert!(matches!(Outer::from_ads(&data[1..]), Err(Error::Reply(_, _, 28))));
+    assert!(Outer::from_ads(&[data.clone(), vec![0]].concat()).is_err());
+    assert!(outer().to_ads(&mut [0; 10]).is_err());
+
+    // strings must be UTF-8 and fit into the field
+    let mut bad = data;
+    bad[12] = 0xff;
+    assert!(matches!(Outer::from_ads(&bad), Err(Error::Reply("decoding string", _, 2))));
+    let long = Outer { name: "too long".into(), .. outer() };
+    assert!(long.to_ads(&mut [0; 29]).is_err());
+}
+
+#[test]
+fn test_struct_access() {
+    run_test(ServerOpts::default(), |device| {
+        device.write(0x4020, 0, &outer_bytes()).unwrap();
+        assert_eq!(device.read_struct::<Outer>(0x4020, 0).unwrap(), outer());
+
+        let value = Outer { name: "pump".into(), .. outer() };
+        device.write_struct(0x4020, 0, &value).unwrap();
+        let mut buf = [0; 29];
+        device.read_exact(0x4020, 0, &mut buf).unwrap();
+        assert_eq!(&buf[10..18], b"pump\0\0\0\0");
+        assert_eq!(device.read_struct::<Outer>(0x4020, 0).unwrap(), value);
+
+        // reading beyond the end of the device memory
+        assert!(device.read_struct::<Outer>(0x4020, 1020).is_err());
+    })
+}
@@ -0,0 +1,200 @@
+// Tests for the symbol and type info decoding.
+
+use byteorder::{WriteBytesExt, LE};
+
+use crate::symbol::*;
+use crate::Error;
+
+// Create a symbol entry from (group, offset, size, base type), flags and
+// (name, type, comment).
+fn symbol_entry(fields: [u32; 4], flags: u16, strings: [&str; 3], extra: &[u8]) -> Vec<u8> {
+    let mut entry = vec![];
+    for &field in &fields {
+        entry.write_u32::<LE>(field).unwrap();
+    }
+    entry.write_u16::<LE>(flags).unwrap();
+    entry.write_u16::<LE>(0).unwrap();
+    for string in &strings {
+        entry.write_u16::<LE>(string.len() as u16).unwrap();
+    }
+    for string in &strings {
+        entry.extend(string.as_bytes());
+        entry.push(0);
+    }
+    entry.extend(extra);
+    let mut data = vec![];
+    data.write_u32::<LE>(entry.len() as u32 + 4).unwrap();
+    data.extend(entry);
+    data
+}
+
+// Create a type entry from (size, offset, base type, flags), (name, type,
+// comment), array dimensions and sub-item entries.
+fn type_entry(fields: [u32; 4], strings: [&str; 3], array: &[(i32, i32)],
+              sub_items: &[Vec<u8>]) -> Vec<u8> {
+    let mut entry = vec![];
+    entry.write_u32::<LE>(1).unwrap();
+    entry.write_u32::<LE>(0).unwrap();
+    entry.write_u32::<LE>(0).unwrap();
+    for &field in &fields {
+        entry.write_u32::<LE>(field).unwrap();
+    }
+    for string in &strings {
+        entry.write_u16::<LE>(string.len() as u16).unwrap();
+    }
+    entry.write_u16::<LE>(array.len() as u16).unwrap();
+    entry.write_u16::<LE>(sub_items.len() as u16).unwrap();
+    for string in &strings {
+        entry.extend(string.as_bytes());
+        entry.push(0);
+    }
+    for &(lower, total) in array {
+        entry.write_i32::<LE>(lower).unwrap();
+        entry.write_i32::<LE>(total).unwrap();
+    }
+    for sub_item in sub_items {
+        entry.extend(sub_item);
+    }
+    // copy mask, which is skipped
+    entry.resize(entry.len() + fields[0] as usize, 0xff);
+    let mut data = vec![];
+    data.write_u32::<LE>(entry.len() as u32 + 4).unwrap();
+    data.extend(entry);
+    data
+}
+
+#[test]
+fn test_decode_types() {
+    let mut data = type_entry([3, 0, 17, 1], ["ARRAY [1..3] OF BYTE", "BYTE", ""],
+                              &[(1, 3)], &[]);
+    data.extend(type_entry([6, 0, 65, 1], ["ST_Inner", "", "inner struct"], &[], &[
+        type_entry([2, 0, 2, 2], ["nA", "INT", ""], &[], &[]),
+        type_entry([3, 2, 17, 2], ["aB", "ARRAY [1..3] OF BYTE", "array"], &[(1, 3)], &[]),
+    ]));
+    data.extend(type_entry([12, 0, 65, 1], ["ST_Outer", "", ""], &[], &[
+        type_entry([6, 0, 65, 2], ["stInner", "ST_Inner", ""], &[], &[]),
+        type_entry([4, 8, 3, 2], ["nX", "DINT", ""], &[], &[]),
+        type_entry([2, 0xffff_ffff, 2, 2], ["nY", "INT", ""], &[], &[]),
+    ]));
+
+    let (_, types) = decode_symbol_info(vec![], data.clone()).unwrap();
+    assert_eq!(types.len(), 3);
+
+    let array = &types["ARRAY [1..3] OF BYTE"];
+    assert_eq!(array.typ, "BYTE");
+    assert_eq!(array.array, [(1, 3)]);
+    assert_eq!(array.size, 3);
+    assert!(array.fields.is_empty());
+
+    let inner = &types["ST_Inner"];
+    assert_eq!(inner.comment, "inner struct");
+    assert_eq!(inner.base_type, 65);
+    assert_eq!(inner.fields.len(), 2);
+    assert_eq!(inner.fields[1].name, "aB");
+    assert_eq!(inner.fields[1].typ, "ARRAY [1..3] OF BYTE");
+    assert_eq!(inner.fields[1].comment, "array");
+    assert_eq!(inner.fields[1].offset, Some(2));
+    assert_eq!(inner.fields[1].array, [(1, 3)]);
+
+    let outer = &types["ST_Outer"];
+    assert_eq!(outer.size, 12);
+    assert_eq!(outer.fields.iter().map(|f| &*f.name).collect::<Vec<_>>(),
+               ["stInner", "nX", "nY"]);
+    assert_eq!(outer.fields[0].typ, "ST_Inner");
+    assert_eq!(outer.fields[1].offset, Some(8));
+    assert_eq!(outer.fields[2].offset, None);
+
+    // truncated data must not panic
+    for len in 1..data.len() {
+        let _ = decode_symbol_info(vec![], data[..len].to_vec());
+    }
+
+    // wrong version
+    let mut bad = data;
+    bad[4] = 2;
+    assert!(matches!(decode_symbol_info(vec![], bad),
+                     Err(Error::Reply(_, "unknown type info version", 2))));
+}
+
+#[test]
+fn test_array_dims() {
+    let data = type_entry([60, 0, 2, 1], ["ARRAY [1..3,0..9] OF INT", "INT", ""],
+                          &[(1, 3), (0, 10)], &[]);
+    let (_, types) = decode_symbol_info(vec![], data).unwrap();
+    let array = &types["ARRAY [1..3,0..9] OF INT"];
+    assert_eq!(array.array, [(1, 3), (0, 9)]);
+    assert_eq!(array.array_dims(), [ArrayDim { lower_bound: 1, elements: 3 },
+                                    ArrayDim { lower_bound: 0, elements: 10 }]);
+
+    assert_eq!(array.element_offset(&[1, 0]), Some(0));
+    assert_eq!(array.element_offset(&[1, 1]), Some(2));
+    assert_eq!(array.element_offset(&[2, 5]), Some(30));
+    assert_eq!(array.element_offset(&[3, 9]), Some(58));
+    // out of bounds, or wrong number of indices
+    assert_eq!(array.element_offset(&[0, 0]), None);
+    assert_eq!(array.element_offset(&[1, 10]), None);
+    assert_eq!(array.element_offset(&[1]), None);
+    assert_eq!(array.element_offset(&[i32::MIN, 0]), None);
+}
+
+#[test]
+fn test_decode_symbols() {
+    let mut data = symbol_entry([0x4040, 0x10, 4, 3], 0x1008,
+                                ["MAIN.nCounter", "DINT", "a counter"], &[]);
+    let first_len = data.len();
+    // unknown additional fields must be skipped
+    data.extend(symbol_entry([0x4020, 0, 81, 30], 0, ["GVL.sName", "STRING(80)", ""],
+                             &[0xff; 23]));
+
+    let (symbols, types) = decode_symbol_info(data.clone(), vec![]).unwrap();
+    assert!(types.is_empty());
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols[0].name, "MAIN.nCounter");
+    assert_eq!(symbols[0].typ, "DINT");
+    assert_eq!(symbols[0].comment, "a counter");
+    assert_eq!((symbols[0].ix_group, symbols[0].ix_offset), (0x4040, 0x10));
+    assert_eq!(symbols[0].size, 4);
+    assert_eq!(symbols[0].base_type, 3);
+    assert_eq!(symbols[0].flags, 0x1008);
+    assert_eq!(symbols[0].flags(), SymbolFlags::TYPE_GUID | SymbolFlags::ATTRIBUTES);
+    assert_eq!(symbols[1].name, "GVL.sName");
+    assert_eq!(symbols[1].typ, "STRING(80)");
+    assert_eq!(symbols[1].comment, "");
+    assert_eq!(symbols[1].size, 81);
+
+    // truncated data must not panic
+    for len in (1..data.len()).filter(|&len| len != first_len) {
+        assert!(decode_symbol_info(data[..len].to_vec(), vec![]).is_err());
+    }
+
+    // entry length too small
+    let mut bad = data.clone();
+    bad[0] = 2;
+    assert!(matches!(decode_symbol_info(bad, vec![]),
+                     Err(Error::Reply(_, "invalid entry length", 2))));
+
+    // string length larger than entry
+    let mut bad = data;
+    bad[24] = 200;
+    assert!(matches!(decode_symbol_info(bad, vec![]),
+                     Err(Error::Reply(_, "string length exceeds entry", 200))));
+}
+
+#[test]
+fn test_symbol_flags() {
+    let flags = SymbolFlags::from_bits(0x1008);
+    assert!(flags.contains(SymbolFlags::TYPE_GUID | SymbolFlags::ATTRIBUTES));
+    assert!(!flags.contains(SymbolFlags::PERSISTENT));
+    assert_eq!(flags.context(), 0);
+    assert_eq!(format!("{:?}", flags), "{TYPE_GUID, ATTRIBUTES}");
+
+    let flags = SymbolFlags::from_bits(0x0_0321);
+    assert!(flags.contains(SymbolFlags::PERSISTENT | SymbolFlags::READ_ONLY));
+    assert_eq!(flags.context(), 3);
+    assert_eq!(format!("{:?}", flags), "{PERSISTENT, READ_ONLY, CONTEXT(3)}");
+
+    // unknown bits are kept
+    let flags = SymbolFlags::from_bits(0x1_0002);
+    assert_eq!(flags.bits(), 0x1_0002);
+    assert_eq!(format!("{:?}", flags), "{BIT_VALUE, 0x10000}");
+}
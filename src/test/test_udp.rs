@@ -1,8 +1,8 @@
 //! Test for the UDP client.
 
 // use std::io::{self, Read, Write};
-// use std::time::Duration;
 use std::net::UdpSocket;
+use std::time::Duration;
 
 use crate::udp;
 
@@ -21,8 +21,12 @@ fn udp_replier(sock: UdpSocket) {
             reply.add_bytes(udp::Tag::TCVersion, b"\x04\x01\x07\x00");
         } else if let Ok(msg) = udp::Message::parse(&buf[..n], udp::ServiceId::AddRoute, false) {
             reply.set_service(udp::ServiceId::AddRoute, true);
-            let status = msg.get_str(udp::Tag::RouteName) != Some("route");
-            reply.add_u32(udp::Tag::Status, status as u32);
+            let status = if msg.get_str(udp::Tag::Password) != Some("1") {
+                udp::ROUTE_AUTH_REJECTED
+            } else {
+                (msg.get_str(udp::Tag::RouteName) != Some("route")) as u32
+            };
+            reply.add_u32(udp::Tag::Status, status);
         } else {
             panic!("received invalid UDP packet");
         };
@@ -47,6 +51,15 @@ fn test_udp() {
     assert_eq!(info.twincat_version, (4, 1, 7));
     assert_eq!(info.os_version, ("Windows NT", 5, 8, 9, "Test".into()));
 
+    let systems = udp::discover(("127.0.0.1", port), Duration::from_millis(200)).unwrap();
+    assert_eq!(systems.len(), 1);
+    assert_eq!(systems[0].hostname, "box");
+    assert_eq!(systems[0].netid, tgt_netid);
+    assert_eq!(systems[0].twincat_version, (4, 1, 7));
+
     udp::add_route(("127.0.0.1", port), tgt_netid, "a", Some("route"), None, None, false).unwrap();
     assert!(udp::add_route(("127.0.0.1", port), tgt_netid, "a", None, None, None, false).is_err());
+    assert!(matches!(udp::add_route(("127.0.0.1", port), tgt_netid, "a", Some("route"),
+                                    None, Some("wrong"), false),
+                     Err(crate::Error::Ads(_, "authentication rejected", udp::ROUTE_AUTH_REJECTED))));
 }
@@ -0,0 +1,47 @@
+//! The byte stream that carries the AMS/TCP protocol.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream};
+
+/// A bidirectional byte stream to an ADS server, normally a TCP connection.
+///
+/// Implement this to run the protocol over other connections, such as
+/// in-memory pipes for testing, and pass it to
+/// [`Client::with_transport`](crate::Client::with_transport).
+///
+/// The client uses three handles to the stream, created with `try_clone`:
+/// one for sending requests, one for the thread that receives replies and
+/// notifications, and one to close the connection.  The handles must work
+/// independently of each other: a `read` on one handle must not block a
+/// `write` on another.  Calling `shutdown` on any handle must close the
+/// stream and make a blocked `read` or `write` on the others return.  This
+/// rules out streams that can't be split, such as most TLS sessions.
+pub trait Transport: Read + Write + Send + 'static {
+    /// Return another handle to the same stream.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+
+    /// Close the stream in both directions.
+    fn shutdown(&self) -> io::Result<()>;
+
+    /// Return the local address of the stream.
+    ///
+    /// This is used to construct the source NetID for `Source::Auto`.  The
+    /// default returns `127.0.0.1:0`, for streams that are not sockets.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+}
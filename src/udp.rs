@@ -1,8 +1,9 @@
 //! Implements the Beckhoff UDP message protocol for basic operations.
 
 use std::convert::TryInto;
-use std::io::Write;
+use std::io::{self, Write};
 use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
 use std::{char, iter, str};
 
 use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, LE};
@@ -187,6 +188,10 @@ impl Message {
     }
 }
 
+/// The status returned by the router when adding a route with wrong
+/// credentials.
+pub const ROUTE_AUTH_REJECTED: u32 = 0x704;
+
 /// Send a UDP message for setting a route.
 ///
 /// - `target`: (host, port) of the AMS router to add the route to
@@ -198,6 +203,9 @@ impl Message {
 /// - `username`: system username for the router, default is `Administrator`
 /// - `password`: system password for the given user, default is `1`
 /// - `temporary`: marks the route as "temporary"
+///
+/// If the router rejects the username or password, an `Error::Ads` with
+/// code `ROUTE_AUTH_REJECTED` is returned.
 pub fn add_route(target: (&str, u16), netid: AmsNetId, host: &str,
                  routename: Option<&str>, username: Option<&str>,
                  password: Option<&str>, temporary: bool) -> Result<()> {
@@ -216,6 +224,8 @@ pub fn add_route(target: (&str, u16), netid: AmsNetId, host: &str,
     match reply.get_u32(Tag::Status) {
         None => Err(Error::Reply("setting route", "no status in reply", 0)),
         Some(0) => Ok(()),
+        Some(ROUTE_AUTH_REJECTED) => Err(Error::Ads("setting route", "authentication rejected",
+                                                    ROUTE_AUTH_REJECTED)),
         Some(n) => crate::errors::ads_error("setting route", n),
     }
 }
@@ -245,54 +255,94 @@ pub struct SysInfo {
 pub fn get_info(target: (&str, u16)) -> Result<SysInfo> {
     let request = Message::new(ServiceId::Identify, AmsAddr::default());
     let reply = request.send_receive(target)?;
+    Ok(SysInfo::from_reply(&reply))
+}
+
+/// Discover systems running TwinCAT by sending a UDP query to a broadcast
+/// address, and collecting the replies that arrive within `timeout`.
+///
+/// - `target`: (broadcast address, port) to send the query to, e.g.
+///   `("255.255.255.255", ads::UDP_PORT)`
+///
+/// Replies that cannot be parsed are ignored.
+pub fn discover(target: (&str, u16), timeout: Duration) -> Result<Vec<SysInfo>> {
+    let request = Message::new(ServiceId::Identify, AmsAddr::default());
+    let sock = UdpSocket::bind("0.0.0.0:0").ctx("binding UDP socket")?;
+    sock.set_broadcast(true).ctx("enabling UDP broadcast")?;
+    sock.send_to(request.as_bytes(), target).ctx("sending UDP request")?;
+
+    let deadline = Instant::now() + timeout;
+    let mut systems = Vec::new();
+    let mut reply = [0; 576];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            break;
+        }
+        sock.set_read_timeout(Some(remaining)).ctx("setting UDP timeout")?;
+        let n = match sock.recv_from(&mut reply) {
+            Ok((n, _)) => n,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock |
+                                         io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(Error::Io("receiving UDP reply", e)),
+        };
+        if let Ok(msg) = Message::parse(&reply[..n], ServiceId::Identify, true) {
+            systems.push(SysInfo::from_reply(&msg));
+        }
+    }
+    Ok(systems)
+}
 
-    // Parse TwinCAT version.
-    let tcver = reply.get_bytes(Tag::TCVersion).unwrap_or(&[]);
-    let twincat_version = if tcver.len() >= 4 {
-        let tcbuild = u16::from_le_bytes(tcver[2..4].try_into().expect("size"));
-        (tcver[0], tcver[1], tcbuild)
-    } else {
-        (0, 0, 0)
-    };
-
-    // Parse OS version.  This is a Windows OSVERSIONINFO structure, which
-    // consists of major/minor/build versions, the platform, and a "service
-    // pack" string, coded as UTF-16.  It is not known how the data looks on
-    // non-Windows devices, but hopefully the format is kept the same.
-    let os_version = if let Some(mut bytes) = reply.get_bytes(Tag::OSVersion) {
-        if bytes.len() >= 22 {
-            // Size of the structure (redundant).
-            let _ = bytes.read_u32::<LE>().expect("size");
-            let major = bytes.read_u32::<LE>().expect("size");
-            let minor = bytes.read_u32::<LE>().expect("size");
-            let build = bytes.read_u32::<LE>().expect("size");
-            let platform = match bytes.read_u32::<LE>().expect("size") {
-                1 => "TC/RTOS",
-                2 => "Windows NT",
-                3 => "Windows CE",
-                _ => "Unknown platform",
-            };
-            let string = if platform == "TC/RTOS" {
-                bytes.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect()
+impl SysInfo {
+    fn from_reply(reply: &Message) -> Self {
+        // Parse TwinCAT version.
+        let tcver = reply.get_bytes(Tag::TCVersion).unwrap_or(&[]);
+        let twincat_version = if tcver.len() >= 4 {
+            let tcbuild = u16::from_le_bytes(tcver[2..4].try_into().expect("size"));
+            (tcver[0], tcver[1], tcbuild)
+        } else {
+            (0, 0, 0)
+        };
+
+        // Parse OS version.  This is a Windows OSVERSIONINFO structure, which
+        // consists of major/minor/build versions, the platform, and a "service
+        // pack" string, coded as UTF-16.  It is not known how the data looks on
+        // non-Windows devices, but hopefully the format is kept the same.
+        let os_version = if let Some(mut bytes) = reply.get_bytes(Tag::OSVersion) {
+            if bytes.len() >= 22 {
+                // Size of the structure (redundant).
+                let _ = bytes.read_u32::<LE>().expect("size");
+                let major = bytes.read_u32::<LE>().expect("size");
+                let minor = bytes.read_u32::<LE>().expect("size");
+                let build = bytes.read_u32::<LE>().expect("size");
+                let platform = match bytes.read_u32::<LE>().expect("size") {
+                    1 => "TC/RTOS",
+                    2 => "Windows NT",
+                    3 => "Windows CE",
+                    _ => "Unknown platform",
+                };
+                let string = if platform == "TC/RTOS" {
+                    bytes.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect()
+                } else {
+                    iter::from_fn(|| bytes.read_u16::<LE>().ok())
+                        .take_while(|&ch| ch != 0)
+                        .filter_map(|ch| char::from_u32(ch as u32)).collect()
+                };
+                (platform, major, minor, build, string)
             } else {
-                iter::from_fn(|| bytes.read_u16::<LE>().ok())
-                    .take_while(|&ch| ch != 0)
-                    .filter_map(|ch| char::from_u32(ch as u32)).collect()
-            };
-            (platform, major, minor, build, string)
+                ("Unknown OS info format", 0, 0, 0, "".into())
+            }
         } else {
-            ("Unknown OS info format", 0, 0, 0, "".into())
+            ("No OS info", 0, 0, 0, "".into())
+        };
+        SysInfo {
+            netid: reply.get_source().netid(),
+            hostname: reply.get_str(Tag::ComputerName).unwrap_or("unknown").into(),
+            twincat_version,
+            os_version,
+            fingerprint: reply.get_str(Tag::Fingerprint).unwrap_or("").into(),
         }
-    } else {
-        ("No OS info", 0, 0, 0, "".into())
-    };
-    Ok(SysInfo {
-        netid: reply.get_source().netid(),
-        hostname: reply.get_str(Tag::ComputerName).unwrap_or("unknown").into(),
-        twincat_version,
-        os_version,
-        fingerprint: reply.get_str(Tag::Fingerprint).unwrap_or("").into(),
-    })
+    }
 }
 
 #[derive(FromBytes, AsBytes, Default)]